@@ -0,0 +1,25 @@
+// Loads cheat files, which the core leaves to its ports. Cheats that can't
+// be read are skipped, so one bad code doesn't lose the rest of the file.
+use gbrs_core::cheats::Cheats;
+use gbrs_core::cpu::Cpu;
+
+pub fn load_cheats(gameboy: &mut Cpu, path: &str) {
+    match Cheats::from_file(path) {
+        Ok((cheats, skipped)) => {
+            for err in skipped {
+                println!("Skipping part of {}: {}", path, err);
+            }
+            println!("Loaded {} cheats from {}", cheats.iter().count(), path);
+            gameboy.mem.cheats = cheats;
+        },
+        Err(err) => println!("Failed to read cheats from {}: {}", path, err),
+    }
+}
+
+// From a cheat file with the same name as the ROM (eg. game.cht for
+// game.gb), if there is one
+pub fn load_cheats_next_to_rom(gameboy: &mut Cpu) {
+    if let Some(path) = Cheats::path_next_to(&gameboy.cart_info.rom_path) {
+        load_cheats(gameboy, &path);
+    }
+}
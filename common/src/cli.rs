@@ -0,0 +1,330 @@
+// The command line, which is the same for both GUIs. Anything given here
+// takes priority over the settings file.
+use crate::cheats::{load_cheats, load_cheats_next_to_rom};
+use crate::link::{connect_link, LinkCommand};
+use crate::movie::{finish_movie, start_movie, MovieCommand};
+use crate::rom_file::load_rom;
+use crate::settings::Settings;
+
+use gbrs_core::colour::colour::Colour;
+use gbrs_core::colour::dmg_palettes::{dmg_palette_by_name, DMG_PALETTES};
+use gbrs_core::config::Config;
+use gbrs_core::cpu::{Cpu, EmulationTarget};
+use gbrs_core::host::{shared, FileHost};
+use gbrs_core::memory::boot_rom::BootRom;
+use gbrs_core::memory::patch::Patch;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use clap::{Parser, ValueEnum};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Model {
+    Dmg,
+    Cgb,
+    Gba,
+    Sgb,
+}
+
+impl Model {
+    pub fn target(&self) -> EmulationTarget {
+        match self {
+            Model::Dmg => EmulationTarget::Dmg,
+            Model::Cgb => EmulationTarget::CgbCgbMode,
+            Model::Gba => EmulationTarget::GbaCgbMode,
+            Model::Sgb => EmulationTarget::Sgb,
+        }
+    }
+}
+
+fn parse_palette(name: &str) -> Result<String, String> {
+    match dmg_palette_by_name(name) {
+        Some(palette) => Ok(palette.name.to_string()),
+        None => Err(format!(
+            "expected one of: {}",
+            DMG_PALETTES
+                .iter()
+                .map(|palette| format!("\"{}\"", palette.name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+#[derive(Parser)]
+#[command(name = "gbrs", version, about = "A Gameboy emulator")]
+pub struct Args {
+    #[arg(help = "The game to run (.gb, .gbc or .sgb, or a .zip or .gz)")]
+    pub rom: String,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Which ROM to run out of a zip with more than one"
+    )]
+    pub rom_entry: Option<String>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Apply an IPS, UPS or BPS patch (by default, one with the same \
+                name as the ROM is used if there is one)"
+    )]
+    pub patch: Option<String>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Load cheats from a .cht file (by default, one with the same \
+                name as the ROM is used if there is one)"
+    )]
+    pub cheats: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Run as this model rather than the best one the game supports"
+    )]
+    pub model: Option<Model>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Run a dumped DMG, SGB or CGB boot ROM before the game"
+    )]
+    pub boot_rom: Option<String>,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Keep battery saves here rather than next to the ROM"
+    )]
+    pub save_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Start the window this many times the size of the screen"
+    )]
+    pub scale: Option<u32>,
+    #[arg(
+        long,
+        value_parser = parse_palette,
+        help = "Colours for original Gameboy games, eg. Green or Pocket"
+    )]
+    pub palette: Option<String>,
+    #[arg(long, help = "Start in fullscreen")]
+    pub fullscreen: bool,
+    #[arg(long, help = "Start paused, for frame advancing from the start")]
+    pub start_paused: bool,
+    #[arg(long, help = "Start with the sound muted")]
+    pub mute: bool,
+
+    #[arg(long, value_name = "FILE", help = "Load a save state at startup")]
+    pub load_state: Option<String>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "play_movie",
+        help = "Record the joypad into a movie, written out on exit"
+    )]
+    pub record_movie: Option<String>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "load_state",
+        help = "Play a movie recorded with --record-movie"
+    )]
+    pub play_movie: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        conflicts_with_all = ["link_connect", "printer"],
+        help = "Wait for another gbrs to link up, eg. on 127.0.0.1:5738"
+    )]
+    pub link_listen: Option<String>,
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        conflicts_with = "printer",
+        help = "Link up with a gbrs started with --link-listen"
+    )]
+    pub link_connect: Option<String>,
+    #[arg(long, help = "Plug a Gameboy Printer into the link port")]
+    pub printer: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Replace the game's save with a .sav or .srm before starting"
+    )]
+    pub import_save: Option<String>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write the game's save out as a .sav or .srm on exit"
+    )]
+    pub export_save: Option<String>,
+
+    #[arg(
+        long,
+        requires = "frames",
+        help = "Run without a window or sound, for scripts and tests"
+    )]
+    pub headless: bool,
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "headless",
+        help = "With --headless, how many frames to run before exiting"
+    )]
+    pub frames: Option<usize>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "headless",
+        help = "With --headless, save the last frame as a PNG"
+    )]
+    pub screenshot: Option<String>,
+}
+
+// For mistakes on the command line, which don't need a panic's backtrace
+fn exit_with_error(message: &str) -> ! {
+    println!("{}", message);
+    process::exit(1);
+}
+
+impl Args {
+    // Exits with a usage message if the command line doesn't make sense
+    pub fn from_env() -> Args {
+        Args::parse()
+    }
+
+    pub fn movie(&self) -> Option<MovieCommand> {
+        match (&self.record_movie, &self.play_movie) {
+            (Some(path), _) => Some(MovieCommand::Record(path.clone())),
+            (_, Some(path)) => Some(MovieCommand::Play(path.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn link(&self) -> Option<LinkCommand> {
+        match (&self.link_listen, &self.link_connect) {
+            (Some(address), _) => Some(LinkCommand::Listen(address.clone())),
+            (_, Some(address)) => Some(LinkCommand::Connect(address.clone())),
+            _ if self.printer => Some(LinkCommand::Printer),
+            _ => None,
+        }
+    }
+
+    pub fn dmg_palette(&self) -> Option<[Colour; 4]> {
+        let name = self.palette.as_ref()?;
+        dmg_palette_by_name(name).map(|palette| palette.colours)
+    }
+
+    pub fn apply_to_settings(&self, settings: &mut Settings) {
+        if let Some(scale) = self.scale {
+            settings.scale = scale;
+        }
+        settings.fullscreen |= self.fullscreen;
+        settings.start_paused |= self.start_paused;
+        settings.mute |= self.mute;
+    }
+
+    pub fn config(
+        &self,
+        sound_buffer_size: usize,
+        sound_sample_rate: usize,
+    ) -> Config {
+        let rom = load_rom(&self.rom, self.rom_entry.as_deref())
+            .unwrap_or_else(|err| {
+                exit_with_error(&format!(
+                    "Failed to load {}: {}",
+                    self.rom, err
+                ))
+            });
+        Config {
+            sound_buffer_size,
+            sound_sample_rate,
+            rom,
+            host: shared(FileHost::new(self.save_dir.clone())),
+            model: self.model.map(|model| model.target()),
+            boot_rom: self.boot_rom.as_deref().map(|path| {
+                BootRom::try_from_file(path).unwrap_or_else(|err| {
+                    exit_with_error(&format!(
+                        "Failed to load boot ROM {}: {}",
+                        path, err
+                    ))
+                })
+            }),
+            patch: self.patch.as_deref().map(|path| {
+                Patch::from_file(path).unwrap_or_else(|err| {
+                    exit_with_error(&format!(
+                        "Failed to read patch {}: {}",
+                        path, err
+                    ))
+                })
+            }),
+        }
+    }
+
+    // Exits if the game can't be started, eg. because its patch is for
+    // another version of it
+    pub fn gameboy(&self, config: &Config) -> Cpu {
+        Cpu::try_from_config(config.clone()).unwrap_or_else(|err| {
+            exit_with_error(&format!("Failed to start {}: {}", self.rom, err))
+        })
+    }
+
+    // Everything that has to happen before the first frame
+    pub fn start(&self, gameboy: &mut Cpu) {
+        gameboy.dmg_palette = self.dmg_palette();
+        match &self.cheats {
+            Some(path) => load_cheats(gameboy, path),
+            None => load_cheats_next_to_rom(gameboy),
+        }
+        if let Some(path) = &self.import_save {
+            if let Err(err) = gameboy.import_save(path) {
+                exit_with_error(&format!(
+                    "Failed to import save file {}: {}",
+                    path, err
+                ));
+            }
+        }
+        if let Some(path) = &self.load_state {
+            let loaded = fs::read(path)
+                .map_err(|err| err.to_string())
+                .and_then(|bytes| {
+                    gameboy.load_state(&bytes).map_err(|err| err.to_string())
+                });
+            if let Err(err) = loaded {
+                exit_with_error(&format!(
+                    "Failed to load save state {}: {}",
+                    path, err
+                ));
+            }
+            println!("Loaded save state {}", path);
+        }
+        if let Some(command) = &self.link() {
+            connect_link(gameboy, command);
+        }
+        if let Some(command) = &self.movie() {
+            start_movie(gameboy, command, self.load_state.is_some());
+        }
+    }
+
+    // And everything after the last
+    pub fn finish(&self, gameboy: &mut Cpu) {
+        if let Some(command) = &self.movie() {
+            finish_movie(gameboy, command);
+        }
+        // Don't lose the last second of progress to the save debounce
+        gameboy.flush_saves();
+        if let Some(path) = &self.export_save {
+            if let Err(err) = gameboy.export_save(path) {
+                exit_with_error(&format!(
+                    "Failed to export save file {}: {}",
+                    path, err
+                ));
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+// Runs the game with no window or sound, as fast as it'll go, for scripts
+// and tests, eg.
+//   gbrs game.gb --headless --frames 600 --screenshot game.png
+use crate::cli::Args;
+use crate::screenshot::save_screenshot_to;
+
+use gbrs_core::cpu::Cpu;
+
+// Returns the CPU once it's run the frames the command line asked for
+pub fn run_headless(mut gameboy: Cpu, args: &Args) -> Cpu {
+    let frames = args.frames.unwrap_or(0);
+    for _ in 0..frames {
+        gameboy.step_one_frame();
+        // Nobody's listening
+        gameboy.mem.apu.discard_samples();
+    }
+    println!("Ran {} frames", frames);
+
+    if let Some(path) = &args.screenshot {
+        save_screenshot_to(&gameboy, path).expect("Failed to save screenshot");
+        println!("Saved screenshot to {}", path);
+    }
+    gameboy
+}
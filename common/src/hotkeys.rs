@@ -0,0 +1,238 @@
+// Emulator controls that aren't Gameboy buttons. The frontends pass key
+// presses in by name, and Hotkeys keeps track of pausing, fast-forward and
+// the volume. Anything it can't do by itself (like taking a screenshot) is
+// returned for the frontend to handle.
+use crate::keys::normalise_key_name;
+use crate::settings::Settings;
+
+use gbrs_core::cheats::Cheats;
+use gbrs_core::config::{Config, ConfigError};
+use gbrs_core::cpu::Cpu;
+
+use std::mem;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Hotkey {
+    Pause,
+    // Held down to fast-forward
+    Turbo,
+    // Pauses if need be, then runs one frame
+    FrameAdvance,
+    Reset,
+    Screenshot,
+    VolumeUp,
+    VolumeDown,
+    Mute,
+    // Starts or stops a video recording
+    Record,
+    // Switches all of the cheats off, or back on
+    Cheats,
+    // Asks for another ROM to swap to
+    LoadRom,
+}
+
+impl Hotkey {
+    pub const ALL: [Hotkey; 11] = [
+        Hotkey::Pause,
+        Hotkey::Turbo,
+        Hotkey::FrameAdvance,
+        Hotkey::Reset,
+        Hotkey::Screenshot,
+        Hotkey::VolumeUp,
+        Hotkey::VolumeDown,
+        Hotkey::Mute,
+        Hotkey::Record,
+        Hotkey::Cheats,
+        Hotkey::LoadRom,
+    ];
+
+    // As written in the settings file
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hotkey::Pause => "pause",
+            Hotkey::Turbo => "turbo",
+            Hotkey::FrameAdvance => "frame_advance",
+            Hotkey::Reset => "reset",
+            Hotkey::Screenshot => "screenshot",
+            Hotkey::VolumeUp => "volume_up",
+            Hotkey::VolumeDown => "volume_down",
+            Hotkey::Mute => "mute",
+            Hotkey::Record => "record",
+            Hotkey::Cheats => "cheats",
+            Hotkey::LoadRom => "load_rom",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Hotkey> {
+        Hotkey::ALL.into_iter().find(|hotkey| hotkey.name() == name)
+    }
+
+    pub fn default_key(&self) -> &'static str {
+        match self {
+            Hotkey::Pause => "P",
+            Hotkey::Turbo => "Tab",
+            Hotkey::FrameAdvance => "N",
+            Hotkey::Reset => "R",
+            Hotkey::Screenshot => "F12",
+            Hotkey::VolumeUp => "Equals",
+            Hotkey::VolumeDown => "Minus",
+            Hotkey::Mute => "M",
+            Hotkey::Record => "F9",
+            Hotkey::Cheats => "F7",
+            Hotkey::LoadRom => "O",
+        }
+    }
+}
+
+// Percent
+const VOLUME_STEP: u8 = 10;
+const MAX_VOLUME: u8 = 100;
+
+pub struct Hotkeys {
+    // Normalised key names
+    bindings: Vec<(String, Hotkey)>,
+    pub turbo_multiplier: f64,
+
+    pub paused: bool,
+    pub turbo: bool,
+    frames_to_advance: usize,
+    // Percent
+    pub volume: u8,
+    pub muted: bool,
+}
+
+impl Hotkeys {
+    pub fn hotkey_for_key(&self, key_name: &str) -> Option<Hotkey> {
+        let key_name = normalise_key_name(key_name);
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == key_name)
+            .map(|(_, hotkey)| *hotkey)
+    }
+
+    // Call when a key is first pressed (not when it repeats). Returns the
+    // hotkey the key is bound to, which the frontend should act on if it's
+    // Reset, Screenshot, Record, Cheats or LoadRom.
+    pub fn key_down(&mut self, key_name: &str) -> Option<Hotkey> {
+        let hotkey = self.hotkey_for_key(key_name)?;
+        match hotkey {
+            Hotkey::Pause => {
+                self.paused = !self.paused;
+                self.frames_to_advance = 0;
+            },
+            Hotkey::Turbo => self.turbo = true,
+            Hotkey::FrameAdvance => {
+                self.paused = true;
+                self.frames_to_advance += 1;
+            },
+            Hotkey::VolumeUp => {
+                self.volume = (self.volume + VOLUME_STEP).min(MAX_VOLUME);
+                self.muted = false;
+            },
+            Hotkey::VolumeDown => {
+                self.volume = self.volume.saturating_sub(VOLUME_STEP);
+            },
+            Hotkey::Mute => self.muted = !self.muted,
+            Hotkey::Reset
+            | Hotkey::Screenshot
+            | Hotkey::Record
+            | Hotkey::Cheats
+            | Hotkey::LoadRom => {},
+        }
+        Some(hotkey)
+    }
+
+    pub fn key_up(&mut self, key_name: &str) {
+        if self.hotkey_for_key(key_name) == Some(Hotkey::Turbo) {
+            self.turbo = false;
+        }
+    }
+
+    // Whether the frontend should run the emulator right now. While paused,
+    // this is true once for every frame advance press, and the frontend
+    // should run a single frame with Cpu::step_one_frame.
+    pub fn should_run(&mut self) -> bool {
+        if !self.paused {
+            return true;
+        }
+        if self.frames_to_advance > 0 {
+            self.frames_to_advance -= 1;
+            return true;
+        }
+        false
+    }
+
+    // To set as Cpu::speed_multiplier
+    pub fn speed_multiplier(&self) -> f64 {
+        match self.turbo {
+            true => self.turbo_multiplier,
+            false => 1.,
+        }
+    }
+
+    // From 0 to 1
+    pub fn volume(&self) -> f32 {
+        match self.muted {
+            true => 0.,
+            false => self.volume as f32 / MAX_VOLUME as f32,
+        }
+    }
+
+    // For frontends whose audio output doesn't have a volume control
+    pub fn apply_volume(&self, samples: &mut [i16]) {
+        let volume = self.volume();
+        if volume == 1. {
+            return;
+        }
+        for sample in samples.iter_mut() {
+            *sample = (*sample as f32 * volume) as i16;
+        }
+    }
+
+    pub fn new(settings: &Settings) -> Hotkeys {
+        Hotkeys {
+            bindings: settings
+                .hotkeys
+                .iter()
+                .map(|(key, hotkey)| (normalise_key_name(key), *hotkey))
+                .collect(),
+            turbo_multiplier: settings.turbo_multiplier,
+            paused: settings.start_paused,
+            turbo: false,
+            frames_to_advance: 0,
+            volume: MAX_VOLUME,
+            muted: settings.mute,
+        }
+    }
+}
+
+// Turns the Gameboy off and on again, as if it had just been started with
+// `config`. Battery saves and cheats are kept, but link cables and movies
+// are not.
+pub fn soft_reset(gameboy: &mut Cpu, config: &Config) {
+    let cheats = mem::replace(&mut gameboy.mem.cheats, Cheats::new());
+    if let Err(err) = restart(gameboy, config) {
+        println!("Failed to reset: {}", err);
+    }
+    gameboy.mem.cheats = cheats;
+}
+
+// Like soft_reset, but for a different game, so the cheats are dropped. If
+// it can't be started, the old Cpu is left running.
+pub fn restart(gameboy: &mut Cpu, config: &Config) -> Result<(), ConfigError> {
+    // The new Cpu loads the save, so the old one has to write it first
+    gameboy.flush_saves();
+    let dmg_palette = gameboy.dmg_palette;
+    *gameboy = Cpu::try_from_config(config.clone())?;
+    gameboy.dmg_palette = dmg_palette;
+    Ok(())
+}
+
+pub fn toggle_cheats(gameboy: &mut Cpu) {
+    let cheats = &mut gameboy.mem.cheats;
+    cheats.set_active(!cheats.is_active());
+    match cheats.is_active() {
+        true => println!("Cheats on"),
+        false => println!("Cheats off"),
+    }
+}
@@ -0,0 +1,299 @@
+// Maps keys and controllers to the Gameboy's buttons. The frontends pass in
+// what's been pressed by name, and Input works out the joypad from that
+// using the mapping in the settings file.
+use crate::keys::normalise_key_name;
+use crate::settings::Settings;
+
+use gbrs_core::cpu::Cpu;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Button {
+    A,
+    B,
+    Start,
+    Select,
+    Up,
+    Down,
+    Left,
+    Right,
+    // Held down to press A or B over and over (autofire)
+    TurboA,
+    TurboB,
+}
+
+impl Button {
+    pub const ALL: [Button; 10] = [
+        Button::A,
+        Button::B,
+        Button::Start,
+        Button::Select,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+        Button::TurboA,
+        Button::TurboB,
+    ];
+
+    // As written in the settings file
+    pub fn name(&self) -> &'static str {
+        match self {
+            Button::A => "a",
+            Button::B => "b",
+            Button::Start => "start",
+            Button::Select => "select",
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::Left => "left",
+            Button::Right => "right",
+            Button::TurboA => "turbo_a",
+            Button::TurboB => "turbo_b",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Button> {
+        Button::ALL.into_iter().find(|button| button.name() == name)
+    }
+}
+
+// Where an input came from. Each kind of device has its own mapping.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Device {
+    Keyboard,
+    // An SDL GameController, by joystick instance ID. Buttons and axes use
+    // SDL's names for them ("a", "dpup", "leftx", ...), which are the same
+    // for every controller in the controller DB.
+    Gamepad(u32),
+    // A raw SFML joystick, by index. Buttons are "button0", "button1", ...
+    // and axes "x", "y", "z", "r", "u", "v", "povx" and "povy", which differ
+    // between controllers and operating systems.
+    Joystick(u32),
+}
+
+// Each button can be on any number of inputs. Axes are mapped in one
+// direction with a + or - on the end ("leftx-" is the left stick pushed
+// left).
+#[derive(Clone)]
+pub struct InputMapping {
+    pub keyboard: Vec<(String, Button)>,
+    pub gamepad: Vec<(String, Button)>,
+    pub joystick: Vec<(String, Button)>,
+    // How far (from 0 to 1) an analog stick has to be pushed to count
+    pub deadzone: f32,
+    // Presses per second while a turbo button is held
+    pub autofire_rate: f64,
+    // SDL's community controller DB (gamecontrollerdb.txt), for controllers
+    // SDL doesn't know about itself
+    pub controller_db: Option<String>,
+}
+
+const DEFAULT_DEADZONE: f32 = 0.5;
+const DEFAULT_AUTOFIRE_RATE: f64 = 10.;
+
+const DEFAULT_KEYBOARD: [(&str, Button); 10] = [
+    ("X", Button::A),
+    ("Z", Button::B),
+    ("Enter", Button::Start),
+    ("Backspace", Button::Select),
+    ("Up", Button::Up),
+    ("Down", Button::Down),
+    ("Left", Button::Left),
+    ("Right", Button::Right),
+    ("S", Button::TurboA),
+    ("A", Button::TurboB),
+];
+
+// By position, so the bottom and right face buttons are A and the top and
+// left ones are B
+const DEFAULT_GAMEPAD: [(&str, Button); 16] = [
+    ("a", Button::A),
+    ("b", Button::A),
+    ("x", Button::B),
+    ("y", Button::B),
+    ("start", Button::Start),
+    ("back", Button::Select),
+    ("dpup", Button::Up),
+    ("lefty-", Button::Up),
+    ("dpdown", Button::Down),
+    ("lefty+", Button::Down),
+    ("dpleft", Button::Left),
+    ("leftx-", Button::Left),
+    ("dpright", Button::Right),
+    ("leftx+", Button::Right),
+    ("rightshoulder", Button::TurboA),
+    ("leftshoulder", Button::TurboB),
+];
+
+// A wired DualShock 4 on macOS, as measured by experimentation
+const DEFAULT_JOYSTICK: [(&str, Button); 16] = [
+    ("button1", Button::A),
+    ("button2", Button::A),
+    ("button0", Button::B),
+    ("button3", Button::B),
+    ("button9", Button::Start),
+    ("button8", Button::Select),
+    ("button13", Button::Select),
+    ("y-", Button::Up),
+    ("povy+", Button::Up),
+    ("y+", Button::Down),
+    ("povy-", Button::Down),
+    ("x-", Button::Left),
+    ("povx-", Button::Left),
+    ("x+", Button::Right),
+    ("povx+", Button::Right),
+    ("button5", Button::TurboA),
+];
+
+fn default_bindings(bindings: &[(&str, Button)]) -> Vec<(String, Button)> {
+    bindings
+        .iter()
+        .map(|(input, button)| (input.to_string(), *button))
+        .collect()
+}
+
+// Keys go through normalise_key_name. Controller inputs are only named one
+// way, but the settings file might not match their case.
+fn normalise_input_name(device: Device, name: &str) -> String {
+    match device {
+        Device::Keyboard => normalise_key_name(name),
+        _ => name.to_lowercase(),
+    }
+}
+
+impl InputMapping {
+    pub fn bindings(&self, device: Device) -> &Vec<(String, Button)> {
+        match device {
+            Device::Keyboard => &self.keyboard,
+            Device::Gamepad(_) => &self.gamepad,
+            Device::Joystick(_) => &self.joystick,
+        }
+    }
+
+    pub fn bindings_mut(
+        &mut self,
+        device: Device,
+    ) -> &mut Vec<(String, Button)> {
+        match device {
+            Device::Keyboard => &mut self.keyboard,
+            Device::Gamepad(_) => &mut self.gamepad,
+            Device::Joystick(_) => &mut self.joystick,
+        }
+    }
+
+    // Replaces whatever the button was on for that kind of device
+    pub fn set_button(
+        &mut self,
+        device: Device,
+        button: Button,
+        inputs: &[String],
+    ) {
+        let bindings = self.bindings_mut(device);
+        bindings.retain(|(_, bound)| *bound != button);
+        for input in inputs {
+            bindings.push((input.clone(), button));
+        }
+    }
+
+    pub fn new() -> InputMapping {
+        InputMapping {
+            keyboard: default_bindings(&DEFAULT_KEYBOARD),
+            gamepad: default_bindings(&DEFAULT_GAMEPAD),
+            joystick: default_bindings(&DEFAULT_JOYSTICK),
+            deadzone: DEFAULT_DEADZONE,
+            autofire_rate: DEFAULT_AUTOFIRE_RATE,
+            controller_db: None,
+        }
+    }
+}
+
+impl Default for InputMapping {
+    fn default() -> InputMapping {
+        InputMapping::new()
+    }
+}
+
+pub struct Input {
+    mapping: InputMapping,
+    // Every key, button and axis direction that's currently held, by its
+    // normalised name
+    held: Vec<(Device, String)>,
+}
+
+impl Input {
+    fn set_held(&mut self, device: Device, name: &str, held: bool) {
+        let name = normalise_input_name(device, name);
+        let index = self
+            .held
+            .iter()
+            .position(|(d, n)| *d == device && *n == name);
+        match (index, held) {
+            (None, true) => self.held.push((device, name)),
+            (Some(index), false) => {
+                self.held.swap_remove(index);
+            },
+            _ => {},
+        }
+    }
+
+    pub fn key_down(&mut self, key_name: &str) {
+        self.set_held(Device::Keyboard, key_name, true);
+    }
+
+    pub fn key_up(&mut self, key_name: &str) {
+        self.set_held(Device::Keyboard, key_name, false);
+    }
+
+    pub fn button(&mut self, device: Device, name: &str, pressed: bool) {
+        self.set_held(device, name, pressed);
+    }
+
+    // `value` goes from -1 to 1. Anything inside the deadzone is let go.
+    pub fn axis(&mut self, device: Device, name: &str, value: f32) {
+        let deadzone = self.mapping.deadzone;
+        self.set_held(device, &format!("{}+", name), value > deadzone);
+        self.set_held(device, &format!("{}-", name), value < -deadzone);
+    }
+
+    // Lets go of everything on a controller that's been unplugged
+    pub fn device_removed(&mut self, device: Device) {
+        self.held.retain(|(d, _)| *d != device);
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.held.iter().any(|(device, name)| {
+            self.mapping.bindings(*device).iter().any(|(bound, b)| {
+                *b == button && normalise_input_name(*device, bound) == *name
+            })
+        })
+    }
+
+    // Turbo buttons alternate between pressed and released in emulated
+    // time, so they keep the same rate when fast-forwarding
+    fn autofire_phase(&self, gameboy: &Cpu) -> bool {
+        let presses =
+            gameboy.ms_since_boot as f64 * self.mapping.autofire_rate / 1000.;
+        presses.fract() < 0.5
+    }
+
+    pub fn update_joypad(&self, gameboy: &mut Cpu) {
+        let autofire = self.autofire_phase(gameboy);
+        let joypad = &mut gameboy.mem.joypad;
+        joypad.a_pressed = self.is_pressed(Button::A)
+            || (autofire && self.is_pressed(Button::TurboA));
+        joypad.b_pressed = self.is_pressed(Button::B)
+            || (autofire && self.is_pressed(Button::TurboB));
+        joypad.start_pressed = self.is_pressed(Button::Start);
+        joypad.select_pressed = self.is_pressed(Button::Select);
+        joypad.up_pressed = self.is_pressed(Button::Up);
+        joypad.down_pressed = self.is_pressed(Button::Down);
+        joypad.left_pressed = self.is_pressed(Button::Left);
+        joypad.right_pressed = self.is_pressed(Button::Right);
+    }
+
+    pub fn new(settings: &Settings) -> Input {
+        Input {
+            mapping: settings.input.clone(),
+            held: vec![],
+        }
+    }
+}
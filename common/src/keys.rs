@@ -0,0 +1,35 @@
+// SDL and SFML name some keys differently ("Return" and "Enter", "=" and
+// "Equal"), so key names are compared in this form, which the settings file
+// can use either of
+pub fn normalise_key_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+
+    let name = match name.as_str() {
+        "return" => "enter",
+        "=" | "equal" => "equals",
+        "-" | "hyphen" => "minus",
+        "leftshift" => "lshift",
+        "rightshift" => "rshift",
+        "leftctrl" | "lcontrol" => "lctrl",
+        "rightctrl" | "rcontrol" => "rctrl",
+        "leftalt" => "lalt",
+        "rightalt" => "ralt",
+        _ => name.as_str(),
+    };
+
+    // SFML calls the number row Num0-Num9 and the keypad Numpad0-Numpad9,
+    // where SDL has 0-9 and Keypad 0-Keypad 9
+    if let Some(rest) = name.strip_prefix("numpad") {
+        return format!("keypad{}", rest);
+    }
+    if let Some(digit) = name.strip_prefix("num") {
+        if digit.len() == 1 && digit.chars().all(|c| c.is_ascii_digit()) {
+            return digit.to_string();
+        }
+    }
+    name.to_string()
+}
@@ -0,0 +1,15 @@
+// Code shared by the desktop GUIs (sdl-gui and sfml-gui) which doesn't
+// depend on how they draw, play sound or read the keyboard
+pub mod cheats;
+pub mod cli;
+pub mod headless;
+pub mod hotkeys;
+pub mod input;
+pub mod keys;
+pub mod link;
+pub mod movie;
+pub mod recording;
+pub mod rom_file;
+pub mod screenshot;
+pub mod settings;
+pub mod video;
@@ -0,0 +1,65 @@
+use gbrs_core::cpu::Cpu;
+use gbrs_core::link::printer::{GameboyPrinter, Printout};
+use gbrs_core::link::tcp::TcpLink;
+use gbrs_core::link::SerialLink;
+use gbrs_core::png;
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Two gbrs windows can be linked by starting one with --link-listen and the
+// other with --link-connect, eg. both with 127.0.0.1:5738
+pub enum LinkCommand {
+    Listen(String),
+    Connect(String),
+    Printer,
+}
+
+// Printouts are saved next to the ROM, and named after when they came out
+fn printout_path(rom_path: &str, number: usize) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    let mut path = PathBuf::from(rom_path);
+    path.set_extension("");
+    format!(
+        "{}-print-{}-{}.png",
+        path.to_string_lossy(),
+        timestamp,
+        number
+    )
+}
+
+fn printer(rom_path: String) -> GameboyPrinter {
+    let mut printouts = 0;
+    GameboyPrinter::new(Box::new(move |printout: Printout| {
+        printouts += 1;
+        let path = printout_path(&rom_path, printouts);
+        let bytes =
+            png::encode_rgba(printout.width, printout.height, &printout.rgba);
+        fs::write(&path, bytes).expect("Failed to save printout");
+        println!("Printed to {}", path);
+    }))
+}
+
+pub fn connect_link(gameboy: &mut Cpu, command: &LinkCommand) {
+    let link: Box<dyn SerialLink> = match command {
+        LinkCommand::Listen(address) => {
+            println!("Waiting for a link cable connection on {}", address);
+            let link =
+                TcpLink::listen(address).expect("Failed to connect link cable");
+            println!("Link cable connected");
+            Box::new(link)
+        },
+        LinkCommand::Connect(address) => Box::new(
+            TcpLink::connect(address).expect("Failed to connect link cable"),
+        ),
+        LinkCommand::Printer => {
+            Box::new(printer(gameboy.cart_info.rom_path.clone()))
+        },
+    };
+    gameboy.mem.serial_cable.connect(link);
+}
@@ -0,0 +1,53 @@
+use gbrs_core::cpu::Cpu;
+use gbrs_core::movie::{Movie, MovieMode};
+
+use std::fs;
+
+pub enum MovieCommand {
+    Record(String),
+    Play(String),
+}
+
+// Must be called before the CPU is stepped. Recordings start from power-on,
+// or from a save state if one was just loaded.
+pub fn start_movie(
+    gameboy: &mut Cpu,
+    command: &MovieCommand,
+    from_save_state: bool,
+) {
+    match command {
+        MovieCommand::Record(path) => {
+            gameboy
+                .start_movie_recording(from_save_state)
+                .expect("Failed to start recording movie");
+            println!("Recording movie to {}", path);
+        },
+        MovieCommand::Play(path) => {
+            let bytes = fs::read(path).expect("Failed to read movie");
+            let movie = Movie::from_bytes(&bytes).expect("Invalid movie");
+            println!("Playing movie {} ({} frames)", path, movie.inputs.len());
+            gameboy
+                .start_movie_playback(movie)
+                .expect("Failed to start playing movie");
+        },
+    }
+}
+
+// Writes out the movie if it was being recorded
+pub fn finish_movie(gameboy: &mut Cpu, command: &MovieCommand) {
+    if let Some(session) = &gameboy.movie {
+        if session.mode == MovieMode::Finished {
+            println!("Movie finished");
+        }
+        println!(
+            "Stopped movie at frame {} ({} lag frames)",
+            session.frame, session.lag_frames
+        );
+    }
+
+    let movie = gameboy.stop_movie();
+    if let (MovieCommand::Record(path), Some(movie)) = (command, movie) {
+        fs::write(path, movie.to_bytes()).expect("Failed to write movie");
+        println!("Saved movie to {}", path);
+    }
+}
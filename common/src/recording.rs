@@ -0,0 +1,54 @@
+use gbrs_core::cpu::Cpu;
+use gbrs_core::recorder::{Recorder, VideoFormat};
+
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Y4M is easy to convert with ffmpeg, eg.
+//   ffmpeg -i game.y4m -i game.wav -c:v libx264 -c:a aac out.mp4
+pub const RECORDING_VIDEO_FORMAT: VideoFormat = VideoFormat::Y4m;
+
+// Recordings are saved next to the ROM, and named after when they started
+fn recording_base_path(rom_path: &str) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    let mut path = PathBuf::from(rom_path);
+    path.set_extension("");
+    format!("{}-recording-{}", path.to_string_lossy(), timestamp)
+}
+
+pub fn toggle_recording(recorder: &mut Option<Recorder>, gameboy: &Cpu) {
+    match recorder.take() {
+        Some(finished) => {
+            let frames = finished.frames_written;
+            finished.finish().expect("Failed to finish recording");
+            println!("Stopped recording ({} frames)", frames);
+        },
+        None => {
+            let base_path = recording_base_path(&gameboy.cart_info.rom_path);
+            *recorder = Some(
+                Recorder::new(&base_path, RECORDING_VIDEO_FORMAT, gameboy)
+                    .expect("Failed to start recording"),
+            );
+            println!("Recording to {}", base_path);
+        },
+    }
+}
+
+// For sound the port isn't going to play, like while fast-forwarding or
+// advancing a frame at a time. A recording still gets it, so that its sound
+// keeps up with its video.
+pub fn skip_audio(recorder: &mut Option<Recorder>, gameboy: &mut Cpu) {
+    if let Some(recorder) = recorder.as_mut() {
+        let apu = &mut gameboy.mem.apu;
+        let mut samples = vec![0; apu.samples_available()];
+        let count = apu.read_samples(&mut samples);
+        recorder
+            .record_audio(&samples[..count])
+            .expect("Failed to record audio");
+    }
+    gameboy.mem.apu.discard_samples();
+}
@@ -0,0 +1,193 @@
+// Loads ROMs as they are, or out of a .zip or .gz, which is how most
+// collections are kept. Saves go next to the archive, named after it.
+use crate::cheats::load_cheats_next_to_rom;
+use crate::hotkeys::restart;
+
+use gbrs_core::config::Config;
+use gbrs_core::cpu::Cpu;
+use gbrs_core::memory::rom::Rom;
+
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+pub const ROM_EXTENSIONS: [&str; 3] = ["gb", "gbc", "sgb"];
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+
+pub fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .is_some_and(|extension| ROM_EXTENSIONS.contains(&extension.as_str()))
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+// With `entry`, the ROM with that name (or path inside the zip). Otherwise
+// the first one.
+fn read_zip(bytes: Vec<u8>, entry: Option<&str>) -> io::Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    let names: Vec<String> = archive
+        .file_names()
+        .filter(|name| is_rom_name(name))
+        .map(String::from)
+        .collect();
+
+    let name = match entry {
+        Some(entry) => names
+            .iter()
+            .find(|name| *name == entry || file_name(name) == entry),
+        None => names.first(),
+    };
+    let name = match name {
+        Some(name) => name.clone(),
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                match entry {
+                    Some(entry) => format!(
+                        "{} isn't in the zip, which has: {}",
+                        entry,
+                        names.join(", ")
+                    ),
+                    None => "There's no .gb, .gbc or .sgb in the zip".into(),
+                },
+            ));
+        },
+    };
+    if entry.is_none() && names.len() > 1 {
+        println!(
+            "The zip has {} ROMs, loading {} (pick another with --rom-entry): {}",
+            names.len(),
+            name,
+            names.join(", ")
+        );
+    }
+
+    let mut file = archive.by_name(&name)?;
+    let mut rom = vec![];
+    file.read_to_end(&mut rom)?;
+    Ok(rom)
+}
+
+fn read_gzip(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut rom = vec![];
+    GzDecoder::new(bytes).read_to_end(&mut rom)?;
+    Ok(rom)
+}
+
+// Archives are recognised by what's in them rather than their extension
+pub fn load_rom(path: &str, entry: Option<&str>) -> io::Result<Rom> {
+    let bytes = fs::read(path)?;
+    let bytes = if bytes.starts_with(ZIP_MAGIC) {
+        read_zip(bytes, entry)?
+    } else if bytes.starts_with(GZIP_MAGIC) {
+        read_gzip(&bytes)?
+    } else {
+        bytes
+    };
+
+    Ok(Rom {
+        bytes,
+        path: path.to_string(),
+    })
+}
+
+// Hot-swaps in the game at `path` (eg. one dropped onto the window), as if
+// gbrs had been started with it. `config` is updated so resets keep the new
+// game. If it can't be loaded, the old game keeps running.
+pub fn swap_rom(gameboy: &mut Cpu, config: &mut Config, path: &str) {
+    let rom = match load_rom(path, None) {
+        Ok(rom) => rom,
+        Err(err) => {
+            println!("Failed to load {}: {}", path, err);
+            return;
+        },
+    };
+    let mut new_config = config.clone();
+    new_config.rom = rom;
+    // A patch from the command line was for the old game, as were the
+    // cheats. Ones next to the new game are still found.
+    new_config.patch = None;
+    match restart(gameboy, &new_config) {
+        Ok(()) => {
+            *config = new_config;
+            println!("Loaded {}", path);
+            load_cheats_next_to_rom(gameboy);
+        },
+        Err(err) => println!("Failed to load {}: {}", path, err),
+    }
+}
+
+// Terminals quote or escape the paths of files dropped onto them
+fn clean_path(line: &str) -> String {
+    let line = line.trim();
+    for quote in ['\'', '"'] {
+        if let Some(path) = line
+            .strip_prefix(quote)
+            .and_then(|line| line.strip_suffix(quote))
+        {
+            return path.to_string();
+        }
+    }
+    line.replace("\\ ", " ")
+}
+
+// For ports that can't have files dropped onto their window: asks for the
+// path of a ROM in the terminal. It's read on another thread, so the game
+// keeps running while it's typed.
+pub struct RomPrompt {
+    receiver: Option<Receiver<String>>,
+}
+
+impl RomPrompt {
+    // Does nothing if it's still waiting on the last one
+    pub fn ask(&mut self) {
+        if self.receiver.is_some() {
+            return;
+        }
+        println!("Type or drop the path of a ROM to load, then press Enter:");
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line).is_ok() {
+                let _ = sender.send(line);
+            }
+        });
+        self.receiver = Some(receiver);
+    }
+
+    // The path, once one has been entered. An empty line cancels.
+    pub fn take_path(&mut self) -> Option<String> {
+        let line = match self.receiver.as_ref()?.try_recv() {
+            Ok(line) => line,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => String::new(),
+        };
+        self.receiver = None;
+        let path = clean_path(&line);
+        match path.is_empty() {
+            true => None,
+            false => Some(path),
+        }
+    }
+
+    pub fn new() -> RomPrompt {
+        RomPrompt { receiver: None }
+    }
+}
+
+impl Default for RomPrompt {
+    fn default() -> RomPrompt {
+        RomPrompt::new()
+    }
+}
@@ -0,0 +1,51 @@
+use gbrs_core::cpu::Cpu;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Screenshots are saved next to the ROM, and named after when they were
+// taken
+fn screenshot_path(rom_path: &str) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0);
+
+    let mut path = PathBuf::from(rom_path);
+    path.set_extension("");
+    format!("{}-screenshot-{}.png", path.to_string_lossy(), timestamp)
+}
+
+// Writes the current frame, including any Super Gameboy border, as a PNG
+pub fn save_screenshot_to(gameboy: &Cpu, path: &str) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        gameboy.screen_width() as u32,
+        gameboy.screen_height() as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&gameboy.get_rgba_frame())?;
+    writer.finish()?;
+    Ok(())
+}
+
+// Returns the path the screenshot was saved to
+pub fn save_screenshot(gameboy: &Cpu) -> std::io::Result<String> {
+    let path = screenshot_path(&gameboy.cart_info.rom_path);
+    save_screenshot_to(gameboy, &path)?;
+    Ok(path)
+}
+
+// For a hotkey. Says where it went, as the frontends have nowhere else to.
+pub fn take_screenshot(gameboy: &Cpu) {
+    match save_screenshot(gameboy) {
+        Ok(path) => println!("Saved screenshot to {}", path),
+        Err(err) => println!("Failed to save screenshot: {}", err),
+    }
+}
@@ -0,0 +1,233 @@
+// The frontends' settings file, gbrs.toml in the platform's config
+// directory (eg. ~/.config/gbrs/gbrs.toml on Linux). Everything in it is
+// optional:
+//
+//   turbo_multiplier = 4.0
+//   deadzone = 0.3
+//   scale_mode = "fit"
+//
+//   [hotkeys]
+//   pause = "P"
+//   screenshot = "F12"
+//
+//   [keyboard]
+//   a = "K"
+//   b = ["J", "Space"]
+//
+//   [gamepad]
+//   turbo_a = "righttrigger+"
+use crate::hotkeys::Hotkey;
+use crate::input::{Button, Device, InputMapping};
+use crate::video::ScaleMode;
+
+use std::fs;
+use std::path::PathBuf;
+
+pub const SETTINGS_FILE_NAME: &str = "gbrs.toml";
+// Used if it's in the config directory and the settings don't say otherwise
+pub const CONTROLLER_DB_FILE_NAME: &str = "gamecontrollerdb.txt";
+const DEFAULT_TURBO_MULTIPLIER: f64 = 4.;
+const DEFAULT_SCALE: u32 = 4;
+
+pub struct Settings {
+    // Key names, as SDL or SFML call them
+    pub hotkeys: Vec<(String, Hotkey)>,
+    // How fast holding the turbo hotkey runs the game
+    pub turbo_multiplier: f64,
+    pub input: InputMapping,
+    // The window starts out this many times the size of the screen
+    pub scale: u32,
+    pub scale_mode: ScaleMode,
+    pub vsync: bool,
+    pub fullscreen: bool,
+    pub mute: bool,
+    // Only from the command line
+    pub start_paused: bool,
+}
+
+fn number(name: &str, value: toml::Value) -> Result<f64, String> {
+    match value {
+        toml::Value::Float(number) => Ok(number),
+        toml::Value::Integer(number) => Ok(number as f64),
+        _ => Err(format!("{} must be a number", name)),
+    }
+}
+
+// Either one name or a list of them
+fn names(name: &str, value: toml::Value) -> Result<Vec<String>, String> {
+    let error = || format!("{} must be a name or a list of names", name);
+    match value {
+        toml::Value::String(name) => Ok(vec![name]),
+        toml::Value::Array(values) => values
+            .into_iter()
+            .map(|value| value.as_str().map(String::from).ok_or_else(error))
+            .collect(),
+        _ => Err(error()),
+    }
+}
+
+impl Settings {
+    pub fn config_dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("gbrs"))
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        Settings::config_dir().map(|dir| dir.join(SETTINGS_FILE_NAME))
+    }
+
+    // The controller DB from the settings, or the one in the config
+    // directory if there is one
+    pub fn controller_db_path(&self) -> Option<PathBuf> {
+        if let Some(path) = &self.input.controller_db {
+            return Some(PathBuf::from(path));
+        }
+        Settings::config_dir()
+            .map(|dir| dir.join(CONTROLLER_DB_FILE_NAME))
+            .filter(|path| path.exists())
+    }
+
+    // Falls back to the defaults if there isn't a settings file, or it
+    // can't be read
+    pub fn load() -> Settings {
+        let path = match Settings::default_path() {
+            Some(path) if path.exists() => path.to_string_lossy().into_owned(),
+            _ => return Settings::new(),
+        };
+        Settings::from_file(&path).unwrap_or_else(|err| {
+            // TOML's errors run over several lines
+            println!("{}", err);
+            println!("Using the default settings");
+            Settings::new()
+        })
+    }
+
+    pub fn from_file(path: &str) -> Result<Settings, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {}", path, err))?;
+        Settings::parse(&text)
+            .map_err(|err| format!("Invalid settings in {}: {}", path, err))
+    }
+
+    // Anything that isn't set is left as the default
+    pub fn parse(text: &str) -> Result<Settings, String> {
+        let table: toml::Table =
+            text.parse().map_err(|err| format!("{}", err))?;
+        let mut settings = Settings::new();
+
+        for (name, value) in table {
+            match (name.as_str(), value) {
+                ("turbo_multiplier", value) => {
+                    settings.turbo_multiplier = number(&name, value)?;
+                    if settings.turbo_multiplier <= 0. {
+                        return Err("turbo_multiplier must be above 0".into());
+                    }
+                },
+                ("deadzone", value) => {
+                    let deadzone = number(&name, value)? as f32;
+                    if !(0. ..1.).contains(&deadzone) {
+                        return Err("deadzone must be from 0 to 1".into());
+                    }
+                    settings.input.deadzone = deadzone;
+                },
+                ("autofire_rate", value) => {
+                    settings.input.autofire_rate = number(&name, value)?;
+                    if settings.input.autofire_rate <= 0. {
+                        return Err("autofire_rate must be above 0".into());
+                    }
+                },
+                ("controller_db", toml::Value::String(path)) => {
+                    settings.input.controller_db = Some(path);
+                },
+                ("hotkeys", toml::Value::Table(hotkeys)) => {
+                    for (name, key) in hotkeys {
+                        let hotkey =
+                            Hotkey::from_name(&name).ok_or_else(|| {
+                                format!("Unknown hotkey {}", name)
+                            })?;
+                        let key = key.as_str().ok_or_else(|| {
+                            format!("Hotkey {} must be a key name", name)
+                        })?;
+                        settings.set_hotkey(hotkey, key);
+                    }
+                },
+                ("scale", toml::Value::Integer(scale)) => {
+                    if scale < 1 {
+                        return Err("scale must be at least 1".into());
+                    }
+                    settings.scale = scale as u32;
+                },
+                ("scale_mode", toml::Value::String(mode)) => {
+                    settings.scale_mode =
+                        ScaleMode::from_name(&mode).ok_or_else(|| {
+                            format!(
+                                "scale_mode must be integer, fit or stretch, not {}",
+                                mode
+                            )
+                        })?;
+                },
+                ("vsync", toml::Value::Boolean(vsync)) => {
+                    settings.vsync = vsync
+                },
+                ("fullscreen", toml::Value::Boolean(fullscreen)) => {
+                    settings.fullscreen = fullscreen
+                },
+                ("mute", toml::Value::Boolean(mute)) => settings.mute = mute,
+                ("keyboard", toml::Value::Table(buttons)) => {
+                    settings.set_buttons(Device::Keyboard, buttons)?
+                },
+                ("gamepad", toml::Value::Table(buttons)) => {
+                    settings.set_buttons(Device::Gamepad(0), buttons)?
+                },
+                ("joystick", toml::Value::Table(buttons)) => {
+                    settings.set_buttons(Device::Joystick(0), buttons)?
+                },
+                (name, _) => return Err(format!("Unknown setting {}", name)),
+            }
+        }
+
+        Ok(settings)
+    }
+
+    fn set_buttons(
+        &mut self,
+        device: Device,
+        buttons: toml::Table,
+    ) -> Result<(), String> {
+        for (name, inputs) in buttons {
+            let button = Button::from_name(&name)
+                .ok_or_else(|| format!("Unknown button {}", name))?;
+            let inputs = names(&name, inputs)?;
+            self.input.set_button(device, button, &inputs);
+        }
+        Ok(())
+    }
+
+    // Replaces whatever key the hotkey was on
+    pub fn set_hotkey(&mut self, hotkey: Hotkey, key_name: &str) {
+        self.hotkeys.retain(|(_, bound)| *bound != hotkey);
+        self.hotkeys.push((key_name.to_string(), hotkey));
+    }
+
+    pub fn new() -> Settings {
+        Settings {
+            hotkeys: Hotkey::ALL
+                .into_iter()
+                .map(|hotkey| (hotkey.default_key().to_string(), hotkey))
+                .collect(),
+            turbo_multiplier: DEFAULT_TURBO_MULTIPLIER,
+            input: InputMapping::new(),
+            scale: DEFAULT_SCALE,
+            scale_mode: ScaleMode::Integer,
+            vsync: true,
+            fullscreen: false,
+            mute: false,
+            start_paused: false,
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings::new()
+    }
+}
@@ -0,0 +1,63 @@
+// How the frontends fit the Gameboy's screen into their windows
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScaleMode {
+    // The biggest whole multiple of the screen that fits, so every pixel is
+    // the same size
+    Integer,
+    // As big as fits without changing the shape of the screen
+    AspectFit,
+    // Fills the window
+    Stretch,
+}
+
+impl ScaleMode {
+    pub const ALL: [ScaleMode; 3] =
+        [ScaleMode::Integer, ScaleMode::AspectFit, ScaleMode::Stretch];
+
+    // As written in the settings file
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScaleMode::Integer => "integer",
+            ScaleMode::AspectFit => "fit",
+            ScaleMode::Stretch => "stretch",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ScaleMode> {
+        ScaleMode::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+}
+
+// Where to draw the screen in a window, as (x, y, width, height). Anything
+// left over is split evenly either side.
+pub fn screen_rect(
+    mode: ScaleMode,
+    window_size: (u32, u32),
+    screen_size: (u32, u32),
+) -> (i32, i32, u32, u32) {
+    let (window_width, window_height) = window_size;
+    let (screen_width, screen_height) = screen_size;
+
+    let (width, height) = match mode {
+        ScaleMode::Integer => {
+            // Too small a window still gets the whole screen
+            let scale = (window_width / screen_width)
+                .min(window_height / screen_height)
+                .max(1);
+            (screen_width * scale, screen_height * scale)
+        },
+        ScaleMode::AspectFit => {
+            let scale = (window_width as f64 / screen_width as f64)
+                .min(window_height as f64 / screen_height as f64);
+            (
+                (screen_width as f64 * scale) as u32,
+                (screen_height as f64 * scale) as u32,
+            )
+        },
+        ScaleMode::Stretch => (window_width, window_height),
+    };
+
+    let x = (window_width as i32 - width as i32) / 2;
+    let y = (window_height as i32 - height as i32) / 2;
+    (x, y, width, height)
+}
@@ -0,0 +1,50 @@
+// Renders a song from a GBS file to a WAV file without any GUI
+// Usage: gbs_to_wav <file.gbs> <song number> <seconds> <output.wav>
+use gbrs_core::constants::SOUND_SAMPLE_RATE;
+use gbrs_core::gbs::{render_song_to_wav, Gbs};
+use std::env;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 5 {
+        println!(
+            "Usage: gbs_to_wav <file.gbs> <song number> <seconds> <output.wav>"
+        );
+        return;
+    }
+
+    let bytes = std::fs::read(&args[1]).expect("Unable to read GBS file");
+    let gbs = match Gbs::parse(&bytes) {
+        Ok(gbs) => gbs,
+        Err(err) => {
+            println!("Can't play {}: {}", args[1], err);
+            return;
+        },
+    };
+    println!(
+        "{} - {} ({} songs)",
+        gbs.header.title, gbs.header.author, gbs.header.song_count
+    );
+
+    // Song numbers are 1-based for people, 0-based for the player
+    let song: u8 = match args[2].parse() {
+        Ok(song) if (1..=gbs.header.song_count).contains(&song) => song,
+        _ => {
+            println!(
+                "The song number should be from 1 to {}",
+                gbs.header.song_count
+            );
+            return;
+        },
+    };
+    let seconds: usize = match args[3].parse() {
+        Ok(seconds) => seconds,
+        Err(_) => {
+            println!("The length should be a whole number of seconds");
+            return;
+        },
+    };
+
+    render_song_to_wav(gbs, song - 1, seconds, SOUND_SAMPLE_RATE, &args[4])
+        .expect("Unable to write WAV file");
+}
@@ -0,0 +1,34 @@
+// Runs a ROM without any GUI and prints whatever it sends out of the link
+// port, which is how test ROMs such as Blargg's report their results
+// Usage: serial_output <rom> <seconds>
+use gbrs_core::config::Config;
+use gbrs_core::constants::{FRAME_RATE, SOUND_BUFFER_SIZE, SOUND_SAMPLE_RATE};
+use gbrs_core::cpu::Cpu;
+use gbrs_core::host::default_host;
+use gbrs_core::memory::rom::Rom;
+use std::env;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        println!("Usage: serial_output <rom> <seconds>");
+        return;
+    }
+
+    let seconds: usize = args[2].parse().expect("Invalid number of seconds");
+    let mut gameboy = Cpu::from_config(Config {
+        sound_buffer_size: SOUND_BUFFER_SIZE,
+        sound_sample_rate: SOUND_SAMPLE_RATE,
+        rom: Rom::from_file(&args[1]),
+        host: default_host(),
+        model: None,
+        boot_rom: None,
+        patch: None,
+    });
+
+    for _ in 0..(seconds as f64 * FRAME_RATE) as usize {
+        gameboy.step_one_frame();
+    }
+
+    print!("{}", String::from_utf8_lossy(gameboy.serial_output()));
+}
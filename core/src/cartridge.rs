@@ -1,4 +1,6 @@
 // Parses the cartridge header
+use crate::crc32::crc32;
+use crate::host::SharedHost;
 use crate::log;
 
 #[cfg(not(feature = "std"))]
@@ -21,10 +23,18 @@ pub struct Cartridge {
     pub ram_size: usize,
 
     pub cgb_support: CGBSupportType,
+    pub sgb_support: bool,
+
+    // Identifies the exact ROM, for things like save states and movies
+    pub rom_crc32: u32,
 }
 
 impl Cartridge {
-    pub fn parse(buffer: &Vec<u8>, rom_path: String) -> Cartridge {
+    pub fn parse(
+        buffer: &Vec<u8>,
+        rom_path: String,
+        host: &SharedHost,
+    ) -> Cartridge {
         let title = get_title(buffer);
 
         let cart_type = buffer[0x0147];
@@ -36,13 +46,13 @@ impl Cartridge {
         let ram_size = match ram_size_id {
             0 => 0,
             1 => {
-                log!("[WARN] Unofficial 2KB RAM size not used by any officially published game.");
+                log!(host, "[WARN] Unofficial 2KB RAM size not used by any officially published game.");
                 2_048
             },
             2 => 8_192,
             3 => 32_768,
             4 => {
-                log!("[WARN] RAM size is larger than a u16. Internal implementations such as BatteryBackedRam may fail.");
+                log!(host, "[WARN] RAM size is larger than a u16. Internal implementations such as BatteryBackedRam may fail.");
                 131_072
             },
             5 => 65_536,
@@ -57,6 +67,9 @@ impl Cartridge {
             _ => CGBSupportType::None,
         };
 
+        // Only games with the new licensee code can use SGB features
+        let sgb_support = buffer[0x0146] == 0x03 && buffer[0x014B] == 0x33;
+
         Cartridge {
             title,
             rom_path,
@@ -64,6 +77,8 @@ impl Cartridge {
             rom_size,
             ram_size,
             cgb_support,
+            sgb_support,
+            rom_crc32: crc32(buffer),
         }
     }
 }
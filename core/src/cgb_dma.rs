@@ -1,35 +1,110 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
 #[derive(Debug, PartialEq)]
 pub enum CgbDmaType {
     GeneralPurpose,
     HBlank,
 }
 
+// How long the CPU is paused for each 0x10 bytes copied, in single speed
+// clocks. It takes the same amount of real time in double speed mode.
+pub const CGB_DMA_BLOCK_CLOCKS: usize = 32;
+
 pub struct CgbDmaConfig {
+    // These move along as the transfer goes
     pub source: u16,
     pub dest: u16,
     pub dma_type: CgbDmaType,
-    pub bytes_copied: u16,
     pub bytes_left: u16,
-    pub transfer_done: bool,
+    // A transfer has been started and hasn't finished or been cancelled
+    pub active: bool,
+    // HBlank DMA copies its first block straight away if the LCD is off
+    pub block_pending: bool,
+    // CPU clocks that the CPU still has to wait for the copying
+    pub stall_cycles: usize,
 }
 
 impl CgbDmaConfig {
-    pub fn set_config_byte(&mut self, value: u8) {
-        self.transfer_done = false;
-        self.dma_type = if value & 0x80 == 0x80 {
+    pub fn set_config_byte(&mut self, value: u8, display_enabled: bool) {
+        let hblank = value & 0x80 == 0x80;
+
+        // Writing with bit 7 clear stops a running HBlank DMA. What's left
+        // can still be read back from HDMA5.
+        if self.active && self.is_hblank_dma() && !hblank {
+            self.active = false;
+            self.block_pending = false;
+            return;
+        }
+
+        self.dma_type = if hblank {
             CgbDmaType::HBlank
         } else {
             CgbDmaType::GeneralPurpose
         };
-        self.bytes_left = ((value & 0x7F) + 1) as u16 * 0x10;
-        self.bytes_copied = 0;
+        self.bytes_left = ((value & 0x7F) as u16 + 1) * 0x10;
+        self.active = true;
+        // There won't be an HBlank until the LCD is turned back on
+        self.block_pending = hblank && !display_enabled;
     }
     pub fn get_config_byte(&self) -> u8 {
-        if self.transfer_done {
-            return 0xFF;
+        let blocks_left = (self.bytes_left / 0x10) as u8;
+        if self.active {
+            // Bit 7 clear while a transfer is running
+            blocks_left - 1
+        } else if blocks_left == 0 {
+            0xFF
+        } else {
+            // Cancelled part way through
+            0x80 | (blocks_left - 1)
         }
-        // TODO: Not sure this is quite the correct calculation
-        ((self.bytes_left / 0x10) - 1) as u8
+    }
+
+    // Whether a block should be copied now. General purpose DMA copies
+    // everything at once, HBlank DMA a block each HBlank.
+    pub fn wants_block(&self, in_hblank: bool) -> bool {
+        self.active
+            && match self.dma_type {
+                CgbDmaType::GeneralPurpose => true,
+                CgbDmaType::HBlank => in_hblank || self.block_pending,
+            }
+    }
+
+    // DMA can't copy from VRAM or from echo RAM and above. The data that
+    // comes out is garbage, so we give 0xFF.
+    pub fn source_is_valid(address: u16) -> bool {
+        !(0x8000..0xA000).contains(&address) && address < 0xE000
+    }
+
+    // Moves on after copying a byte. Returns false if the destination has
+    // run off the end of VRAM, which ends the transfer.
+    pub fn advance(&mut self) -> bool {
+        self.source = self.source.wrapping_add(1);
+        self.dest += 1;
+        self.bytes_left -= 1;
+        self.dest <= 0x9FFF
+    }
+
+    pub fn finish_block(&mut self, double_speed: bool) {
+        self.block_pending = false;
+        self.stall_cycles += match double_speed {
+            true => CGB_DMA_BLOCK_CLOCKS * 2,
+            false => CGB_DMA_BLOCK_CLOCKS,
+        };
+        if self.bytes_left == 0 {
+            self.active = false;
+        }
+    }
+
+    // The dest wrapped past 0x9FFF
+    pub fn abort(&mut self) {
+        self.active = false;
+        self.block_pending = false;
+        self.bytes_left = 0;
+    }
+
+    // Hands the CPU stall over to the CPU
+    pub fn take_stall_cycles(&mut self) -> usize {
+        core::mem::take(&mut self.stall_cycles)
     }
 
     pub fn is_hblank_dma(&self) -> bool {
@@ -81,8 +156,38 @@ impl CgbDmaConfig {
             dest: 0,
             dma_type: CgbDmaType::GeneralPurpose,
             bytes_left: 0,
-            bytes_copied: 0,
-            transfer_done: false,
+            active: false,
+            block_pending: false,
+            stall_cycles: 0,
+        }
+    }
+}
+
+impl SaveState for CgbDmaConfig {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.source);
+        state.write_u16(self.dest);
+        state.write_bool(self.dma_type == CgbDmaType::HBlank);
+        state.write_u16(self.bytes_left);
+        state.write_bool(self.active);
+        state.write_bool(self.block_pending);
+        state.write_usize(self.stall_cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.source = state.read_u16();
+        self.dest = state.read_u16();
+        self.dma_type = match state.read_bool() {
+            true => CgbDmaType::HBlank,
+            false => CgbDmaType::GeneralPurpose,
+        };
+        self.bytes_left = state.read_u16();
+        if self.bytes_left > 0x800 {
+            state.corrupt = true;
+            self.bytes_left = 0;
         }
+        self.active = state.read_bool() && self.bytes_left > 0;
+        self.block_pending = state.read_bool();
+        self.stall_cycles = state.read_usize();
     }
 }
@@ -0,0 +1,452 @@
+// Game Genie and GameShark cheats.
+//
+// A Game Genie sits between the cartridge and the Gameboy, and swaps out
+// bytes as they're read from the ROM. Its codes (ABC-DEF, or ABC-DEF-GHI
+// with a compare value) hold an address, a new value and optionally the
+// value that's meant to be there. Banked ROM only has one address for every
+// bank, so the compare value is what picks the right bank: the byte is only
+// replaced while the bank that has the expected value is mapped in.
+//
+// A GameShark writes to RAM instead, once every frame (in VBlank, before the
+// game gets to run its interrupt handler). Its codes are TTVVAAAA: a type,
+// the value, and the address with its low byte first. Type 01 writes to
+// whatever's mapped in, and 9X picks which bank of CGB work RAM to write to.
+// Other types are for GameShark features we don't have, so aren't accepted.
+//
+// Cheat files are RetroArch's .cht format, which is easy to find cheats in:
+//
+//   cheats = 2
+//
+//   cheat0_desc = "Infinite lives"
+//   cheat0_code = "01099AD1"
+//   cheat0_enable = true
+//
+//   cheat1_desc = "Start on world 8"
+//   cheat1_code = "076-4BF-E6E+086-4CF-E6E"
+//   cheat1_enable = false
+//
+// Real cheat files have codes for other devices mixed in, so cheats that
+// can't be read are skipped rather than failing the whole file.
+use crate::constants::MBC_ROM_END;
+use core::fmt;
+
+#[cfg(feature = "std")]
+use std::{fs, io, path::PathBuf};
+
+#[cfg(not(feature = "std"))]
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+pub const CHEAT_FILE_EXTENSION: &str = "cht";
+
+// The GameShark code type for writing to RAM
+const GAMESHARK_WRITE_TYPE: u8 = 0x01;
+// The GameShark code type for writing to a bank of CGB work RAM
+const GAMESHARK_WRAM_BANK_TYPE: u8 = 0x90;
+
+#[derive(Debug, PartialEq)]
+pub enum CheatError {
+    // Not a Game Genie or GameShark code
+    InvalidCode(String),
+    // A line of a cheat file (counting from 1) that isn't `key = value`
+    InvalidLine(usize),
+    // A cheat in a cheat file whose code is missing, or isn't one we support
+    InvalidCheat { index: usize, code: String },
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheatError::InvalidCode(code) => write!(
+                f,
+                "{} isn't a Game Genie (ABC-DEF or ABC-DEF-GHI) or GameShark \
+                 (01VVAAAA or 9XVVAAAA) code",
+                code
+            ),
+            CheatError::InvalidLine(line) => {
+                write!(f, "line {} should be `key = value`", line)
+            },
+            CheatError::InvalidCheat { index, code } if code.is_empty() => {
+                write!(f, "cheat{} has no code", index)
+            },
+            CheatError::InvalidCheat { index, code } => write!(
+                f,
+                "cheat{}'s code ({}) isn't a Game Genie or GameShark code we \
+                 support",
+                index, code
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatCode {
+    GameGenie {
+        address: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+    GameShark {
+        code_type: u8,
+        address: u16,
+        value: u8,
+    },
+}
+
+impl CheatCode {
+    // The CGB work RAM bank a GameShark code writes to, if it says
+    pub fn wram_bank(&self) -> Option<usize> {
+        match self {
+            CheatCode::GameShark { code_type, .. }
+                if code_type & 0xF0 == GAMESHARK_WRAM_BANK_TYPE =>
+            {
+                Some((code_type & 0x07) as usize)
+            },
+            _ => None,
+        }
+    }
+
+    fn parse_hex(digits: &str) -> Option<Vec<u8>> {
+        digits
+            .chars()
+            .map(|digit| digit.to_digit(16).map(|digit| digit as u8))
+            .collect()
+    }
+
+    // The Game Genie scrambles its codes a little: the address's top digit
+    // is flipped and moved to the end, and the compare value is flipped and
+    // rotated
+    fn parse_game_genie(digits: &[u8]) -> Option<CheatCode> {
+        let address = ((digits[5] ^ 0xF) as u16) << 12
+            | (digits[2] as u16) << 8
+            | (digits[3] as u16) << 4
+            | digits[4] as u16;
+        if address > MBC_ROM_END {
+            return None;
+        }
+        // The 8th digit is a check digit, which we don't check
+        let compare = match digits.len() {
+            9 => Some((digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xBA),
+            _ => None,
+        };
+        Some(CheatCode::GameGenie {
+            address,
+            value: digits[0] << 4 | digits[1],
+            compare,
+        })
+    }
+
+    fn parse_gameshark(digits: &[u8]) -> Option<CheatCode> {
+        let byte = |i: usize| digits[i] << 4 | digits[i + 1];
+        let code_type = byte(0);
+        if code_type != GAMESHARK_WRITE_TYPE
+            && code_type & 0xF0 != GAMESHARK_WRAM_BANK_TYPE
+        {
+            return None;
+        }
+        let address = (byte(6) as u16) << 8 | byte(4) as u16;
+        // Writing to the ROM would switch banks
+        if address <= MBC_ROM_END {
+            return None;
+        }
+        Some(CheatCode::GameShark {
+            code_type,
+            address,
+            value: byte(2),
+        })
+    }
+
+    // Game Genie codes can be written with or without their dashes
+    pub fn parse(code: &str) -> Result<CheatCode, CheatError> {
+        let invalid = || CheatError::InvalidCode(code.to_string());
+        let digits =
+            CheatCode::parse_hex(&code.replace('-', "")).ok_or_else(invalid)?;
+        match digits.len() {
+            6 | 9 => CheatCode::parse_game_genie(&digits),
+            8 if !code.contains('-') => CheatCode::parse_gameshark(&digits),
+            _ => None,
+        }
+        .ok_or_else(invalid)
+    }
+}
+
+#[derive(Clone)]
+pub struct Cheat {
+    pub description: String,
+    // As it was typed, which can be several codes joined with +
+    pub code: String,
+    pub enabled: bool,
+    pub codes: Vec<CheatCode>,
+}
+
+impl Cheat {
+    pub fn new(
+        description: &str,
+        code: &str,
+        enabled: bool,
+    ) -> Result<Cheat, CheatError> {
+        let codes = code
+            .split(|c: char| c == '+' || c.is_whitespace())
+            .filter(|code| !code.is_empty())
+            .map(CheatCode::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if codes.is_empty() {
+            return Err(CheatError::InvalidCode(code.to_string()));
+        }
+        Ok(Cheat {
+            description: description.to_string(),
+            code: code.to_string(),
+            enabled,
+            codes,
+        })
+    }
+}
+
+// A cheat as it's read out of a .cht file, which can be in any order
+struct CheatFileEntry {
+    index: usize,
+    description: String,
+    code: String,
+    enabled: bool,
+}
+
+impl CheatFileEntry {
+    fn new(index: usize) -> CheatFileEntry {
+        CheatFileEntry {
+            index,
+            description: String::new(),
+            code: String::new(),
+            enabled: false,
+        }
+    }
+}
+
+// A Game Genie code that's in use
+#[derive(Clone, Copy)]
+struct RomPatch {
+    address: u16,
+    value: u8,
+    compare: Option<u8>,
+}
+
+#[derive(Clone)]
+pub struct Cheats {
+    // By index, which can have gaps as libretro numbers cheats itself
+    cheats: Vec<Option<Cheat>>,
+    // All of the cheats can be switched off at once, without forgetting
+    // which ones are enabled
+    active: bool,
+    // Every enabled Game Genie code, as they're checked on each ROM read
+    rom_patches: Vec<RomPatch>,
+}
+
+impl Cheats {
+    // Replaces ROM reads with Game Genie codes
+    #[inline(always)]
+    pub fn read_rom(&self, address: u16, value: u8) -> u8 {
+        for patch in &self.rom_patches {
+            if patch.address == address
+                && patch.compare.is_none_or(|compare| compare == value)
+            {
+                return patch.value;
+            }
+        }
+        value
+    }
+
+    // The GameShark codes to write this frame
+    pub fn ram_writes(&self) -> impl Iterator<Item = &CheatCode> {
+        self.enabled()
+            .flat_map(|cheat| cheat.codes.iter())
+            .filter(|code| matches!(code, CheatCode::GameShark { .. }))
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = &Cheat> {
+        self.cheats
+            .iter()
+            .flatten()
+            .filter(move |cheat| self.active && cheat.enabled)
+    }
+
+    fn update_rom_patches(&mut self) {
+        let rom_patches = self
+            .enabled()
+            .flat_map(|cheat| cheat.codes.iter())
+            .filter_map(|code| match *code {
+                CheatCode::GameGenie {
+                    address,
+                    value,
+                    compare,
+                } => Some(RomPatch {
+                    address,
+                    value,
+                    compare,
+                }),
+                _ => None,
+            })
+            .collect();
+        self.rom_patches = rom_patches;
+    }
+
+    // With their indices, skipping any gaps
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Cheat)> {
+        self.cheats
+            .iter()
+            .enumerate()
+            .filter_map(|(index, cheat)| Some((index, cheat.as_ref()?)))
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Cheat> {
+        self.cheats.get(index)?.as_ref()
+    }
+
+    // Returns the new cheat's index
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        self.cheats.push(Some(cheat));
+        self.update_rom_patches();
+        self.cheats.len() - 1
+    }
+
+    // Puts a cheat at a particular index (for libretro's retro_cheat_set).
+    // If the code is invalid, the index is left empty.
+    pub fn set(
+        &mut self,
+        index: usize,
+        enabled: bool,
+        code: &str,
+    ) -> Result<(), CheatError> {
+        if self.cheats.len() <= index {
+            self.cheats.resize_with(index + 1, || None);
+        }
+        let cheat = Cheat::new("", code, enabled);
+        self.cheats[index] = cheat.as_ref().ok().cloned();
+        self.update_rom_patches();
+        cheat.map(|_| ())
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(Some(cheat)) = self.cheats.get_mut(index) {
+            cheat.enabled = enabled;
+        }
+        self.update_rom_patches();
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if let Some(cheat) = self.cheats.get_mut(index) {
+            *cheat = None;
+        }
+        self.update_rom_patches();
+    }
+
+    pub fn reset(&mut self) {
+        self.cheats.clear();
+        self.update_rom_patches();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+        self.update_rom_patches();
+    }
+
+    // In the .cht format. Keys other than the description, code and
+    // whether it's enabled are ignored. Returns what had to be skipped
+    // along with the cheats that could be read.
+    pub fn parse(text: &str) -> (Cheats, Vec<CheatError>) {
+        let mut entries: Vec<CheatFileEntry> = Vec::new();
+        let mut skipped = Vec::new();
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some(pair) => pair,
+                None => {
+                    skipped.push(CheatError::InvalidLine(line_number + 1));
+                    continue;
+                },
+            };
+            let value = value.trim().trim_matches('"');
+
+            // cheatN_field
+            let (index, field) = match key
+                .trim()
+                .strip_prefix("cheat")
+                .and_then(|key| key.split_once('_'))
+                .and_then(|(index, field)| Some((index.parse().ok()?, field)))
+            {
+                Some(key) => key,
+                None => continue,
+            };
+            let entry = match entries.iter().position(|e| e.index == index) {
+                Some(position) => &mut entries[position],
+                None => {
+                    entries.push(CheatFileEntry::new(index));
+                    entries.last_mut().unwrap()
+                },
+            };
+            match field {
+                "desc" => entry.description = value.to_string(),
+                "code" => entry.code = value.to_string(),
+                "enable" => entry.enabled = value == "true",
+                _ => {},
+            }
+        }
+
+        let mut cheats = Cheats::new();
+        entries.sort_by_key(|entry| entry.index);
+        for entry in entries {
+            match Cheat::new(&entry.description, &entry.code, entry.enabled) {
+                Ok(cheat) => {
+                    cheats.add(cheat);
+                },
+                Err(_) => skipped.push(CheatError::InvalidCheat {
+                    index: entry.index,
+                    code: entry.code,
+                }),
+            }
+        }
+        (cheats, skipped)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn from_file(path: &str) -> io::Result<(Cheats, Vec<CheatError>)> {
+        let text = fs::read_to_string(path)?;
+        Ok(Cheats::parse(&text))
+    }
+
+    // The path of a cheat file with the same name as the ROM, eg. game.cht
+    // for game.gb, if there is one
+    #[cfg(feature = "std")]
+    pub fn path_next_to(rom_path: &str) -> Option<String> {
+        if rom_path.is_empty() {
+            return None;
+        }
+        let mut path = PathBuf::from(rom_path);
+        path.set_extension(CHEAT_FILE_EXTENSION);
+        match path.exists() {
+            true => Some(path.to_string_lossy().into_owned()),
+            false => None,
+        }
+    }
+
+    pub fn new() -> Cheats {
+        Cheats {
+            cheats: Vec::new(),
+            active: true,
+            rom_patches: Vec::new(),
+        }
+    }
+}
+
+impl Default for Cheats {
+    fn default() -> Cheats {
+        Cheats::new()
+    }
+}
@@ -1,5 +1,6 @@
 // Data pertaining to rendering coloured background/window tiles
 // Defined by writing to VRAM bank 1 0x9800 to 0x9FFF
+use crate::state::{SaveState, StateReader, StateWriter};
 
 const BG_MAP_ATTRIBUTE_TABLE_SIZE: usize = 0x800; // 0x9FFF - 0x9800 + 0th addr
 
@@ -81,3 +82,17 @@ impl BgMapAttributeTable {
         }
     }
 }
+
+impl SaveState for BgMapAttributeTable {
+    fn save_state(&self, state: &mut StateWriter) {
+        for entry in self.entries.iter() {
+            state.write_u8(entry.as_u8());
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        for entry in self.entries.iter_mut() {
+            *entry = BgMapAttributeEntry::from_u8(state.read_u8());
+        }
+    }
+}
@@ -0,0 +1,87 @@
+// Colours to show the original Gameboy's 4 shades in, lightest first.
+// The GPU always draws in greys, and these are swapped in on the way out.
+use super::colour::Colour;
+use super::grey_shades::grey_shade_id_from_colour;
+
+pub struct DmgPalette {
+    pub name: &'static str,
+    pub colours: [Colour; 4],
+}
+
+const fn colour(rgb: u32) -> Colour {
+    Colour {
+        red: (rgb >> 16) as u8,
+        green: (rgb >> 8) as u8,
+        blue: rgb as u8,
+    }
+}
+
+pub const DMG_PALETTES: [DmgPalette; 5] = [
+    // What the GPU draws
+    DmgPalette {
+        name: "Grey",
+        colours: [
+            colour(0xDDDDDD),
+            colour(0xAAAAAA),
+            colour(0x888888),
+            colour(0x555555),
+        ],
+    },
+    // The original pea soup LCD
+    DmgPalette {
+        name: "Green",
+        colours: [
+            colour(0x9BBC0F),
+            colour(0x8BAC0F),
+            colour(0x306230),
+            colour(0x0F380F),
+        ],
+    },
+    DmgPalette {
+        name: "Pocket",
+        colours: [
+            colour(0xC4CFA1),
+            colour(0x8B956D),
+            colour(0x4D533C),
+            colour(0x1F1F1F),
+        ],
+    },
+    DmgPalette {
+        name: "Light",
+        colours: [
+            colour(0x00B581),
+            colour(0x009A71),
+            colour(0x00694A),
+            colour(0x004F3B),
+        ],
+    },
+    DmgPalette {
+        name: "Black and white",
+        colours: [
+            colour(0xFFFFFF),
+            colour(0xAAAAAA),
+            colour(0x555555),
+            colour(0x000000),
+        ],
+    },
+];
+
+// Case insensitive
+pub fn dmg_palette_by_name(name: &str) -> Option<&'static DmgPalette> {
+    DMG_PALETTES
+        .iter()
+        .find(|palette| palette.name.eq_ignore_ascii_case(name))
+}
+
+// Recolours RGBA pixels drawn in the GPU's greys
+pub fn apply_dmg_palette(rgba: &mut [u8], palette: &[Colour; 4]) {
+    for pixel in rgba.chunks_mut(4) {
+        let shade = grey_shade_id_from_colour(Colour::new(
+            pixel[0], pixel[1], pixel[2],
+        ));
+        let colour = palette[shade as usize];
+        pixel[0] = colour.red;
+        pixel[1] = colour.green;
+        pixel[2] = colour.blue;
+    }
+}
@@ -22,3 +22,17 @@ pub fn colour_from_grey_shade_id(id: u8) -> Colour {
         _ => panic!("Invalid grey shade id {}", id),
     }
 }
+
+// The reverse of colour_from_grey_shade_id, for the Super Gameboy which
+// recolours the DMG's output
+pub fn grey_shade_id_from_colour(colour: Colour) -> u8 {
+    if colour.red == white().red {
+        0
+    } else if colour.red == light_grey().red {
+        1
+    } else if colour.red == dark_grey().red {
+        2
+    } else {
+        3
+    }
+}
@@ -1,4 +1,5 @@
 pub mod bg_map_attributes;
 pub mod colour;
+pub mod dmg_palettes;
 pub mod grey_shades;
 pub mod palette_ram;
@@ -1,4 +1,5 @@
 use super::colour::Colour;
+use crate::state::{SaveState, StateReader, StateWriter};
 use crate::{combine_u8, cpu::EmulationTarget, memory::ram::Ram};
 
 fn palette_spec_read(address: u16, auto_increment: bool) -> u8 {
@@ -18,16 +19,21 @@ fn palette_data_write(
     address: &mut u16,
     value: u8,
     auto_increment: bool,
+    locked: bool,
 ) {
-    ram.write(*address, value);
+    // The write is lost while the GPU is drawing, but the address still
+    // moves on
+    if !locked {
+        ram.write(*address, value);
+    }
     if auto_increment {
         *address = (*address + 1) % 64;
     }
 }
 
 pub struct PaletteRam {
-    // If this is false, we're a DMG.
-    cgb_features: bool,
+    // If this is false, we're a DMG (or a CGB in DMG compatibility mode)
+    pub cgb_features: bool,
     bg_palette_ram: Ram,
     bg_address: u16,
     bg_auto_increment: bool,
@@ -61,10 +67,14 @@ impl PaletteRam {
         self.read_colour(&self.obj_palette_ram, base_offset + colour_id * 2)
     }
 
-    pub fn raw_read(&self, address: u16) -> u8 {
+    // The data registers are locked while the GPU is drawing (mode 3)
+    pub fn raw_read(&self, address: u16, locked: bool) -> u8 {
         if !self.cgb_features {
             return 0xFF;
         }
+        if locked && (address == 0xFF69 || address == 0xFF6B) {
+            return 0xFF;
+        }
 
         match address {
             0xFF68 => {
@@ -81,7 +91,7 @@ impl PaletteRam {
         }
     }
 
-    pub fn raw_write(&mut self, address: u16, value: u8) {
+    pub fn raw_write(&mut self, address: u16, value: u8, locked: bool) {
         if !self.cgb_features {
             return;
         }
@@ -97,6 +107,7 @@ impl PaletteRam {
                 &mut self.bg_address,
                 value,
                 self.bg_auto_increment,
+                locked,
             ),
 
             0xFF6A => palette_spec_write(
@@ -109,6 +120,7 @@ impl PaletteRam {
                 &mut self.obj_address,
                 value,
                 self.obj_auto_increment,
+                locked,
             ),
 
             _ => panic!(
@@ -132,3 +144,23 @@ impl PaletteRam {
         }
     }
 }
+
+impl SaveState for PaletteRam {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.bg_palette_ram.save_state(state);
+        state.write_u16(self.bg_address);
+        state.write_bool(self.bg_auto_increment);
+        self.obj_palette_ram.save_state(state);
+        state.write_u16(self.obj_address);
+        state.write_bool(self.obj_auto_increment);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.bg_palette_ram.load_state(state);
+        self.bg_address = state.read_u16();
+        self.bg_auto_increment = state.read_bool();
+        self.obj_palette_ram.load_state(state);
+        self.obj_address = state.read_u16();
+        self.obj_auto_increment = state.read_bool();
+    }
+}
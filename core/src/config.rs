@@ -1,10 +1,56 @@
 // Config for creating CPUs
 // This helps with ports
+use crate::cpu::EmulationTarget;
+use crate::host::SharedHost;
+use crate::memory::boot_rom::BootRom;
+use crate::memory::patch::{Patch, PatchError};
 use crate::memory::rom::Rom;
+use core::fmt;
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
 
 #[derive(Clone)]
 pub struct Config {
     pub sound_buffer_size: usize,
     pub sound_sample_rate: usize,
     pub rom: Rom,
+    // See host::default_host for one that saves next to the ROM
+    pub host: SharedHost,
+    // Runs the game as this model rather than the best one it supports
+    pub model: Option<EmulationTarget>,
+    // Runs this before the game, rather than skipping straight to it
+    pub boot_rom: Option<BootRom>,
+    // Applied to the ROM before it's loaded. Without one, a patch with the
+    // same name as the ROM (eg. game.ips for game.gb) is used if there is one.
+    pub patch: Option<Patch>,
+}
+
+// Why a Cpu couldn't be made from a Config
+#[derive(Debug)]
+pub enum ConfigError {
+    // The patch next to the ROM couldn't be read
+    #[cfg(feature = "std")]
+    ReadPatch {
+        path: String,
+        error: std::io::Error,
+    },
+    ApplyPatch {
+        path: String,
+        error: PatchError,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            ConfigError::ReadPatch { path, error } => {
+                write!(f, "couldn't read patch {}: {}", path, error)
+            },
+            ConfigError::ApplyPatch { path, error } => {
+                write!(f, "couldn't apply patch {}: {}", path, error)
+            },
+        }
+    }
 }
@@ -13,16 +13,22 @@ pub const SCREEN_HEIGHT: usize = 144;
 pub const SCREEN_BUFFER_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
 pub const SCREEN_RGBA_SLICE_SIZE: usize = SCREEN_BUFFER_SIZE * 4;
 
+// The Super Gameboy draws a border around the screen
+pub const SGB_SCREEN_WIDTH: usize = 256;
+pub const SGB_SCREEN_HEIGHT: usize = 224;
+
+pub const SGB_SCREEN_BUFFER_SIZE: usize = SGB_SCREEN_WIDTH * SGB_SCREEN_HEIGHT;
+
 pub const CLOCK_SPEED: usize = 4194304;
-pub const DEFAULT_FRAME_RATE: usize = 60;
+// How many (single speed) clocks the Gameboy spends on each frame. This is
+// why it really runs at ~59.73 FPS rather than 60.
+pub const CLOCKS_PER_FRAME: usize = 70224;
+pub const FRAME_RATE: f64 = CLOCK_SPEED as f64 / CLOCKS_PER_FRAME as f64;
 
-// The amount of sound samples we collect before firing them off for
-// playback. This number is essentially guessed.
+// Defaults for ports that don't have an opinion on their sound output. The
+// APU itself uses whatever is set in the Config.
 pub const SOUND_BUFFER_SIZE: usize = 2048;
 pub const SOUND_SAMPLE_RATE: usize = 48000;
-// The amount of APU step()s we should run before
-// we sample for audio.
-pub const APU_SAMPLE_CLOCKS: usize = CLOCK_SPEED / SOUND_SAMPLE_RATE;
 
 // MBC_ROM_START is 0
 pub const MBC_ROM_END: u16 = 0x7FFF;
@@ -62,13 +68,15 @@ pub const HRAM_START: u16 = 0xFF80;
 pub const HRAM_END: u16 = 0xFFFE;
 
 pub const LCD_DATA_START: u16 = 0xFF40;
-pub const LCD_DATA_END: u16 = 0xFF4C;
+pub const LCD_DATA_END: u16 = 0xFF4B;
 
 pub const CGB_DMA_START: u16 = 0xFF51;
 pub const CGB_DMA_END: u16 = 0xFF55;
 
 pub const CGB_PALETTE_DATA_START: u16 = 0xFF68;
 pub const CGB_PALETTE_DATA_END: u16 = 0xFF6B;
+// OPRI
+pub const CGB_OBJECT_PRIORITY: u16 = 0xFF6C;
 
 pub const INTERRUPT_ENABLE_ADDRESS: u16 = 0xFFFF;
 pub const INTERRUPT_FLAG_ADDRESS: u16 = 0xFF0F;
@@ -1,13 +1,24 @@
 use crate::cartridge::{CGBSupportType, Cartridge};
-use crate::config::Config;
+use crate::colour::colour::Colour;
+use crate::colour::dmg_palettes::apply_dmg_palette;
+use crate::config::{Config, ConfigError};
 use crate::constants::*;
 use crate::gpu::Gpu;
+use crate::host::SharedHost;
 use crate::interrupts::*;
 use crate::log;
+use crate::memory::battery_backed_ram::SaveFormat;
 use crate::memory::memory::Memory;
+#[cfg(feature = "std")]
+use crate::memory::patch::Patch;
+use crate::movie::{Movie, MovieError, MovieMode, MovieSession, MovieStart};
 use crate::registers::Registers;
+use crate::state::{SaveState, StateError, StateReader, StateWriter};
 use crate::{bitmatch, combine_u8, compute_equal, compute_mask, set_bit};
 
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
 const BREAKPOINTS: [u16; 0] = [];
 const CPU_DEBUG: bool = false;
 
@@ -16,6 +27,11 @@ const COND_Z: u8 = 0b01;
 const COND_NC: u8 = 0b10;
 const COND_C: u8 = 0b11;
 
+const STATE_MAGIC: &[u8; 8] = b"GBRSSTAT";
+// Bump this whenever the layout of save states changes
+const STATE_VERSION: u8 = 10;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum EmulationTarget {
     // Original GameBoy
     Dmg,
@@ -25,6 +41,8 @@ pub enum EmulationTarget {
     CgbCgbMode,
     // GmaeBoy Advance in CGB back-compat mode
     GbaCgbMode,
+    // Super GameBoy, with borders and colourisation
+    Sgb,
 }
 
 impl EmulationTarget {
@@ -34,6 +52,17 @@ impl EmulationTarget {
             EmulationTarget::CgbDmgMode => false,
             EmulationTarget::CgbCgbMode => true,
             EmulationTarget::GbaCgbMode => true,
+            EmulationTarget::Sgb => false,
+        }
+    }
+
+    // Some registers exist on CGB hardware even in DMG compatibility mode
+    pub fn is_cgb_hardware(&self) -> bool {
+        match self {
+            EmulationTarget::Dmg | EmulationTarget::Sgb => false,
+            EmulationTarget::CgbDmgMode
+            | EmulationTarget::CgbCgbMode
+            | EmulationTarget::GbaCgbMode => true,
         }
     }
 }
@@ -43,6 +72,8 @@ impl EmulationTarget {
 const TARGET_FOR_CGB_OPTIONAL_GAMES: EmulationTarget =
     EmulationTarget::CgbCgbMode;
 
+// Games with SGB features still run on a DMG unless the SGB is asked for, as
+// its bigger screen with a border isn't what everyone wants
 fn emulation_target_for_cart_info(cart_info: &Cartridge) -> EmulationTarget {
     match cart_info.cgb_support {
         CGBSupportType::None => EmulationTarget::Dmg,
@@ -51,14 +82,34 @@ fn emulation_target_for_cart_info(cart_info: &Cartridge) -> EmulationTarget {
     }
 }
 
+// Colour hardware runs games that don't know about it in DMG compatibility
+// mode, whatever we ask for
+fn emulation_target_for_model(
+    model: EmulationTarget,
+    cart_info: &Cartridge,
+) -> EmulationTarget {
+    match cart_info.cgb_support {
+        CGBSupportType::None if model.has_cgb_features() => {
+            EmulationTarget::CgbDmgMode
+        },
+        _ => model,
+    }
+}
+
 pub struct Cpu {
     pub cart_info: Cartridge,
+    pub emulation_target: EmulationTarget,
     pub mem: Memory,
 
     pub regs: Registers,
 
     pub gpu: Gpu,
-    pub frame_rate: usize,
+    // Frames run by step_one_frame
+    pub frame_count: usize,
+    // How fast ports should run the emulation compared to a real Gameboy.
+    // Above 1 fast forwards, below 1 is slow motion. It doesn't change what
+    // happens in a frame, only how often ports ask for one (see frame_rate).
+    pub speed_multiplier: f64,
 
     pub ints: Interrupts,
     // When EI is executed, they're turned on after the instruction after the EI
@@ -71,6 +122,17 @@ pub struct Cpu {
     clock_counter: usize,
 
     halted: bool,
+
+    // An input movie being recorded or played back
+    pub movie: Option<MovieSession>,
+
+    // Colours for DMG games in place of the GPU's greys. CGB and SGB games
+    // bring their own.
+    pub dmg_palette: Option<[Colour; 4]>,
+
+    // Where logs, saves and the like go. Shared with the parts of the
+    // Gameboy that need it.
+    pub host: SharedHost,
 }
 
 impl Cpu {
@@ -184,48 +246,112 @@ impl Cpu {
         }
     }
 
-    // Runs enough steps to be ready to render one frame
-    // (GUI implementations should get the frame from gpu.finished_frame)
-    pub fn step_one_frame(&mut self) -> usize {
-        let mut cycles_per_frame = CLOCK_SPEED / self.frame_rate;
-        if self.mem.speed_switch.current_speed_is_double {
-            cycles_per_frame *= 2;
+    // The size of the picture from get_rgba_frame. This is bigger than the
+    // Gameboy screen when there's a Super Gameboy border around it.
+    pub fn screen_width(&self) -> usize {
+        match self.mem.sgb {
+            Some(_) => SGB_SCREEN_WIDTH,
+            None => SCREEN_WIDTH,
         }
+    }
+
+    pub fn screen_height(&self) -> usize {
+        match self.mem.sgb {
+            Some(_) => SGB_SCREEN_HEIGHT,
+            None => SCREEN_HEIGHT,
+        }
+    }
+
+    // The last finished frame, including any Super Gameboy border
+    pub fn get_rgba_frame(&self) -> Vec<u8> {
+        match &self.mem.sgb {
+            Some(sgb) => sgb.get_rgba_frame(),
+            None => {
+                let mut rgba = self.gpu.get_rgba_frame().to_vec();
+                if let Some(palette) = &self.dmg_palette {
+                    if !self.emulation_target.has_cgb_features() {
+                        apply_dmg_palette(&mut rgba, palette);
+                    }
+                }
+                rgba
+            },
+        }
+    }
+
+    // Runs until the GPU enters VBlank, so the frame is ready to render
+    // (GUI implementations should get the frame from get_rgba_frame). While
+    // the LCD is off, runs for the length of a frame instead.
+    pub fn step_one_frame(&mut self) -> usize {
+        // A frame that ended while a port was stepping some other way has
+        // already been missed
+        self.gpu.take_frame_ready();
 
         let mut cycles = 0;
-        while cycles < cycles_per_frame {
+        while !self.gpu.take_frame_ready() {
             cycles += self.step()
         }
+        self.frame_count += 1;
 
         cycles
     }
 
-    // Runs the CPU until the APU has filled its buffer defined by the
-    // SOUND_BUFFER_SIZE constant
+    // How many times a second ports should call step_one_frame to run at
+    // speed_multiplier
+    pub fn frame_rate(&self) -> f64 {
+        FRAME_RATE * self.speed_multiplier
+    }
+
+    // Runs the CPU until the APU has generated at least the sound buffer size
+    // given in the Config. The samples can then be collected with
+    // APU::read_samples
     pub fn step_until_full_audio_buffer(&mut self) -> usize {
         let mut cycles = 0;
 
-        loop {
+        while !self.mem.apu.is_buffer_full() {
             cycles += self.step();
-            if self.mem.apu.buffer_full {
-                self.mem.apu.buffer_full = false;
-                break;
-            }
         }
 
         cycles
     }
 
+    // Calls a subroutine as if a CALL instruction had been executed at the
+    // current PC. Used by GBS playback to run the rip's INIT and PLAY code.
+    pub fn call(&mut self, address: u16) {
+        self.halted = false;
+        self.stack_push(self.regs.pc);
+        self.regs.pc = address;
+    }
+
+    // Returns the number of CPU clocks that passed, which are twice as fast
+    // as normal in double speed mode
     pub fn step(&mut self) -> usize {
+        // In double speed mode, the CPU (along with the timers and serial
+        // port, which Memory::step runs from the CPU's clocks) does two steps
+        // in the time the GPU and APU do one
+        let double_speed = self.mem.speed_switch.current_speed_is_double;
         let mut cycles = self.single_speed_step();
-        if self.mem.speed_switch.current_speed_is_double {
+        // Unless the first step switched speeds
+        if double_speed && self.mem.speed_switch.current_speed_is_double {
             cycles += self.single_speed_step();
         }
-        let half_speed_cycles =
-            match self.mem.speed_switch.current_speed_is_double {
-                true => cycles / 2,
-                false => cycles,
+        let mut half_speed_cycles = match double_speed {
+            true => cycles / 2,
+            false => cycles,
+        };
+
+        if let Some(cgb_mode) = self.mem.take_key0_lock() {
+            self.set_cgb_mode(cgb_mode);
+        }
+
+        // While the speed switches, only the CPU and timers are stopped
+        let stall_cycles = self.mem.speed_switch.take_stall_cycles();
+        if stall_cycles > 0 {
+            half_speed_cycles += stall_cycles;
+            cycles += match self.mem.speed_switch.current_speed_is_double {
+                true => stall_cycles * 2,
+                false => stall_cycles,
             };
+        }
 
         for _ in 0..half_speed_cycles {
             self.gpu.step(&mut self.ints, &mut self.mem);
@@ -236,21 +362,210 @@ impl Cpu {
             self.mem.apu.step();
         }
 
+        // Real time, so that the RTC doesn't run fast in double speed mode
+        self.clock_counter += half_speed_cycles;
+        // A single step can be longer than a millisecond, like the pause
+        // after a speed switch
+        self.ms_since_boot += self.clock_counter / (CLOCK_SPEED / 1000);
+        self.clock_counter %= CLOCK_SPEED / 1000;
+
+        if let Some(movie) = self.movie.as_mut() {
+            movie.step(half_speed_cycles, &mut self.mem.joypad);
+        }
+
         cycles
     }
 
+    // For CGB hardware, switches between CGB mode and DMG compatibility mode.
+    // The CGB boot ROM does this through KEY0 when it's finished.
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.emulation_target = match (self.emulation_target, cgb_mode) {
+            (EmulationTarget::CgbDmgMode, true) => EmulationTarget::CgbCgbMode,
+            (
+                EmulationTarget::CgbCgbMode | EmulationTarget::GbaCgbMode,
+                false,
+            ) => EmulationTarget::CgbDmgMode,
+            (target, _) => target,
+        };
+        self.mem.set_cgb_features(cgb_mode);
+        self.gpu.cgb_features = cgb_mode;
+    }
+
+    // Snapshots the whole machine, to be restored with load_state
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bytes.extend_from_slice(STATE_MAGIC);
+        state.write_u8(STATE_VERSION);
+        state.write_u32(self.cart_info.rom_crc32);
+        SaveState::save_state(self, &mut state);
+        state.bytes
+    }
+
+    // If the state can't be loaded, the CPU is left as it was
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        if bytes.len() < STATE_MAGIC.len()
+            || &bytes[..STATE_MAGIC.len()] != STATE_MAGIC
+        {
+            return Err(StateError::InvalidHeader);
+        }
+
+        let mut state = StateReader::new(&bytes[STATE_MAGIC.len()..]);
+        let version = state.read_u8();
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if state.read_u32() != self.cart_info.rom_crc32 {
+            return Err(StateError::RomMismatch);
+        }
+
+        let backup = self.save_state();
+        SaveState::load_state(self, &mut state);
+
+        if state.corrupt || !state.is_at_end() {
+            self.load_state(&backup)
+                .expect("Failed to restore state after a bad load");
+            return Err(StateError::Corrupt);
+        }
+        Ok(())
+    }
+
+    // The game's save in the .sav layout, or None if the cartridge doesn't
+    // have a battery
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.save_data_as(SaveFormat::Sav)
+    }
+
+    pub fn save_data_as(&self, format: SaveFormat) -> Option<Vec<u8>> {
+        match self.mem.battery() {
+            Some(battery) if battery.has_battery() => {
+                Some(battery.save_data(format))
+            },
+            _ => None,
+        }
+    }
+
+    // Replaces the game's save. Takes either the .sav or .srm layout, and
+    // passes it on to the host to keep.
+    pub fn load_save_data(&mut self, bytes: &[u8]) {
+        if let Some(battery) = self.mem.battery_mut() {
+            battery.load_save_data(bytes);
+            battery.flush();
+        }
+    }
+
+    // Hands any save that's waiting on the debounce to the host. This also
+    // happens when the Cpu is dropped.
+    pub fn flush_saves(&mut self) {
+        if let Some(battery) = self.mem.battery_mut() {
+            battery.flush();
+        }
+    }
+
+    // The layout is picked from the file extension
+    #[cfg(feature = "std")]
+    pub fn export_save(&self, path: &str) -> std::io::Result<()> {
+        match self.save_data_as(SaveFormat::from_path(path)) {
+            Some(bytes) => std::fs::write(path, bytes),
+            None => Err(std::io::Error::other(
+                "This cartridge doesn't have a battery",
+            )),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn import_save(&mut self, path: &str) -> std::io::Result<()> {
+        let bytes = std::fs::read(path)?;
+        self.load_save_data(&bytes);
+        Ok(())
+    }
+
+    fn is_at_power_on(&self) -> bool {
+        self.ms_since_boot == 0 && self.clock_counter == 0
+    }
+
+    // Starts recording the joypad into a movie. Without a save state, this
+    // must be called before the CPU has been stepped at all.
+    pub fn start_movie_recording(
+        &mut self,
+        from_save_state: bool,
+    ) -> Result<(), MovieError> {
+        let start = if from_save_state {
+            MovieStart::SaveState(self.save_state())
+        } else if self.is_at_power_on() {
+            MovieStart::PowerOn
+        } else {
+            return Err(MovieError::NotAtPowerOn);
+        };
+
+        let movie =
+            Movie::new(self.cart_info.rom_crc32, self.emulation_target, start);
+        let mut session = MovieSession::new(movie, MovieMode::Recording);
+        session.begin_frame(&mut self.mem.joypad);
+        self.movie = Some(session);
+        Ok(())
+    }
+
+    // Power-on movies must be played on a CPU that hasn't been stepped yet.
+    // Movies that start from a save state load it first.
+    pub fn start_movie_playback(
+        &mut self,
+        movie: Movie,
+    ) -> Result<(), MovieError> {
+        if movie.rom_crc32 != self.cart_info.rom_crc32 {
+            return Err(MovieError::RomMismatch);
+        }
+        if movie.model != self.emulation_target {
+            return Err(MovieError::ModelMismatch);
+        }
+        match &movie.start {
+            MovieStart::PowerOn => {
+                if !self.is_at_power_on() {
+                    return Err(MovieError::NotAtPowerOn);
+                }
+            },
+            MovieStart::SaveState(state) => self.load_state(state)?,
+        }
+
+        let mut session = MovieSession::new(movie, MovieMode::Playing);
+        session.begin_frame(&mut self.mem.joypad);
+        self.movie = Some(session);
+        Ok(())
+    }
+
+    // Hands the joypad back to the port, and returns the movie (which, if it
+    // was being recorded, now has every frame up to this point)
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        self.mem.joypad.movie_input = None;
+        self.movie.take().map(|session| session.movie)
+    }
+
+    // Everything the game has sent out of the link port, oldest first
+    pub fn serial_output(&mut self) -> &[u8] {
+        self.mem.serial_cable.output.make_contiguous()
+    }
+
+    // Like serial_output, but empties the buffer
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        Vec::from(core::mem::take(&mut self.mem.serial_cable.output))
+    }
+
     pub fn single_speed_step(&mut self) -> usize {
         let p = self.ime_on_pending;
 
         let cycles: usize;
 
-        if self.halted {
+        let dma_stall_cycles = self.gpu.take_dma_stall_cycles();
+        if dma_stall_cycles > 0 {
+            // The CPU waits for CGB DMA to finish copying
+            cycles = dma_stall_cycles;
+        } else if self.halted {
             cycles = 4;
         } else {
             let op = self.read_next();
 
             if CPU_DEBUG {
                 log!(
+                    self.host,
                     "PC: {:#06x} | OPCODE: {:#04x} | {}",
                     self.regs.pc - 1,
                     op,
@@ -366,11 +681,22 @@ impl Cpu {
                 // STOP
                 0b00010000 => {
                     if self.mem.speed_switch.armed {
+                        // STOP resets DIV, then the CPU pauses for a really
+                        // long time while the speed switches (see step)
+                        self.mem_write(0xFF04, 0);
                         self.mem.speed_switch.execute_speed_switch();
-                        // CPU halts for a really long time during speed switch
-                        SPEED_SWITCH_HALT_CYCLES
+                        log!(
+                            self.host,
+                            "Performing CGB speed switch. New speed: {}",
+                            match self.mem.speed_switch.current_speed_is_double
+                            {
+                                true => "Double",
+                                false => "Single",
+                            }
+                        );
+                        4
                     } else {
-                        log!("[WARN] STOP with un-armed CGB Speed Switch. Not used in commercial games.");
+                        log!(self.host, "[WARN] STOP with un-armed CGB Speed Switch. Not used in commercial games.");
                         4
                     }
                 },
@@ -747,12 +1073,6 @@ impl Cpu {
 
         self.process_interrupts();
 
-        self.clock_counter += cycles;
-        if self.clock_counter >= CLOCK_SPEED / 1000 {
-            self.ms_since_boot += 1;
-            self.clock_counter = 0;
-        }
-
         return cycles;
     }
 
@@ -839,22 +1159,90 @@ impl Cpu {
         }
     }
 
+    // Panics if the patch can't be read or applied. Ports that load files
+    // the player picks should use try_from_config.
     pub fn from_config(config: Config) -> Cpu {
+        Cpu::try_from_config(config).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_from_config(config: Config) -> Result<Cpu, ConfigError> {
+        let mut rom = config.rom;
+        #[cfg(feature = "std")]
+        let patch = match config.patch {
+            Some(patch) => Some(patch),
+            None => match Patch::path_next_to(&rom.path) {
+                Some(path) => {
+                    Some(Patch::from_file(&path).map_err(|error| {
+                        ConfigError::ReadPatch { path, error }
+                    })?)
+                },
+                None => None,
+            },
+        };
+        #[cfg(not(feature = "std"))]
+        let patch = config.patch;
+        if let Some(patch) = patch {
+            rom.apply_patch(&patch).map_err(|error| {
+                ConfigError::ApplyPatch {
+                    path: patch.path.clone(),
+                    error,
+                }
+            })?;
+            log!(config.host, "Applied patch {}", patch.path);
+        }
+
         let cart_info =
-            Cartridge::parse(&config.rom.bytes, config.rom.path.clone());
-        let emulation_target = emulation_target_for_cart_info(&cart_info);
+            Cartridge::parse(&rom.bytes, rom.path.clone(), &config.host);
+        let emulation_target = match config.model {
+            Some(model) => emulation_target_for_model(model, &cart_info),
+            None => emulation_target_for_cart_info(&cart_info),
+        };
+        // The CGB boot ROM runs in CGB mode, and picks the game's mode itself
+        // through KEY0
+        let emulation_target = match (&config.boot_rom, emulation_target) {
+            (Some(boot_rom), EmulationTarget::CgbDmgMode)
+                if boot_rom.is_cgb() =>
+            {
+                EmulationTarget::CgbCgbMode
+            },
+            (_, target) => target,
+        };
+
+        let mem = Memory::from_info(
+            cart_info.clone(),
+            rom,
+            &emulation_target,
+            config.sound_sample_rate,
+            config.sound_buffer_size,
+            config.host,
+        );
+        let mut cpu = Cpu::from_memory(cart_info, mem, &emulation_target);
+
+        // The boot ROM starts from the very beginning, and leaves things
+        // as from_memory already has them by the time it gets to 0x100
+        // (apart from KEY0, which it sets itself)
+        if let Some(boot_rom) = config.boot_rom {
+            cpu.mem.boot_rom = Some(boot_rom);
+            cpu.regs.pc = 0;
+        }
+        Ok(cpu)
+    }
 
+    pub fn from_memory(
+        cart_info: Cartridge,
+        mem: Memory,
+        emulation_target: &EmulationTarget,
+    ) -> Cpu {
+        let host = mem.host.clone();
         Cpu {
-            mem: Memory::from_info(
-                cart_info.clone(),
-                config.rom,
-                &emulation_target,
-            ),
+            mem,
             cart_info,
-            regs: Registers::new(&emulation_target),
+            emulation_target: *emulation_target,
+            regs: Registers::new(emulation_target),
 
-            gpu: Gpu::new(emulation_target.has_cgb_features()),
-            frame_rate: DEFAULT_FRAME_RATE,
+            gpu: Gpu::new(emulation_target.has_cgb_features(), host.clone()),
+            frame_count: 0,
+            speed_multiplier: 1.0,
 
             ints: Interrupts::new(),
             ime_on_pending: false,
@@ -863,6 +1251,43 @@ impl Cpu {
             clock_counter: 0,
 
             halted: false,
+
+            movie: None,
+
+            dmg_palette: None,
+
+            host,
+        }
+    }
+}
+
+impl SaveState for Cpu {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.regs.save_state(state);
+        self.ints.save_state(state);
+        state.write_bool(self.ime_on_pending);
+        state.write_bool(self.halted);
+        state.write_usize(self.ms_since_boot);
+        state.write_usize(self.clock_counter);
+        state.write_usize(self.frame_count);
+
+        self.mem.save_state(state);
+        self.gpu.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.regs.load_state(state);
+        self.ints.load_state(state);
+        self.ime_on_pending = state.read_bool();
+        self.halted = state.read_bool();
+        self.ms_since_boot = state.read_usize();
+        self.clock_counter = state.read_usize();
+        self.frame_count = state.read_usize();
+
+        self.mem.load_state(state);
+        self.gpu.load_state(state);
+        if self.emulation_target.is_cgb_hardware() {
+            self.set_cgb_mode(self.mem.has_cgb_features());
         }
     }
 }
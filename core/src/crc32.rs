@@ -0,0 +1,58 @@
+// The CRC-32 used by PNG, zip and the IPS/BPS family. Used to identify ROMs
+// and to check file integrity.
+
+const fn make_crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+const CRC_TABLE: [u32; 256] = make_crc_table();
+
+// For checksumming data that isn't in one contiguous slice
+pub struct Crc32 {
+    value: u32,
+}
+
+impl Crc32 {
+    pub fn update(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.value = CRC_TABLE[((self.value ^ *b as u32) & 0xFF) as usize]
+                ^ (self.value >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.value ^ 0xFFFF_FFFF
+    }
+
+    pub fn new() -> Crc32 {
+        Crc32 { value: 0xFFFF_FFFF }
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
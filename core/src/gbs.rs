@@ -0,0 +1,382 @@
+// Plays GBS (Gameboy Sound System) music rips. These are a game's sound
+// driver and music data with a small header describing how to start a song
+// and how often to call the driver.
+// https://ocremix.org/info/GBS_Format_Specification
+use crate::cartridge::{CGBSupportType, Cartridge};
+use crate::constants::CLOCKS_PER_FRAME;
+use crate::cpu::{Cpu, EmulationTarget};
+use crate::crc32::crc32;
+use crate::host::SharedHost;
+use crate::memory::mbcs::gbs::GbsMapper;
+use crate::memory::memory::Memory;
+use crate::memory::rom::Rom;
+use crate::{combine_u8, log};
+use core::fmt;
+
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+
+const HEADER_SIZE: usize = 0x70;
+// The CPU waits here (in a JR loop) for the next call to PLAY. The area
+// below the load address isn't part of the rip, so we're free to use it.
+const IDLE_ADDRESS: u16 = 0x0100;
+
+#[derive(Debug, PartialEq)]
+pub enum GbsError {
+    // Doesn't start with "GBS"
+    NotGbs,
+    // Too short to have a whole header and any music data
+    Truncated,
+    // The data has to be loaded somewhere in the ROM, above the vectors
+    InvalidLoadAddress(u16),
+    NoSongs,
+    // A song number (0-based) past the end of the rip
+    NoSuchSong { song: u8, song_count: u8 },
+}
+
+impl fmt::Display for GbsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GbsError::NotGbs => write!(f, "not a GBS file"),
+            GbsError::Truncated => write!(f, "the GBS file is cut short"),
+            GbsError::InvalidLoadAddress(address) => {
+                write!(f, "invalid GBS load address {:#06x}", address)
+            },
+            GbsError::NoSongs => write!(f, "the GBS file has no songs"),
+            GbsError::NoSuchSong { song, song_count } => write!(
+                f,
+                "song {} doesn't exist, this GBS has {} songs",
+                *song as usize + 1,
+                song_count
+            ),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GbsHeader {
+    pub version: u8,
+    pub song_count: u8,
+    // 1-based, as in the file
+    pub first_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+impl GbsHeader {
+    // When bit 2 of the timer control is set, PLAY is called at the timer's
+    // rate rather than every VBlank
+    pub fn uses_timer(&self) -> bool {
+        self.timer_control & 0b100 != 0
+    }
+
+    pub fn uses_double_speed(&self) -> bool {
+        self.timer_control & 0x80 != 0
+    }
+
+    // How many CPU cycles (as returned by Cpu::step) between calls to PLAY
+    pub fn play_period(&self) -> usize {
+        if self.uses_timer() {
+            let clocks_per_tick = match self.timer_control & 0b11 {
+                0b00 => 1024,
+                0b01 => 16,
+                0b10 => 64,
+                0b11 => 256,
+                _ => unreachable!(),
+            };
+            (256 - self.timer_modulo as usize) * clocks_per_tick
+        } else if self.uses_double_speed() {
+            // VBlank happens at the same real-world rate, which is twice as
+            // many CPU cycles in double speed
+            CLOCKS_PER_FRAME * 2
+        } else {
+            CLOCKS_PER_FRAME
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Gbs {
+    pub header: GbsHeader,
+    // Everything after the header, which is loaded at header.load_address
+    pub data: Vec<u8>,
+}
+
+impl Gbs {
+    pub fn parse(bytes: &[u8]) -> Result<Gbs, GbsError> {
+        if !bytes.starts_with(b"GBS") {
+            return Err(GbsError::NotGbs);
+        }
+        if bytes.len() <= HEADER_SIZE {
+            return Err(GbsError::Truncated);
+        }
+
+        let read_u16 =
+            |offset: usize| combine_u8!(bytes[offset + 1], bytes[offset]);
+
+        let header = GbsHeader {
+            version: bytes[0x03],
+            song_count: bytes[0x04],
+            first_song: bytes[0x05],
+            load_address: read_u16(0x06),
+            init_address: read_u16(0x08),
+            play_address: read_u16(0x0A),
+            stack_pointer: read_u16(0x0C),
+            timer_modulo: bytes[0x0E],
+            timer_control: bytes[0x0F],
+            title: get_header_string(&bytes[0x10..0x30]),
+            author: get_header_string(&bytes[0x30..0x50]),
+            copyright: get_header_string(&bytes[0x50..0x70]),
+        };
+
+        if header.load_address < 0x0400 || header.load_address > 0x7FFF {
+            return Err(GbsError::InvalidLoadAddress(header.load_address));
+        }
+        if header.song_count == 0 {
+            return Err(GbsError::NoSongs);
+        }
+
+        Ok(Gbs {
+            header,
+            data: bytes[HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    // Lays the rip out as a ROM image, with RST and interrupt vectors jumping
+    // to their relocated versions at the load address
+    fn build_rom(&self) -> Rom {
+        let load_address = self.header.load_address as usize;
+        let mut bytes = vec![0xFF; load_address];
+        bytes.extend_from_slice(&self.data);
+
+        for vector in (0x00..=0x60).step_by(8) {
+            let target = (load_address + vector) as u16;
+            bytes[vector] = 0xC3; // JP a16
+            bytes[vector + 1] = (target & 0xFF) as u8;
+            bytes[vector + 2] = (target >> 8) as u8;
+        }
+
+        let idle = IDLE_ADDRESS as usize;
+        bytes[idle] = 0x18; // JR -2
+        bytes[idle + 1] = 0xFE;
+
+        Rom::from_bytes(bytes)
+    }
+}
+
+fn get_header_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|b| **b != 0)
+        .map(|b| *b as char)
+        .collect()
+}
+
+pub struct GbsPlayer {
+    pub gbs: Gbs,
+    pub cpu: Cpu,
+    // 0-based
+    pub song: u8,
+
+    play_period: usize,
+    cycles_since_play: usize,
+    // A call to PLAY that's due, but waiting for INIT or the last PLAY to
+    // return first
+    play_pending: bool,
+    sound_sample_rate: usize,
+    sound_buffer_size: usize,
+    host: SharedHost,
+}
+
+impl GbsPlayer {
+    // Resets the machine and calls INIT for a song (0-based). If there's no
+    // such song, the current one keeps playing.
+    pub fn start_song(&mut self, song: u8) -> Result<(), GbsError> {
+        self.cpu = boot_song(
+            &self.gbs,
+            song,
+            self.sound_sample_rate,
+            self.sound_buffer_size,
+            self.host.clone(),
+        )?;
+        self.song = song;
+        self.cycles_since_play = 0;
+        self.play_pending = false;
+        Ok(())
+    }
+
+    pub fn step(&mut self) -> usize {
+        let cycles = self.cpu.step();
+        self.cycles_since_play += cycles;
+
+        while self.cycles_since_play >= self.play_period {
+            self.cycles_since_play -= self.play_period;
+            self.play_pending = true;
+        }
+
+        // If INIT or the last PLAY is still running, PLAY is called as soon
+        // as it returns
+        if self.play_pending && self.cpu.regs.pc == IDLE_ADDRESS {
+            self.play_pending = false;
+            self.cpu.call(self.gbs.header.play_address);
+        }
+
+        cycles
+    }
+
+    pub fn step_until_full_audio_buffer(&mut self) -> usize {
+        let mut cycles = 0;
+
+        while !self.cpu.mem.apu.is_buffer_full() {
+            cycles += self.step();
+        }
+
+        cycles
+    }
+
+    // Starts on the song the GBS file says is first
+    pub fn new(
+        gbs: Gbs,
+        sound_sample_rate: usize,
+        sound_buffer_size: usize,
+        host: SharedHost,
+    ) -> GbsPlayer {
+        if gbs.header.version != 1 {
+            log!(host, "[WARN] Unknown GBS version {}", gbs.header.version);
+        }
+
+        let song = match gbs.header.first_song {
+            first_song if first_song <= gbs.header.song_count => {
+                first_song.saturating_sub(1)
+            },
+            _ => 0,
+        };
+        GbsPlayer {
+            cpu: boot_song(
+                &gbs,
+                song,
+                sound_sample_rate,
+                sound_buffer_size,
+                host.clone(),
+            )
+            .expect("The first song was checked against the song count"),
+            song,
+            play_period: gbs.header.play_period(),
+            cycles_since_play: 0,
+            play_pending: false,
+            sound_sample_rate,
+            sound_buffer_size,
+            host,
+            gbs,
+        }
+    }
+}
+
+// Builds a fresh machine with the rip loaded and INIT called for the song
+fn boot_song(
+    gbs: &Gbs,
+    song: u8,
+    sound_sample_rate: usize,
+    sound_buffer_size: usize,
+    host: SharedHost,
+) -> Result<Cpu, GbsError> {
+    let header = &gbs.header;
+    if song >= header.song_count {
+        return Err(GbsError::NoSuchSong {
+            song,
+            song_count: header.song_count,
+        });
+    }
+
+    let (emulation_target, cgb_support) = match header.uses_double_speed() {
+        true => (EmulationTarget::CgbCgbMode, CGBSupportType::Required),
+        false => (EmulationTarget::Dmg, CGBSupportType::None),
+    };
+
+    let rom = gbs.build_rom();
+    let cart_info = Cartridge {
+        title: header.title.clone(),
+        rom_path: String::new(),
+        cart_type: 0,
+        rom_size: rom.bytes.len(),
+        ram_size: 8_192,
+        cgb_support,
+        sgb_support: false,
+        rom_crc32: crc32(&rom.bytes),
+    };
+    let mem = Memory::with_mbc(
+        Box::new(GbsMapper::new(rom)),
+        &emulation_target,
+        sound_sample_rate,
+        sound_buffer_size,
+        host,
+    );
+    let mut cpu = Cpu::from_memory(cart_info, mem, &emulation_target);
+    cpu.mem.speed_switch.current_speed_is_double = header.uses_double_speed();
+
+    // Some drivers expect the sound hardware to already be on
+    let init_writes = [
+        (0xFF26, 0x80),
+        (0xFF25, 0xFF),
+        (0xFF24, 0x77),
+        (0xFF06, header.timer_modulo),
+        (0xFF07, header.timer_control & 0b111),
+    ];
+    for (address, value) in init_writes {
+        cpu.mem.write(&mut cpu.ints, &mut cpu.gpu, address, value);
+    }
+
+    cpu.regs.sp = header.stack_pointer;
+    cpu.regs.a = song;
+    cpu.regs.pc = IDLE_ADDRESS;
+    cpu.call(header.init_address);
+    Ok(cpu)
+}
+
+// Headlessly renders `seconds` of a song (0-based) to a stereo WAV file.
+// A song that doesn't exist is an InvalidInput error.
+#[cfg(feature = "std")]
+pub fn render_song_to_wav(
+    gbs: Gbs,
+    song: u8,
+    seconds: usize,
+    sample_rate: usize,
+    path: &str,
+) -> std::io::Result<()> {
+    use crate::constants::SOUND_BUFFER_SIZE;
+    use crate::host::default_host;
+    use crate::wav::WavWriter;
+    use std::{
+        fs::File,
+        io::{BufWriter, Error, ErrorKind},
+    };
+
+    let mut player =
+        GbsPlayer::new(gbs, sample_rate, SOUND_BUFFER_SIZE, default_host());
+    player
+        .start_song(song)
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))?;
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut wav = WavWriter::new(file, sample_rate, 2)?;
+
+    let mut samples_left = seconds * sample_rate * 2;
+    let mut buffer = vec![0; SOUND_BUFFER_SIZE];
+    while samples_left > 0 {
+        player.step_until_full_audio_buffer();
+        let count = player.cpu.mem.apu.read_samples(&mut buffer);
+        let count = count.min(samples_left);
+        wav.write_samples(&buffer[..count])?;
+        samples_left -= count;
+    }
+
+    wav.finish()?;
+    Ok(())
+}
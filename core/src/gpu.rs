@@ -4,11 +4,13 @@ use crate::colour::grey_shades;
 use crate::colour::grey_shades::colour_from_grey_shade_id;
 use crate::combine_u8;
 use crate::constants::*;
+use crate::host::SharedHost;
 use crate::interrupts::*;
 use crate::lcd::*;
 use crate::log;
 use crate::memory::memory::Memory;
 use crate::memory::ram::Ram;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 use smallvec::SmallVec;
 
@@ -29,7 +31,7 @@ pub struct Sprite {
 }
 
 pub struct Gpu {
-    cgb_features: bool,
+    pub cgb_features: bool,
     // This is the WIP frame that the GPU draws to
     frame: [Colour; SCREEN_BUFFER_SIZE],
     // This is the last rendered frame displayed on the LCD, only updated
@@ -71,6 +73,16 @@ pub struct Gpu {
     dma_cycles: u8,
 
     cgb_dma: CgbDmaConfig,
+    // OPRI, which CGB hardware sets when running DMG games so that sprites
+    // overlap in the DMG's order
+    object_priority_by_x: bool,
+
+    // Clocks since the last frame ended, and whether one has ended since
+    // take_frame_ready was last called. A frame ends when VBlank starts, or
+    // every CLOCKS_PER_FRAME while the LCD is off so that ports still get
+    // their frames.
+    frame_clocks: usize,
+    frame_ready: bool,
 
     // The global 40-sprite OAM cache
     // SmallVec doesn't do blocks of 40 so we leave 24 empty slots, it's still
@@ -80,6 +92,8 @@ pub struct Gpu {
     // TODO: These come straight from sprite_cache. Maybe they can be &Sprite?
     //   Would that be faster?
     sprites_on_line: SmallVec<[Sprite; 10]>,
+
+    host: SharedHost,
 }
 
 impl Gpu {
@@ -133,17 +147,19 @@ impl Gpu {
             0xFF4A => self.wy = value,
             0xFF4B => self.wx = value,
 
-            0xFF4C => log!(
-                "[WARN] Unknown LCD register write at {:#06x} (value: {:#04x})",
-                raw_address,
-                value
-            ),
-
             0xFF51 => self.cgb_dma.set_source_upper(value),
             0xFF52 => self.cgb_dma.set_source_lower(value),
             0xFF53 => self.cgb_dma.set_dest_upper(value),
             0xFF54 => self.cgb_dma.set_dest_lower(value),
-            0xFF55 => self.cgb_dma.set_config_byte(value),
+            0xFF55 => self
+                .cgb_dma
+                .set_config_byte(value, self.control.display_enable),
+
+            0xFF6C => {
+                if self.cgb_features {
+                    self.object_priority_by_x = value & 1 != 0
+                }
+            },
 
             _ => panic!(
                 "Unsupported GPU write at {:#06x} (value: {:#04x})",
@@ -178,13 +194,23 @@ impl Gpu {
             0xFF54 => self.cgb_dma.get_dest_lower(),
             0xFF55 => self.cgb_dma.get_config_byte(),
 
+            0xFF6C if self.cgb_features => {
+                0xFE | self.object_priority_by_x as u8
+            },
+
             _ => {
-                log!("Unsupported GPU read at {:#06x}", raw_address);
+                log!(self.host, "Unsupported GPU read at {:#06x}", raw_address);
                 0xFF
             },
         }
     }
 
+    // Palette RAM can't be accessed while the GPU is drawing with it
+    pub fn is_drawing(&self) -> bool {
+        self.control.display_enable
+            && self.status.get_mode() == LcdMode::Transfer
+    }
+
     fn cache_all_sprites(&mut self) {
         // There's room for 40 sprites in the OAM table
         let mut i = 0;
@@ -239,57 +265,52 @@ impl Gpu {
         // Really, we should be disabling access to anything but HRAM now,
         // but if the rom is nice then there shouldn't be an issue.
         if self.dma_cycles != 0 {
-            log!("INTERRUPTING DMA!")
+            log!(self.host, "INTERRUPTING DMA!")
         }
         self.dma_source = source;
         self.dma_cycles = gpu_timing::DMA_CYCLES;
     }
 
-    fn update_cgb_generic_dma(
+    // Copies a block of 0x10 bytes for CGB DMA if one is due
+    fn update_cgb_dma(
         &mut self,
         ints: &mut Interrupts,
         mem: &mut Memory,
+        in_hblank: bool,
     ) {
-        if self.cgb_dma.bytes_left > 0 && !self.cgb_dma.is_hblank_dma() {
-            for i in 0..self.cgb_dma.bytes_left {
-                let value = mem.read(ints, self, self.cgb_dma.source + i);
-                mem.write(ints, self, self.cgb_dma.dest + i, value);
-            }
-            self.cgb_dma.bytes_left = 0;
-        }
-    }
+        while self.cgb_dma.wants_block(in_hblank) {
+            for _ in 0..0x10 {
+                let source = self.cgb_dma.source;
+                let value = match CgbDmaConfig::source_is_valid(source) {
+                    true => mem.read(ints, self, source),
+                    false => 0xFF,
+                };
+                mem.write(ints, self, self.cgb_dma.dest, value);
 
-    fn update_cgb_hblank_dma(
-        &mut self,
-        ints: &mut Interrupts,
-        mem: &mut Memory,
-    ) {
-        if self.cgb_dma.bytes_left > 0 && self.cgb_dma.is_hblank_dma() {
-            for i in 0..0x10 {
-                // TODO: This shouldn't be called, all DMAs are a multiple of 0x10 long
-                if self.cgb_dma.bytes_left == 0 {
+                if !self.cgb_dma.advance() {
+                    self.cgb_dma.abort();
                     break;
                 }
-                let value = mem.read(
-                    ints,
-                    self,
-                    self.cgb_dma.source + self.cgb_dma.bytes_copied + i,
-                );
-                mem.write(
-                    ints,
-                    self,
-                    self.cgb_dma.dest + self.cgb_dma.bytes_copied + i,
-                    value,
-                );
             }
-            self.cgb_dma.bytes_left -= 0x10;
-            self.cgb_dma.bytes_copied += 0x10;
+            self.cgb_dma
+                .finish_block(mem.speed_switch.current_speed_is_double);
+
+            // HBlank DMA only does one block at a time
+            if self.cgb_dma.is_hblank_dma() {
+                break;
+            }
         }
     }
 
+    // The CPU is paused while CGB DMA copies data. This gives the number of
+    // CPU clocks it has to wait for.
+    pub fn take_dma_stall_cycles(&mut self) -> usize {
+        self.cgb_dma.take_stall_cycles()
+    }
+
     fn update_dma(&mut self, ints: &mut Interrupts, mem: &mut Memory) {
         if self.cgb_features {
-            self.update_cgb_generic_dma(ints, mem)
+            self.update_cgb_dma(ints, mem, false)
         }
 
         // There isn't one pending
@@ -297,7 +318,12 @@ impl Gpu {
             return;
         }
 
-        self.dma_cycles -= 1;
+        // OAM DMA runs at the CPU's speed, so finishes in half the time in
+        // double speed mode
+        self.dma_cycles = match mem.speed_switch.current_speed_is_double {
+            true => self.dma_cycles.saturating_sub(2),
+            false => self.dma_cycles - 1,
+        };
         // Ready to actually perform DMA?
         if self.dma_cycles == 0 {
             let source = (self.dma_source as u16) * 0x100;
@@ -309,7 +335,7 @@ impl Gpu {
         }
     }
 
-    fn enter_vblank(&mut self, ints: &mut Interrupts) {
+    fn enter_vblank(&mut self, ints: &mut Interrupts, mem: &mut Memory) {
         ints.raise_interrupt(InterruptReason::VBlank);
 
         // TODO: This seems like odd behaviour to me.
@@ -318,6 +344,7 @@ impl Gpu {
         }
 
         self.finished_frame = self.frame.clone();
+        self.end_frame(ints, mem);
     }
 
     fn run_ly_compare(&mut self, ints: &mut Interrupts) {
@@ -330,11 +357,29 @@ impl Gpu {
         }
     }
 
+    pub fn take_frame_ready(&mut self) -> bool {
+        core::mem::take(&mut self.frame_ready)
+    }
+
+    pub fn is_display_enabled(&self) -> bool {
+        self.control.display_enable
+    }
+
+    fn end_frame(&mut self, ints: &mut Interrupts, mem: &mut Memory) {
+        self.frame_clocks = 0;
+        self.frame_ready = true;
+        mem.apply_ram_cheats(ints, self);
+    }
+
     pub fn step(&mut self, ints: &mut Interrupts, mem: &mut Memory) {
         // TODO: Check that a DMA is performed even with display off
         self.update_dma(ints, mem);
 
+        self.frame_clocks += 1;
         if !self.control.display_enable {
+            if self.frame_clocks >= CLOCKS_PER_FRAME {
+                self.end_frame(ints, mem);
+            }
             return;
         }
 
@@ -384,8 +429,11 @@ impl Gpu {
             self.run_ly_compare(ints);
             // Done with frame, enter VBlank
             if self.ly == gpu_timing::VBLANK_ON {
-                self.enter_vblank(ints);
+                self.enter_vblank(ints, mem);
                 self.status.set_mode(LcdMode::VBlank);
+                if let Some(sgb) = mem.sgb.as_mut() {
+                    sgb.vblank(&self.finished_frame);
+                }
             } else {
                 if mode != LcdMode::OAMSearch {
                     self.status.set_mode(LcdMode::OAMSearch);
@@ -403,7 +451,7 @@ impl Gpu {
         }
 
         if self.lx == gpu_timing::HBLANK_ON {
-            self.update_cgb_hblank_dma(ints, mem);
+            self.update_cgb_dma(ints, mem, true);
             if self.status.hblank_interrupt {
                 ints.raise_interrupt(InterruptReason::LCDStat)
             }
@@ -439,18 +487,22 @@ impl Gpu {
         let uy = y as usize;
         let idx = uy * SCREEN_WIDTH + ux;
 
-        let bg_col: Colour;
-        let bg_col_id = if self.cgb_features || self.control.bg_display {
-            let (new_col, id) = self.get_background_colour_at(ints, mem, x, y);
-            bg_col = new_col;
-            id
-        } else {
-            bg_col = grey_shades::white();
-            0
-        };
+        let (bg_col, bg_col_id, bg_priority) =
+            if self.cgb_features || self.control.bg_display {
+                self.get_background_colour_at(ints, mem, x, y)
+            } else {
+                (grey_shades::white(), 0, false)
+            };
 
         // If there's a non-transparent sprite here, use its colour
-        let s_col = self.get_sprite_colour_at(mem, bg_col, bg_col_id, x, y);
+        let s_col = self.get_sprite_colour_at(
+            mem,
+            bg_col,
+            bg_col_id,
+            bg_priority,
+            x,
+            y,
+        );
 
         self.frame[idx] = s_col;
     }
@@ -485,7 +537,7 @@ impl Gpu {
         mem: &Memory,
         x: u8,
         y: u8,
-    ) -> (Colour, u16) {
+    ) -> (Colour, u16, bool) {
         let is_window = self.control.window_enable
             && x as isize > self.wx as isize - 8
             && y >= self.wy;
@@ -571,20 +623,87 @@ impl Gpu {
             let colour = mem
                 .palette_ram
                 .get_bg_palette_colour(tile_metadata.palette as u16, col_id);
-            (colour, col_id)
+            (colour, col_id, tile_metadata.priority)
         } else {
             (
                 self.get_shade_from_colour_id(col_id, self.bg_pallette),
                 col_id,
+                false,
             )
         }
     }
 
+    // The colour id of a sprite's pixel, 0 being transparent
+    fn get_sprite_colour_id(
+        &self,
+        mem: &Memory,
+        sprite: &Sprite,
+        ix: i32,
+        iy: i32,
+    ) -> u16 {
+        let sprite_height = if self.control.obj_size { 16 } else { 8 };
+
+        let mut subx = (ix - sprite.x_pos) as u8;
+        let mut suby = iy - sprite.y_pos;
+
+        // Tile address for 8x8 mode
+        let mut pattern = sprite.pattern_id;
+
+        if sprite_height == 16 {
+            if suby > 7 {
+                suby -= 8;
+
+                if sprite.y_flip {
+                    pattern = sprite.pattern_id & 0xFE;
+                } else {
+                    pattern = sprite.pattern_id | 0x01;
+                }
+            } else {
+                if sprite.y_flip {
+                    pattern = sprite.pattern_id | 0x01;
+                } else {
+                    pattern = sprite.pattern_id & 0xFE;
+                }
+            }
+        }
+
+        if sprite.x_flip {
+            subx = 7 - subx
+        }
+        // Flipping each half of an 8x16 sprite and swapping them over (above)
+        // flips the whole thing
+        if sprite.y_flip {
+            suby = 7 - suby
+        }
+
+        let tile_address = 0x8000 + (pattern as u16) * 16;
+        let line_we_need = suby as u16 * 2;
+        let bank = if self.cgb_features && sprite.use_upper_vram_bank {
+            1
+        } else {
+            0
+        };
+        let tile_address = tile_address + line_we_need;
+
+        let tile_line0 = mem.vram.read_arbitrary_bank(bank, tile_address);
+        let tile_line1 = mem.vram.read_arbitrary_bank(bank, tile_address + 1);
+        let tile_line = combine_u8!(tile_line1, tile_line0);
+
+        self.get_colour_id_in_line(tile_line, subx)
+    }
+
+    // DMG picks between overlapping sprites by their X position, CGB by
+    // their place in OAM unless OPRI says otherwise
+    fn objects_prioritised_by_x(&self) -> bool {
+        !self.cgb_features || self.object_priority_by_x
+    }
+
     fn get_sprite_colour_at(
         &self,
         mem: &Memory,
         bg_col: Colour,
         bg_col_id: u16,
+        bg_priority: bool,
         x: u8,
         y: u8,
     ) -> Colour {
@@ -593,106 +712,65 @@ impl Gpu {
             return bg_col;
         }
 
-        let sprite_height = if self.control.obj_size { 16 } else { 8 };
-
         let ix = x as i32;
         let iy = y as i32;
+        let by_x = self.objects_prioritised_by_x();
 
-        let mut maybe_colour: Option<Colour> = None;
-        let mut min_x: i32 = SCREEN_WIDTH as i32 + 8;
+        // Find the highest priority sprite with a non-transparent pixel here.
+        // sprites_on_line is in OAM order, so ties on X go to the first one.
+        let mut winner: Option<(&Sprite, u16)> = None;
         for sprite in &self.sprites_on_line {
-            let mut above_bg = sprite.above_bg;
-            // In CGB mode, bg_display off means sprites always get priority.
-            if self.cgb_features && !self.control.bg_display {
-                above_bg = true;
+            if sprite.x_pos > ix || (sprite.x_pos + 8) <= ix {
+                continue;
             }
-
-            if sprite.x_pos <= ix
-                && (sprite.x_pos + 8) > ix
-                && sprite.x_pos < min_x
-            {
-                if !above_bg && bg_col_id != 0 {
+            if let Some((best, _)) = winner {
+                if sprite.x_pos >= best.x_pos {
                     continue;
                 }
+            }
 
-                let mut subx = (ix - sprite.x_pos) as u8;
-                let mut suby = iy - sprite.y_pos;
-
-                // Tile address for 8x8 mode
-                let mut pattern = sprite.pattern_id;
-
-                if sprite_height == 16 {
-                    if suby > 7 {
-                        suby -= 8;
-
-                        if sprite.y_flip {
-                            pattern = sprite.pattern_id & 0xFE;
-                        } else {
-                            pattern = sprite.pattern_id | 0x01;
-                        }
-                    } else {
-                        if sprite.y_flip {
-                            pattern = sprite.pattern_id | 0x01;
-                        } else {
-                            pattern = sprite.pattern_id & 0xFE;
-                        }
-                    }
-                }
-
-                if sprite.x_flip {
-                    subx = 7 - subx
-                }
-                // TODO: Not sure if this applies to vertically flipped 8x16 mode sprites
-                if sprite.y_flip {
-                    suby = 7 - suby
-                }
-
-                let tile_address = 0x8000 + (pattern as u16) * 16;
-                let line_we_need = suby as u16 * 2;
-                let bank = if self.cgb_features && sprite.use_upper_vram_bank {
-                    1
-                } else {
-                    0
-                };
-                let tile_address = tile_address + line_we_need;
-                // log!("Sprite at [{},{}] is using upper VRAM bank? {:?}, pattern_id {}, address: {:#06x}", sprite.x_pos, sprite.y_pos, sprite.use_upper_vram_bank, sprite.pattern_id, tile_address);
-
-                let tile_line0 =
-                    mem.vram.read_arbitrary_bank(bank, tile_address);
-                let tile_line1 =
-                    mem.vram.read_arbitrary_bank(bank, tile_address + 1);
-                let tile_line = combine_u8!(tile_line1, tile_line0);
+            let col_id = self.get_sprite_colour_id(mem, sprite, ix, iy);
+            if col_id == 0 {
+                // This pixel is transparent
+                continue;
+            }
+            winner = Some((sprite, col_id));
+            if !by_x {
+                break;
+            }
+        }
 
-                let col_id = self.get_colour_id_in_line(tile_line, subx);
+        let (sprite, col_id) = match winner {
+            Some(winner) => winner,
+            None => return bg_col,
+        };
 
-                if col_id == 0 {
-                    // This pixel is transparent
-                    continue;
-                } else {
-                    if self.cgb_features {
-                        let colour = mem.palette_ram.get_obj_palette_colour(
-                            sprite.cgb_palette as u16,
-                            col_id,
-                        );
-                        maybe_colour = Some(colour)
-                    } else {
-                        let palette = if sprite.use_palette_0 {
-                            self.sprite_pallete_1
-                        } else {
-                            self.sprite_pallete_2
-                        };
-
-                        min_x = sprite.x_pos;
-                        maybe_colour =
-                            Some(self.get_shade_from_colour_id(col_id, palette))
-                    }
-                }
-            }
+        // Only the sprite that won gets compared with the background. Even
+        // if it's behind, the sprites it beat don't show through.
+        let bg_wins = if self.cgb_features {
+            // In CGB mode, bg_display off is a master switch that puts
+            // sprites on top regardless. Otherwise either the tile's or the
+            // sprite's priority bit can put non-zero background over it.
+            self.control.bg_display
+                && bg_col_id != 0
+                && (bg_priority || !sprite.above_bg)
+        } else {
+            !sprite.above_bg && bg_col_id != 0
+        };
+        if bg_wins {
+            return bg_col;
         }
 
-        match maybe_colour {
-            Some(col) => col,
-            None => bg_col,
+        if self.cgb_features {
+            mem.palette_ram
+                .get_obj_palette_colour(sprite.cgb_palette as u16, col_id)
+        } else {
+            let palette = if sprite.use_palette_0 {
+                self.sprite_pallete_1
+            } else {
+                self.sprite_pallete_2
+            };
+            self.get_shade_from_colour_id(col_id, palette)
         }
     }
 
@@ -724,7 +802,7 @@ impl Gpu {
         out_array
     }
 
-    pub fn new(cgb_features: bool) -> Gpu {
+    pub fn new(cgb_features: bool, host: SharedHost) -> Gpu {
         let empty_frame = [grey_shades::white(); SCREEN_BUFFER_SIZE];
         Gpu {
             cgb_features,
@@ -747,8 +825,123 @@ impl Gpu {
             dma_source: 0,
             dma_cycles: 0,
             cgb_dma: CgbDmaConfig::new(),
+            object_priority_by_x: false,
+            frame_clocks: 0,
+            frame_ready: false,
             sprite_cache: SmallVec::with_capacity(40),
             sprites_on_line: SmallVec::with_capacity(10),
+            host,
+        }
+    }
+}
+
+fn save_frame(frame: &[Colour], state: &mut StateWriter) {
+    for colour in frame {
+        state.write_u8(colour.red);
+        state.write_u8(colour.green);
+        state.write_u8(colour.blue);
+    }
+}
+
+fn load_frame(frame: &mut [Colour], state: &mut StateReader) {
+    for colour in frame.iter_mut() {
+        colour.red = state.read_u8();
+        colour.green = state.read_u8();
+        colour.blue = state.read_u8();
+    }
+}
+
+impl SaveState for Gpu {
+    fn save_state(&self, state: &mut StateWriter) {
+        save_frame(&self.frame, state);
+        save_frame(&self.finished_frame, state);
+
+        let registers = [
+            self.scy,
+            self.scx,
+            self.wy,
+            self.wx,
+            self.ly,
+            self.lyc,
+            self.window_line_counter,
+            self.bg_pallette,
+            self.sprite_pallete_1,
+            self.sprite_pallete_2,
+            u8::from(self.status),
+            u8::from(self.control.clone()),
+            self.dma_source,
+            self.dma_cycles,
+        ];
+        for value in registers {
+            state.write_u8(value);
+        }
+        state.write_u16(self.lx);
+
+        self.oam.save_state(state);
+        self.cgb_dma.save_state(state);
+        state.write_bool(self.object_priority_by_x);
+        state.write_usize(self.frame_clocks);
+
+        // The sprite cache is only refreshed at certain points, so it can
+        // differ from OAM and has to be saved separately
+        state.write_u8(self.sprite_cache.len() as u8);
+        for sprite in &self.sprite_cache {
+            state.write_u32(sprite.y_pos as u32);
+            state.write_u32(sprite.x_pos as u32);
+            state.write_u8(sprite.pattern_id);
+            state.write_bool(sprite.above_bg);
+            state.write_bool(sprite.y_flip);
+            state.write_bool(sprite.x_flip);
+            state.write_bool(sprite.use_palette_0);
+            state.write_bool(sprite.use_upper_vram_bank);
+            state.write_u8(sprite.cgb_palette);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        load_frame(&mut self.frame, state);
+        load_frame(&mut self.finished_frame, state);
+
+        self.scy = state.read_u8();
+        self.scx = state.read_u8();
+        self.wy = state.read_u8();
+        self.wx = state.read_u8();
+        self.ly = state.read_u8();
+        self.lyc = state.read_u8();
+        self.window_line_counter = state.read_u8();
+        self.bg_pallette = state.read_u8();
+        self.sprite_pallete_1 = state.read_u8();
+        self.sprite_pallete_2 = state.read_u8();
+        self.status = LcdStatus::from(state.read_u8());
+        self.control = LcdControl::from(state.read_u8());
+        self.dma_source = state.read_u8();
+        self.dma_cycles = state.read_u8();
+        self.lx = state.read_u16();
+
+        self.oam.load_state(state);
+        self.cgb_dma.load_state(state);
+        self.object_priority_by_x = state.read_bool();
+        self.frame_clocks = state.read_usize();
+        self.frame_ready = false;
+
+        let sprite_count = state.read_u8() as usize;
+        if sprite_count > 40 {
+            state.corrupt = true;
+            return;
+        }
+        self.sprite_cache.clear();
+        for _ in 0..sprite_count {
+            self.sprite_cache.push(Sprite {
+                y_pos: state.read_u32() as i32,
+                x_pos: state.read_u32() as i32,
+                pattern_id: state.read_u8(),
+                above_bg: state.read_bool(),
+                y_flip: state.read_bool(),
+                x_flip: state.read_bool(),
+                use_palette_0: state.read_bool(),
+                use_upper_vram_bank: state.read_bool(),
+                cgb_palette: state.read_u8(),
+            });
         }
     }
 }
@@ -21,11 +21,11 @@ macro_rules! set_bit(
 
 #[macro_export]
 macro_rules! log {
-    ($($a:expr),*) => {
+    ($host:expr, $($a:expr),*) => {
         {
             #[cfg(not(feature = "std"))]
             use alloc::format;
-            (crate::callbacks::CALLBACKS.lock().log)(&format!($($a,)*)[..])
+            $host.borrow_mut().log(&format!($($a,)*)[..])
         }
     };
 }
@@ -0,0 +1,148 @@
+// The port's side of the emulator. Every Cpu gets its own Host, so that
+// several Gameboys can run in one process, each logging and saving
+// somewhere different.
+// Every method has a default that does nothing, so ports only need to
+// implement the parts they care about.
+
+#[cfg(not(feature = "std"))]
+use alloc::{rc::Rc, vec, vec::Vec};
+use core::cell::RefCell;
+#[cfg(feature = "std")]
+use std::{fs, io::Read, path::PathBuf, rc::Rc};
+
+pub trait Host {
+    fn log(&mut self, _message: &str) {}
+
+    // Stores battery-backed RAM. Called at most about once a second while the
+    // game is changing it.
+    fn save(&mut self, _game_name: &str, _rom_path: &str, _save_data: &[u8]) {}
+
+    // Returns the save stored by `save`, or `expected_size` zeroes for a game
+    // that hasn't been saved yet
+    fn load(
+        &mut self,
+        _game_name: &str,
+        _rom_path: &str,
+        expected_size: usize,
+    ) -> Vec<u8> {
+        vec![0; expected_size]
+    }
+
+    // Called with every byte the Gameboy sends out of the link port. Test
+    // ROMs use this to print their results.
+    fn serial(&mut self, _byte: u8) {}
+
+    // Seconds since the Unix epoch, used by cartridge real time clocks to
+    // catch up on time that passed while the game wasn't running
+    fn now(&mut self) -> u64 {
+        0
+    }
+
+    // Called when a rumble cartridge turns its motor on or off
+    fn set_rumble(&mut self, _on: bool) {}
+}
+
+// Shared between the Cpu and the parts of the Gameboy that talk to the host
+pub type SharedHost = Rc<RefCell<dyn Host>>;
+
+// Does nothing at all
+pub struct NullHost;
+
+impl Host for NullHost {}
+
+// Prints logs and keeps saves as <rom>.sav, next to the ROM unless given a
+// directory for them
+#[cfg(feature = "std")]
+pub struct FileHost {
+    save_dir: Option<PathBuf>,
+}
+
+#[cfg(feature = "std")]
+impl FileHost {
+    fn save_file_path(&self, rom_path: &str) -> PathBuf {
+        let mut sav_path = PathBuf::from(rom_path);
+        sav_path.set_extension("sav");
+
+        match (&self.save_dir, sav_path.file_name()) {
+            (Some(save_dir), Some(file_name)) => save_dir.join(file_name),
+            _ => sav_path,
+        }
+    }
+
+    pub fn new(save_dir: Option<PathBuf>) -> FileHost {
+        FileHost { save_dir }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Host for FileHost {
+    fn log(&mut self, message: &str) {
+        println!("{}", message);
+    }
+
+    // Saves are flushed when the Cpu is dropped, so failing to write one
+    // mustn't panic
+    fn save(&mut self, _game_name: &str, rom_path: &str, save_data: &[u8]) {
+        if let Some(save_dir) = &self.save_dir {
+            if let Err(err) = fs::create_dir_all(save_dir) {
+                self.log(&format!(
+                    "[WARN] Failed to create save directory {}: {}",
+                    save_dir.display(),
+                    err
+                ));
+                return;
+            }
+        }
+        let save_path = self.save_file_path(rom_path);
+        if let Err(err) = fs::write(&save_path, save_data) {
+            self.log(&format!(
+                "[WARN] Failed to write save file {}: {}",
+                save_path.display(),
+                err
+            ));
+        }
+    }
+
+    fn load(
+        &mut self,
+        _game_name: &str,
+        rom_path: &str,
+        expected_size: usize,
+    ) -> Vec<u8> {
+        let save_path = self.save_file_path(rom_path);
+        let mut buffer = vec![];
+        let file_result = fs::File::open(save_path);
+
+        if let Ok(mut file) = file_result {
+            file.read_to_end(&mut buffer)
+                .expect("Unable to read save file");
+            buffer
+        } else {
+            // The save file likely does not exist
+            vec![0; expected_size]
+        }
+    }
+
+    fn now(&mut self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+pub fn shared<H: Host + 'static>(host: H) -> SharedHost {
+    Rc::new(RefCell::new(host))
+}
+
+// FileHost with std, otherwise NullHost
+pub fn default_host() -> SharedHost {
+    #[cfg(feature = "std")]
+    {
+        shared(FileHost::new(None))
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        shared(NullHost)
+    }
+}
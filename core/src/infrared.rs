@@ -0,0 +1,137 @@
+// CGB infrared port (RP, 0xFF56)
+// Games flash the LED and watch the sensor for light from whatever's in front
+// of it, which is an InfraredPeer: another Gameboy, a toy, a TV remote...
+use crate::cpu::Cpu;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, rc::Rc};
+use core::cell::RefCell;
+#[cfg(feature = "std")]
+use std::rc::Rc;
+
+// Both bits have to be set for the sensor to be read
+const READ_ENABLE: u8 = 0b1100_0000;
+
+pub trait InfraredPeer {
+    // Called whenever this Gameboy turns its LED on or off
+    fn set_led(&mut self, on: bool);
+
+    // Whether light is shining onto this Gameboy's sensor
+    fn receiving(&self) -> bool;
+}
+
+pub struct Infrared {
+    pub cgb_features: bool,
+    led_on: bool,
+    read_enable: u8,
+    peer: Option<Box<dyn InfraredPeer>>,
+}
+
+impl Infrared {
+    pub fn read(&self) -> u8 {
+        if !self.cgb_features {
+            return 0xFF;
+        }
+
+        let receiving = self.read_enable == READ_ENABLE
+            && self.peer.as_ref().is_some_and(|peer| peer.receiving());
+        // Bit 1 is low while light is being received, and the unused bits
+        // read high
+        let signal_bit = if receiving { 0 } else { 0b10 };
+        self.read_enable | 0b0011_1100 | signal_bit | self.led_on as u8
+    }
+
+    pub fn write(&mut self, value: u8) {
+        if !self.cgb_features {
+            return;
+        }
+
+        self.read_enable = value & READ_ENABLE;
+        let led_on = value & 1 != 0;
+        if led_on != self.led_on {
+            self.led_on = led_on;
+            if let Some(peer) = self.peer.as_mut() {
+                peer.set_led(led_on);
+            }
+        }
+    }
+
+    pub fn connect(&mut self, mut peer: Box<dyn InfraredPeer>) {
+        peer.set_led(self.led_on);
+        self.peer = Some(peer);
+    }
+
+    pub fn disconnect(&mut self) {
+        self.peer = None;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.peer.is_some()
+    }
+
+    pub fn new(cgb_features: bool) -> Infrared {
+        Infrared {
+            cgb_features,
+            led_on: false,
+            read_enable: 0,
+            peer: None,
+        }
+    }
+}
+
+// The peer is plugged in by the port, so isn't saved
+impl SaveState for Infrared {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.led_on);
+        state.write_u8(self.read_enable);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        let led_on = state.read_bool();
+        self.read_enable = state.read_u8() & READ_ENABLE;
+        if led_on != self.led_on {
+            self.led_on = led_on;
+            if let Some(peer) = self.peer.as_mut() {
+                peer.set_led(led_on);
+            }
+        }
+    }
+}
+
+// Two Gameboys in the same process pointed at each other
+pub struct LocalInfrared {
+    // Each side's LED, indexed by side
+    leds: Rc<RefCell<[bool; 2]>>,
+    side: usize,
+}
+
+impl LocalInfrared {
+    pub fn pair() -> (LocalInfrared, LocalInfrared) {
+        let leds = Rc::new(RefCell::new([false; 2]));
+        (
+            LocalInfrared {
+                leds: leds.clone(),
+                side: 0,
+            },
+            LocalInfrared { leds, side: 1 },
+        )
+    }
+}
+
+impl InfraredPeer for LocalInfrared {
+    fn set_led(&mut self, on: bool) {
+        self.leds.borrow_mut()[self.side] = on;
+    }
+
+    fn receiving(&self) -> bool {
+        self.leds.borrow()[1 - self.side]
+    }
+}
+
+// Lines up two Gameboys' infrared ports. They can then be stepped in turn.
+pub fn connect(a: &mut Cpu, b: &mut Cpu) {
+    let (a_end, b_end) = LocalInfrared::pair();
+    a.mem.infrared.connect(Box::new(a_end));
+    b.mem.infrared.connect(Box::new(b_end));
+}
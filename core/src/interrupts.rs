@@ -1,3 +1,5 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
 pub const INTERRUPT_VECTORS: [u16; 5] = [0x40, 0x48, 0x50, 0x58, 0x60];
 
 #[derive(Clone)]
@@ -110,3 +112,17 @@ impl Interrupts {
         }
     }
 }
+
+impl SaveState for Interrupts {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.enable_read());
+        state.write_u8(self.flag_read());
+        state.write_bool(self.ime);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.enable_write(state.read_u8());
+        self.flag_write(state.read_u8());
+        self.ime = state.read_bool();
+    }
+}
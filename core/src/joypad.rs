@@ -1,3 +1,6 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+use core::cell::Cell;
+
 enum JoypadReadoutMode {
     Buttons,
     Directions,
@@ -18,6 +21,12 @@ pub struct Joypad {
     pub b_pressed: bool,
     pub start_pressed: bool,
     pub select_pressed: bool,
+
+    // While a movie is recording or playing, the game sees these buttons
+    // (see buttons_bitmask) rather than the ones above
+    pub movie_input: Option<u8>,
+    // Set whenever the game reads 0xFF00, for lag frame detection
+    pub polled: Cell<bool>,
 }
 
 impl Joypad {
@@ -32,20 +41,26 @@ impl Joypad {
         }
     }
 
-    #[inline(always)]
-    fn direction_bits(&self) -> u8 {
-        (!self.right_pressed as u8)
-            | ((!self.left_pressed as u8) << 1)
-            | ((!self.up_pressed as u8) << 2)
-            | ((!self.down_pressed as u8) << 3)
+    // All 8 buttons in one byte, 1 meaning pressed. The lower nibble is
+    // right, left, up, down and the upper is A, B, select, start, matching
+    // the order of the bits the game reads.
+    pub fn buttons_bitmask(&self) -> u8 {
+        (self.right_pressed as u8)
+            | ((self.left_pressed as u8) << 1)
+            | ((self.up_pressed as u8) << 2)
+            | ((self.down_pressed as u8) << 3)
+            | ((self.a_pressed as u8) << 4)
+            | ((self.b_pressed as u8) << 5)
+            | ((self.select_pressed as u8) << 6)
+            | ((self.start_pressed as u8) << 7)
     }
 
     #[inline(always)]
-    fn button_bits(&self) -> u8 {
-        (!self.a_pressed as u8)
-            | ((!self.b_pressed as u8) << 1)
-            | ((!self.select_pressed as u8) << 2)
-            | ((!self.start_pressed as u8) << 3)
+    fn effective_bitmask(&self) -> u8 {
+        match self.movie_input {
+            Some(bitmask) => bitmask,
+            None => self.buttons_bitmask(),
+        }
     }
 
     #[inline(always)]
@@ -59,10 +74,19 @@ impl Joypad {
 
     #[inline(always)]
     pub fn read(&self) -> u8 {
+        self.read_as_player(0, self.effective_bitmask())
+    }
+
+    // The Super Gameboy lets up to 4 players share the joypad register. With
+    // neither set of buttons selected, it reads out the current player.
+    #[inline(always)]
+    pub fn read_as_player(&self, player: u8, bitmask: u8) -> u8 {
+        self.polled.set(true);
+
         let n = match self.readout_mode {
-            JoypadReadoutMode::Buttons => self.button_bits(),
-            JoypadReadoutMode::Directions => self.direction_bits(),
-            JoypadReadoutMode::Neither => 0xF,
+            JoypadReadoutMode::Buttons => (!bitmask >> 4) & 0x0F,
+            JoypadReadoutMode::Directions => !bitmask & 0x0F,
+            JoypadReadoutMode::Neither => 0xF - player,
         };
 
         n | self.selection_bits()
@@ -79,6 +103,27 @@ impl Joypad {
             b_pressed: false,
             start_pressed: false,
             select_pressed: false,
+            movie_input: None,
+            polled: Cell::new(false),
         }
     }
 }
+
+impl SaveState for Joypad {
+    // The buttons themselves are input from the port, so aren't saved
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(match self.readout_mode {
+            JoypadReadoutMode::Buttons => 0,
+            JoypadReadoutMode::Directions => 1,
+            JoypadReadoutMode::Neither => 2,
+        });
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.readout_mode = match state.read_u8() {
+            0 => JoypadReadoutMode::Buttons,
+            1 => JoypadReadoutMode::Directions,
+            _ => JoypadReadoutMode::Neither,
+        };
+    }
+}
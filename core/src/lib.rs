@@ -4,19 +4,32 @@
 extern crate alloc;
 
 pub mod alu;
-pub mod callbacks;
 pub mod cartridge;
 pub mod cgb_dma;
+pub mod cheats;
 pub mod colour; // innit bruv
 pub mod config;
 pub mod constants;
 pub mod cpu;
+pub mod crc32;
+pub mod gbs;
 pub mod gpu;
 pub mod helpers;
+pub mod host;
+pub mod infrared;
 pub mod interrupts;
 pub mod joypad;
 pub mod lcd;
+pub mod link;
 pub mod memory;
+pub mod movie;
+pub mod png;
+#[cfg(feature = "std")]
+pub mod recorder;
 pub mod registers;
 pub mod serial_cable;
+pub mod sgb;
 pub mod sound;
+pub mod state;
+#[cfg(feature = "std")]
+pub mod wav;
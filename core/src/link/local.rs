@@ -0,0 +1,70 @@
+// Connects two Gameboys running in the same process, eg. for a port that
+// shows two screens side by side
+use crate::cpu::Cpu;
+use crate::link::SerialLink;
+
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, rc::Rc};
+use core::cell::RefCell;
+#[cfg(feature = "std")]
+use std::rc::Rc;
+
+struct LocalCable {
+    // Indexed by side
+    ready: [Option<u8>; 2],
+    incoming: [Option<u8>; 2],
+}
+
+pub struct LocalLink {
+    cable: Rc<RefCell<LocalCable>>,
+    side: usize,
+}
+
+impl LocalLink {
+    fn other_side(&self) -> usize {
+        1 - self.side
+    }
+
+    // Both ends of one cable
+    pub fn pair() -> (LocalLink, LocalLink) {
+        let cable = Rc::new(RefCell::new(LocalCable {
+            ready: [None; 2],
+            incoming: [None; 2],
+        }));
+
+        (
+            LocalLink {
+                cable: cable.clone(),
+                side: 0,
+            },
+            LocalLink { cable, side: 1 },
+        )
+    }
+}
+
+impl SerialLink for LocalLink {
+    fn set_ready(&mut self, outgoing: Option<u8>) {
+        self.cable.borrow_mut().ready[self.side] = outgoing;
+    }
+
+    fn exchange(&mut self, outgoing: u8) -> Option<u8> {
+        let other = self.other_side();
+        let mut cable = self.cable.borrow_mut();
+
+        // The other Gameboy's transfer completes the next time it's stepped
+        let incoming = cable.ready[other].take()?;
+        cable.incoming[other] = Some(outgoing);
+        Some(incoming)
+    }
+
+    fn poll(&mut self, _cycles: usize) -> Option<u8> {
+        self.cable.borrow_mut().incoming[self.side].take()
+    }
+}
+
+// Plugs a cable between two Gameboys. They can then be stepped in turn.
+pub fn connect(a: &mut Cpu, b: &mut Cpu) {
+    let (a_end, b_end) = LocalLink::pair();
+    a.mem.serial_cable.connect(Box::new(a_end));
+    b.mem.serial_cable.connect(Box::new(b_end));
+}
@@ -0,0 +1,26 @@
+// Things that can be plugged into the other end of the link cable
+
+pub mod local;
+pub mod printer;
+#[cfg(feature = "std")]
+pub mod tcp;
+
+// A byte is shifted out of each end of the cable at the same time, so every
+// transfer swaps the two Gameboys' SB registers. Whichever side uses its
+// internal clock drives the transfer with exchange, and the side using an
+// external clock finds out about it through poll.
+pub trait SerialLink {
+    // Called whenever the byte this Gameboy would send in an externally
+    // clocked transfer changes. None when it isn't waiting for one.
+    fn set_ready(&mut self, outgoing: Option<u8>);
+
+    // Drives a transfer with this Gameboy's clock. Returns the byte from the
+    // other end, or None if nothing was listening (the line then reads 0xFF).
+    fn exchange(&mut self, outgoing: u8) -> Option<u8>;
+
+    // Returns the byte the other end clocked into this Gameboy since the last
+    // call, if it did. The other end was sent the byte from set_ready.
+    // `cycles` have passed since the last call, which lets slow links avoid
+    // checking on every step.
+    fn poll(&mut self, cycles: usize) -> Option<u8>;
+}
@@ -0,0 +1,319 @@
+// Game Boy Printer
+// Packets look like this, with the printer answering 0x00 to every byte
+// apart from the last two:
+//   0x88 0x33 command compression length(LE u16) data... checksum(LE u16)
+//   0x00 (printer answers 0x81) 0x00 (printer answers with its status)
+// Protocol details from https://gbdev.io/pandocs/Gameboy_Printer.html
+use crate::constants::CLOCK_SPEED;
+use crate::link::SerialLink;
+
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, vec, vec::Vec};
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+const ALIVE: u8 = 0x81;
+
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+const COMMAND_STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM_ERROR: u8 = 0b0000_0001;
+const STATUS_PRINTING: u8 = 0b0000_0010;
+const STATUS_IMAGE_FULL: u8 = 0b0000_0100;
+const STATUS_UNPROCESSED_DATA: u8 = 0b0000_1000;
+
+pub const PRINTER_WIDTH: usize = 160;
+const TILES_PER_ROW: usize = PRINTER_WIDTH / 8;
+const TILE_BYTES: usize = 16;
+// The printer's RAM holds 9 bands of 2 tile rows each
+const IMAGE_BUFFER_SIZE: usize = 0x2280;
+
+// Real printers take a few seconds. Games only need to see the busy flag
+// for a while before they carry on.
+const PRINTING_CLOCKS: usize = CLOCK_SPEED / 2;
+
+// The printer's shades, from palette index 0 (paper) to 3 (black ink)
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+pub struct Printout {
+    pub width: usize,
+    pub height: usize,
+    // 4 bytes per pixel, row by row
+    pub rgba: Vec<u8>,
+    // Feed before and after the image, in units of 2 pixel rows (0-15)
+    pub margin_before: u8,
+    pub margin_after: u8,
+}
+
+pub type PrintCallback = Box<dyn FnMut(Printout)>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum PacketPosition {
+    Magic(usize),
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+pub struct GameboyPrinter {
+    on_print: PrintCallback,
+
+    position: PacketPosition,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    calculated_checksum: u16,
+
+    // Decompressed tile data waiting to be printed
+    image: Vec<u8>,
+    status: u8,
+    printing_clocks_left: usize,
+}
+
+impl GameboyPrinter {
+    // Works out what to send back for the byte that's being received
+    fn receive(&mut self, byte: u8) -> u8 {
+        let position = self.position;
+        let mut response = 0x00;
+
+        self.position = match position {
+            PacketPosition::Magic(i) => {
+                if byte != MAGIC[i] {
+                    PacketPosition::Magic(0)
+                } else if i + 1 < MAGIC.len() {
+                    PacketPosition::Magic(i + 1)
+                } else {
+                    self.calculated_checksum = 0;
+                    PacketPosition::Command
+                }
+            },
+            PacketPosition::Command => {
+                self.command = byte;
+                PacketPosition::Compression
+            },
+            PacketPosition::Compression => {
+                self.compressed = byte & 1 != 0;
+                PacketPosition::LengthLow
+            },
+            PacketPosition::LengthLow => {
+                self.length = byte as usize;
+                PacketPosition::LengthHigh
+            },
+            PacketPosition::LengthHigh => {
+                self.length |= (byte as usize) << 8;
+                self.data.clear();
+                match self.length {
+                    0 => PacketPosition::ChecksumLow,
+                    _ => PacketPosition::Data,
+                }
+            },
+            PacketPosition::Data => {
+                self.data.push(byte);
+                match self.data.len() == self.length {
+                    true => PacketPosition::ChecksumLow,
+                    false => PacketPosition::Data,
+                }
+            },
+            PacketPosition::ChecksumLow => {
+                self.checksum = byte as u16;
+                PacketPosition::ChecksumHigh
+            },
+            PacketPosition::ChecksumHigh => {
+                self.checksum |= (byte as u16) << 8;
+                if self.checksum == self.calculated_checksum {
+                    self.status &= !STATUS_CHECKSUM_ERROR;
+                    self.run_command();
+                } else {
+                    self.status |= STATUS_CHECKSUM_ERROR;
+                }
+                PacketPosition::Alive
+            },
+            PacketPosition::Alive => {
+                response = ALIVE;
+                PacketPosition::Status
+            },
+            PacketPosition::Status => {
+                response = self.status;
+                PacketPosition::Magic(0)
+            },
+        };
+
+        // The checksum covers everything from the command to the data
+        match position {
+            PacketPosition::Command
+            | PacketPosition::Compression
+            | PacketPosition::LengthLow
+            | PacketPosition::LengthHigh
+            | PacketPosition::Data => {
+                self.calculated_checksum =
+                    self.calculated_checksum.wrapping_add(byte as u16)
+            },
+            _ => {},
+        }
+
+        response
+    }
+
+    fn run_command(&mut self) {
+        match self.command {
+            COMMAND_INIT => {
+                self.image.clear();
+                self.status = 0;
+                self.printing_clocks_left = 0;
+            },
+            COMMAND_DATA => {
+                let data = match self.compressed {
+                    true => decompress(&self.data),
+                    false => self.data.clone(),
+                };
+                let space = IMAGE_BUFFER_SIZE - self.image.len();
+                self.image.extend_from_slice(&data[..data.len().min(space)]);
+
+                if !self.image.is_empty() {
+                    self.status |= STATUS_UNPROCESSED_DATA;
+                }
+                if self.image.len() == IMAGE_BUFFER_SIZE {
+                    self.status |= STATUS_IMAGE_FULL;
+                }
+            },
+            COMMAND_PRINT => {
+                if self.data.len() < 4 {
+                    return;
+                }
+                let margins = self.data[1];
+                let palette = self.data[2];
+
+                if !self.image.is_empty() {
+                    let printout = self.render(palette, margins);
+                    (self.on_print)(printout);
+                }
+
+                self.image.clear();
+                self.status &= !STATUS_UNPROCESSED_DATA;
+                self.status |= STATUS_PRINTING | STATUS_IMAGE_FULL;
+                self.printing_clocks_left = PRINTING_CLOCKS;
+            },
+            COMMAND_STATUS => {},
+            _ => {},
+        }
+    }
+
+    fn render(&self, palette: u8, margins: u8) -> Printout {
+        // Some games send a palette of 0, which printers treat as the
+        // standard one
+        let palette = match palette {
+            0 => 0b11_10_01_00,
+            _ => palette,
+        };
+
+        let tile_rows = self.image.len() / (TILES_PER_ROW * TILE_BYTES);
+        let height = tile_rows * 8;
+        let mut rgba = vec![0; PRINTER_WIDTH * height * 4];
+
+        for tile_index in 0..tile_rows * TILES_PER_ROW {
+            let tile = &self.image
+                [tile_index * TILE_BYTES..(tile_index + 1) * TILE_BYTES];
+            let tile_x = (tile_index % TILES_PER_ROW) * 8;
+            let tile_y = (tile_index / TILES_PER_ROW) * 8;
+
+            for row in 0..8 {
+                let low = tile[row * 2];
+                let high = tile[row * 2 + 1];
+                for column in 0..8 {
+                    let bit = 7 - column;
+                    let colour_index =
+                        ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                    let shade = (palette >> (colour_index * 2)) & 0b11;
+                    let value = SHADES[shade as usize];
+
+                    let i =
+                        ((tile_y + row) * PRINTER_WIDTH + tile_x + column) * 4;
+                    rgba[i..i + 4].copy_from_slice(&[value, value, value, 255]);
+                }
+            }
+        }
+
+        Printout {
+            width: PRINTER_WIDTH,
+            height,
+            rgba,
+            margin_before: margins >> 4,
+            margin_after: margins & 0x0F,
+        }
+    }
+
+    pub fn new(on_print: PrintCallback) -> GameboyPrinter {
+        GameboyPrinter {
+            on_print,
+
+            position: PacketPosition::Magic(0),
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            calculated_checksum: 0,
+
+            image: Vec::with_capacity(IMAGE_BUFFER_SIZE),
+            status: 0,
+            printing_clocks_left: 0,
+        }
+    }
+}
+
+// The printer's RLE: a control byte with the top bit set is followed by one
+// byte to repeat (control & 0x7F) + 2 times. Otherwise, the next
+// control + 1 bytes are copied as they are.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(IMAGE_BUFFER_SIZE);
+    let mut i = 0;
+
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+
+        if control & 0x80 != 0 {
+            let count = (control & 0x7F) as usize + 2;
+            if let Some(byte) = data.get(i) {
+                out.resize(out.len() + count, *byte);
+            }
+            i += 1;
+        } else {
+            let count = control as usize + 1;
+            let end = (i + count).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+
+    out
+}
+
+impl SerialLink for GameboyPrinter {
+    // The Gameboy always drives the printer's clock
+    fn set_ready(&mut self, _outgoing: Option<u8>) {}
+
+    fn exchange(&mut self, outgoing: u8) -> Option<u8> {
+        Some(self.receive(outgoing))
+    }
+
+    fn poll(&mut self, cycles: usize) -> Option<u8> {
+        if self.printing_clocks_left > 0 {
+            self.printing_clocks_left =
+                self.printing_clocks_left.saturating_sub(cycles);
+            if self.printing_clocks_left == 0 {
+                self.status &= !(STATUS_PRINTING | STATUS_IMAGE_FULL);
+            }
+        }
+        None
+    }
+}
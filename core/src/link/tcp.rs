@@ -0,0 +1,200 @@
+// Links two gbrs processes over TCP, eg. two windows on one machine talking
+// over localhost. One side listens and the other connects.
+//
+// The link runs in lockstep: a Gameboy that drives a transfer waits for the
+// other end to answer before carrying on, just like the real hardware can't
+// finish shifting a byte until the other end has shifted one back.
+use crate::link::SerialLink;
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Every message is a kind byte followed by a data byte
+const MESSAGE_TRANSFER: u8 = 0;
+const MESSAGE_REPLY: u8 = 1;
+const MESSAGE_NO_REPLY: u8 = 2;
+
+// Checking the socket is slow compared to a CPU step, so incoming transfers
+// are only looked for this often. A normal speed transfer takes 4096 clocks.
+const POLL_INTERVAL_CLOCKS: usize = 256;
+
+// If the other end takes longer than this to answer a transfer, it's treated
+// as if the cable was pulled out
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct TcpLink {
+    // None once the other end has gone away
+    stream: Option<TcpStream>,
+    ready: Option<u8>,
+    clocks_since_poll: usize,
+    // Bytes of a message that hasn't fully arrived yet
+    partial_message: Vec<u8>,
+}
+
+impl TcpLink {
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn send(&mut self, message: [u8; 2]) {
+        let sent = match self.stream.as_mut() {
+            Some(stream) => write_message(stream, message),
+            None => return,
+        };
+        if sent.is_err() {
+            self.stream = None;
+        }
+    }
+
+    // Returns None if no message is waiting, or if the connection dropped.
+    // With `blocking`, waits up to REPLY_TIMEOUT for one.
+    fn read_message(&mut self, blocking: bool) -> Option<[u8; 2]> {
+        let stream = self.stream.as_mut()?;
+        let deadline = match blocking {
+            true => Some(Instant::now() + REPLY_TIMEOUT),
+            false => None,
+        };
+        match read_message(stream, &mut self.partial_message, deadline) {
+            Ok(message) => message,
+            Err(_) => {
+                self.stream = None;
+                None
+            },
+        }
+    }
+
+    fn answer_transfer(&mut self, incoming: u8) -> Option<u8> {
+        match self.ready.take() {
+            Some(outgoing) => {
+                self.send([MESSAGE_REPLY, outgoing]);
+                Some(incoming)
+            },
+            None => {
+                self.send([MESSAGE_NO_REPLY, 0]);
+                None
+            },
+        }
+    }
+
+    // Waits for the other gbrs to connect
+    pub fn listen<A: ToSocketAddrs>(address: A) -> io::Result<TcpLink> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+        TcpLink::from_stream(stream)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<TcpLink> {
+        TcpLink::from_stream(TcpStream::connect(address)?)
+    }
+
+    pub fn from_stream(stream: TcpStream) -> io::Result<TcpLink> {
+        // Every transfer is a round trip, so don't let Nagle batch them up
+        stream.set_nodelay(true)?;
+        // Polling for transfers mustn't hold up the emulator. Waiting for a
+        // reply is done by retrying until REPLY_TIMEOUT.
+        stream.set_nonblocking(true)?;
+
+        Ok(TcpLink {
+            stream: Some(stream),
+            ready: None,
+            clocks_since_poll: 0,
+            partial_message: Vec::with_capacity(2),
+        })
+    }
+}
+
+fn write_message(stream: &mut TcpStream, message: [u8; 2]) -> io::Result<()> {
+    let mut written = 0;
+    while written < message.len() {
+        match stream.write(&message[written..]) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(count) => written += count,
+            Err(err)
+                if err.kind() == ErrorKind::Interrupted
+                    || err.kind() == ErrorKind::WouldBlock =>
+            {
+                thread::yield_now()
+            },
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+// Without a deadline, gives None if a whole message hasn't arrived yet
+fn read_message(
+    stream: &mut TcpStream,
+    partial_message: &mut Vec<u8>,
+    deadline: Option<Instant>,
+) -> io::Result<Option<[u8; 2]>> {
+    while partial_message.len() < 2 {
+        let mut byte = [0];
+        match stream.read(&mut byte) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => partial_message.push(byte[0]),
+            Err(err) if err.kind() == ErrorKind::Interrupted => {},
+            Err(err) if err.kind() == ErrorKind::WouldBlock => match deadline {
+                None => return Ok(None),
+                // Timing out is handled as a disconnection
+                Some(deadline) if Instant::now() >= deadline => {
+                    return Err(ErrorKind::TimedOut.into())
+                },
+                Some(_) => thread::yield_now(),
+            },
+            Err(err) => return Err(err),
+        }
+    }
+
+    let message = [partial_message[0], partial_message[1]];
+    partial_message.clear();
+    Ok(Some(message))
+}
+
+impl SerialLink for TcpLink {
+    fn set_ready(&mut self, outgoing: Option<u8>) {
+        self.ready = outgoing;
+    }
+
+    fn exchange(&mut self, outgoing: u8) -> Option<u8> {
+        self.send([MESSAGE_TRANSFER, outgoing]);
+
+        loop {
+            let [kind, byte] = self.read_message(true)?;
+            match kind {
+                MESSAGE_REPLY => return Some(byte),
+                MESSAGE_NO_REPLY => return None,
+                // Both ends started a transfer with their own clock at the
+                // same time. Neither is listening, so neither gets anything.
+                MESSAGE_TRANSFER => self.send([MESSAGE_NO_REPLY, 0]),
+                _ => {
+                    self.stream = None;
+                    return None;
+                },
+            }
+        }
+    }
+
+    fn poll(&mut self, cycles: usize) -> Option<u8> {
+        self.clocks_since_poll += cycles;
+        if self.clocks_since_poll < POLL_INTERVAL_CLOCKS {
+            return None;
+        }
+        self.clocks_since_poll = 0;
+
+        while let Some([kind, byte]) = self.read_message(false) {
+            // Replies only come back to exchange, which waits for them (and
+            // drops the connection if they take too long). Anything else
+            // here means the two ends have got out of step.
+            if kind != MESSAGE_TRANSFER {
+                self.stream = None;
+                return None;
+            }
+            if let Some(incoming) = self.answer_transfer(byte) {
+                return Some(incoming);
+            }
+        }
+        None
+    }
+}
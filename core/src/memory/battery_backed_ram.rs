@@ -1,16 +1,43 @@
 // RAM with a save file
-use crate::{callbacks::CALLBACKS, cartridge::Cartridge, memory::ram::Ram};
+// Cartridges with a clock keep it in here too, as the clock is also powered
+// by the battery and saved alongside the RAM.
+use crate::memory::rtc::{Rtc, RTC_SAVE_SIZE};
+use crate::state::{SaveState, StateReader, StateWriter};
+use crate::{cartridge::Cartridge, host::SharedHost, memory::ram::Ram};
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
 
 // The amount of milliseconds we wait before saving our save file
 // (otherwise eg. Link's Awakening would write 2,700 save files
 //  on its first frame)
 const DEBOUNCE_MILLIS: usize = 1000;
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SaveFormat {
+    // The RAM, then the clock for cartridges that have one
+    Sav,
+    // Just the RAM, as used by libretro frontends
+    Srm,
+}
+
+impl SaveFormat {
+    pub fn from_path(path: &str) -> SaveFormat {
+        match path.to_lowercase().ends_with(".srm") {
+            true => SaveFormat::Srm,
+            false => SaveFormat::Sav,
+        }
+    }
+}
+
 pub struct BatteryBackedRam {
     pub ram: Ram,
     pub size: usize,
+    pub rtc: Option<Rtc>,
 
     cart: Cartridge,
+    // MBCs log through this too
+    pub host: SharedHost,
 
     battery_enabled: bool,
     changed_since_last_save: bool,
@@ -36,7 +63,18 @@ impl BatteryBackedRam {
         self.changed_since_last_save = true;
     }
 
+    pub fn write_rtc(&mut self, register: usize, value: u8) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.write(register, value);
+            self.changed_since_last_save = true;
+        }
+    }
+
     pub fn step(&mut self, ms_since_boot: usize) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.step(ms_since_boot);
+        }
+
         if !self.changed_since_last_save || !self.battery_enabled {
             return;
         }
@@ -49,42 +87,118 @@ impl BatteryBackedRam {
         }
     }
 
+    pub fn has_battery(&self) -> bool {
+        self.battery_enabled
+    }
+
+    // Saves straight away rather than waiting for the debounce. Clocks are
+    // always saved so that the next load knows when the game was closed.
+    pub fn flush(&mut self) {
+        if self.battery_enabled
+            && (self.changed_since_last_save || self.rtc.is_some())
+        {
+            self.save_ram_contents();
+        }
+    }
+
     fn save_ram_contents(&mut self) {
         self.changed_since_last_save = false;
 
-        (CALLBACKS.lock().save)(
+        let save_data = self.save_data(SaveFormat::Sav);
+        self.host.borrow_mut().save(
             &self.cart.title[..],
             &self.cart.rom_path[..],
-            &self.ram.bytes,
+            &save_data,
         );
     }
 
+    pub fn save_data(&self, format: SaveFormat) -> Vec<u8> {
+        let mut bytes = self.ram.bytes.clone();
+        if let (SaveFormat::Sav, Some(rtc)) = (format, self.rtc.as_ref()) {
+            let now = self.host.borrow_mut().now();
+            bytes.extend_from_slice(&rtc.save_data(now));
+        }
+        bytes
+    }
+
+    // Takes either format. Short saves are padded with zeroes, and anything
+    // past the RAM that isn't a clock is ignored.
+    pub fn load_save_data(&mut self, bytes: &[u8]) {
+        let ram_bytes = bytes.len().min(self.size);
+        self.ram.bytes.fill(0);
+        self.ram.bytes[..ram_bytes].copy_from_slice(&bytes[..ram_bytes]);
+
+        if let Some(rtc) = self.rtc.as_mut() {
+            let now = self.host.borrow_mut().now();
+            rtc.load_save_data(&bytes[ram_bytes..], now);
+        }
+
+        // Make sure it ends up wherever the host keeps saves
+        self.changed_since_last_save = true;
+    }
+
     pub fn new(
         cart: Cartridge,
         additional_ram_size: usize,
         battery_enabled: bool,
+        has_rtc: bool,
+        host: SharedHost,
     ) -> BatteryBackedRam {
         // Some MBCs, like MBC2, always have a few bytes of RAM installed.
         // The cartridge header only tells us about additional external RAM.
         let ram_size = cart.ram_size + additional_ram_size;
+        let save_size = match has_rtc {
+            true => ram_size + RTC_SAVE_SIZE,
+            false => ram_size,
+        };
 
-        let save_contents = (CALLBACKS.lock().load)(
+        let save_contents = host.borrow_mut().load(
             &cart.title[..],
             &cart.rom_path[..],
-            ram_size,
+            save_size,
         );
 
-        let ram = Ram::from_bytes(save_contents, ram_size);
-
-        BatteryBackedRam {
-            ram,
+        let mut battery_backed_ram = BatteryBackedRam {
+            ram: Ram::new(ram_size),
             size: ram_size,
+            rtc: match has_rtc {
+                true => Some(Rtc::new()),
+                false => None,
+            },
 
             cart,
+            host,
             battery_enabled,
             changed_since_last_save: false,
 
             last_saved_at: 0,
+        };
+        battery_backed_ram.load_save_data(&save_contents);
+        battery_backed_ram.changed_since_last_save = false;
+        battery_backed_ram
+    }
+}
+
+// Whatever hasn't been saved yet would otherwise be lost when the game is
+// closed
+impl Drop for BatteryBackedRam {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl SaveState for BatteryBackedRam {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.ram.save_state(state);
+        if let Some(rtc) = self.rtc.as_ref() {
+            rtc.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.ram.load_state(state);
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.load_state(state);
         }
     }
 }
@@ -0,0 +1,94 @@
+// The little program a real Gameboy runs at power on, which scrolls the logo
+// down and then unmaps itself by writing to 0xFF50. gbrs starts games with
+// the registers as the boot ROM would leave them, so this is optional, and
+// users have to dump their own.
+use core::fmt;
+
+#[cfg(feature = "std")]
+use std::{fs, io};
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+// DMG and SGB
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+// The CGB boot ROM is split around the cartridge header at 0x100-0x1FF
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+const CARTRIDGE_HEADER_START: u16 = 0x100;
+const CARTRIDGE_HEADER_END: u16 = 0x1FF;
+
+#[derive(Debug)]
+pub enum BootRomError {
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    // Not the size of any boot ROM
+    WrongSize(usize),
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            BootRomError::Io(err) => write!(f, "{}", err),
+            BootRomError::WrongSize(size) => write!(
+                f,
+                "boot ROMs are {} bytes (DMG/SGB) or {} bytes (CGB), not {}",
+                DMG_BOOT_ROM_SIZE, CGB_BOOT_ROM_SIZE, size
+            ),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BootRom {
+    bytes: Vec<u8>,
+    // Covers the start of the cartridge until the boot ROM is finished
+    pub mapped: bool,
+}
+
+impl BootRom {
+    // None where the cartridge shows through
+    #[inline(always)]
+    pub fn read(&self, address: u16) -> Option<u8> {
+        if !self.mapped
+            || (CARTRIDGE_HEADER_START..=CARTRIDGE_HEADER_END)
+                .contains(&address)
+        {
+            return None;
+        }
+        self.bytes.get(address as usize).copied()
+    }
+
+    // The CGB boot ROM picks between CGB and DMG compatibility mode
+    pub fn is_cgb(&self) -> bool {
+        self.bytes.len() == CGB_BOOT_ROM_SIZE
+    }
+
+    #[cfg(feature = "std")]
+    pub fn try_from_file(path: &str) -> Result<BootRom, BootRomError> {
+        BootRom::try_from_bytes(fs::read(path).map_err(BootRomError::Io)?)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn from_file(path: &str) -> BootRom {
+        BootRom::try_from_file(path).unwrap_or_else(|err| {
+            panic!("Unable to load boot ROM {}: {}", path, err)
+        })
+    }
+
+    pub fn try_from_bytes(bytes: Vec<u8>) -> Result<BootRom, BootRomError> {
+        if bytes.len() != DMG_BOOT_ROM_SIZE && bytes.len() != CGB_BOOT_ROM_SIZE
+        {
+            return Err(BootRomError::WrongSize(bytes.len()));
+        }
+        Ok(BootRom {
+            bytes,
+            mapped: true,
+        })
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> BootRom {
+        BootRom::try_from_bytes(bytes)
+            .unwrap_or_else(|err| panic!("Invalid boot ROM: {}", err))
+    }
+}
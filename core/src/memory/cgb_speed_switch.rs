@@ -1,13 +1,18 @@
-use crate::log;
+use crate::constants::SPEED_SWITCH_HALT_CYCLES;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 pub struct CgbSpeedSwitch {
     pub armed: bool,
     pub current_speed_is_double: bool,
-    cgb_features: bool,
+    pub cgb_features: bool,
+    // Set by a speed switch. The CPU and timers stop for this many (single
+    // speed) clocks while the rest of the Gameboy carries on.
+    stall_cycles: usize,
 }
 
-// TODO: Actually act on this for CPU speed.
-//   This just tracks the byte state.
+// The CPU, timers and serial port run twice as fast in double speed mode,
+// everything else (GPU, APU, CGB DMA) keeps to the normal speed. Cpu::step
+// does the actual splitting of clocks.
 impl CgbSpeedSwitch {
     pub fn write_switch_byte(&mut self, value: u8) {
         if self.cgb_features {
@@ -28,13 +33,12 @@ impl CgbSpeedSwitch {
     pub fn execute_speed_switch(&mut self) {
         self.armed = false;
         self.current_speed_is_double = !self.current_speed_is_double;
-        log!(
-            "Performing CGB speed switch. New speed: {}",
-            match self.current_speed_is_double {
-                true => "Double",
-                false => "Single",
-            }
-        );
+        self.stall_cycles = SPEED_SWITCH_HALT_CYCLES;
+    }
+
+    // Hands the speed switch pause over to the CPU
+    pub fn take_stall_cycles(&mut self) -> usize {
+        core::mem::take(&mut self.stall_cycles)
     }
 
     pub fn new(cgb_features: bool) -> Self {
@@ -42,6 +46,21 @@ impl CgbSpeedSwitch {
             armed: false,
             current_speed_is_double: false,
             cgb_features,
+            stall_cycles: 0,
         }
     }
 }
+
+// The stall is always used up by the Cpu::step that switched speeds, so it
+// doesn't need saving
+impl SaveState for CgbSpeedSwitch {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.armed);
+        state.write_bool(self.current_speed_is_double);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.armed = state.read_bool();
+        self.current_speed_is_double = state.read_bool();
+    }
+}
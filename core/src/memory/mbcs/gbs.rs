@@ -0,0 +1,75 @@
+use crate::memory::mbcs::MBC;
+use crate::memory::ram::Ram;
+use crate::memory::rom::Rom;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+// 16KB (one bank size) in bytes
+const KB_16: usize = 16_384;
+const RAM_SIZE: usize = 8_192;
+
+// GBS rips don't come with a real cartridge, so they get this minimal mapper
+// instead. Banks are switched by writing to 0x2000-0x3FFF like on an MBC1,
+// and there's always 8KB of RAM at 0xA000.
+pub struct GbsMapper {
+    pub rom: Rom,
+    pub rom_bank: usize,
+    pub ram: Ram,
+}
+
+impl MBC for GbsMapper {
+    fn read(&self, address: u16) -> u8 {
+        let rom_address = match address {
+            0x0..=0x3FFF => address as usize,
+            _ => self.rom_bank * KB_16 + (address as usize - 0x4000),
+        };
+
+        // Rips are rarely a whole number of banks long
+        match self.rom.bytes.get(rom_address) {
+            Some(value) => *value,
+            None => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if let 0x2000..=0x3FFF = address {
+            self.rom_bank = match value {
+                0 => 1,
+                n => n as usize,
+            };
+        }
+    }
+
+    fn ram_read(&self, address: u16) -> u8 {
+        self.ram.read(address)
+    }
+
+    fn ram_write(&mut self, address: u16, value: u8) {
+        self.ram.write(address, value)
+    }
+
+    fn step(&mut self, _ms_since_boot: usize) {
+        // Nothing is battery-backed
+    }
+}
+
+impl GbsMapper {
+    pub fn new(rom: Rom) -> Self {
+        GbsMapper {
+            rom,
+            rom_bank: 1,
+            ram: Ram::new(RAM_SIZE),
+        }
+    }
+}
+
+impl SaveState for GbsMapper {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.rom_bank);
+        self.ram.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.rom_bank = state.read_usize();
+        self.ram.load_state(state);
+    }
+}
@@ -1,8 +1,10 @@
 use crate::cartridge::Cartridge;
+use crate::host::SharedHost;
 use crate::log;
 use crate::memory::battery_backed_ram::BatteryBackedRam;
 use crate::memory::mbcs::MBC;
 use crate::memory::rom::Rom;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 // 16KB (one bank size) in bytes
 pub const KB_16: usize = 16_384;
@@ -52,7 +54,7 @@ impl MBC for MBC1 {
 
     fn ram_read(&self, address: u16) -> u8 {
         if !self.ram_enabled && !self.has_shown_ram_warning {
-            log!("[WARN] MBC1 RAM read while disabled");
+            log!(self.ram.host, "[WARN] MBC1 RAM read while disabled");
         }
 
         // When an address outside of RAM space is read, the gameboy
@@ -68,7 +70,7 @@ impl MBC for MBC1 {
 
     fn ram_write(&mut self, address: u16, value: u8) {
         if !self.ram_enabled && !self.has_shown_ram_warning {
-            log!("[WARN] MBC1 RAM write while disabled");
+            log!(self.ram.host, "[WARN] MBC1 RAM write while disabled");
             // Otherwise the game is slowed down by constant debug printing
             self.has_shown_ram_warning = true;
         }
@@ -84,6 +86,13 @@ impl MBC for MBC1 {
     fn step(&mut self, ms_since_boot: usize) {
         self.ram.step(ms_since_boot)
     }
+
+    fn battery(&self) -> Option<&BatteryBackedRam> {
+        Some(&self.ram)
+    }
+    fn battery_mut(&mut self) -> Option<&mut BatteryBackedRam> {
+        Some(&mut self.ram)
+    }
 }
 
 impl MBC1 {
@@ -93,7 +102,7 @@ impl MBC1 {
         self.rom.bytes[KB_16 * ub + ua]
     }
 
-    pub fn new(cart_info: Cartridge, rom: Rom) -> Self {
+    pub fn new(cart_info: Cartridge, rom: Rom, host: SharedHost) -> Self {
         // TODO: Banked RAM
         if cart_info.ram_size > 8_192 {
             panic!("gbrs doesn't support banked (>=32K) MBC1 RAM");
@@ -104,8 +113,22 @@ impl MBC1 {
             rom,
             ram_enabled: false,
             rom_bank: 1,
-            ram: BatteryBackedRam::new(cart_info, 0, has_battery),
+            ram: BatteryBackedRam::new(cart_info, 0, has_battery, false, host),
             has_shown_ram_warning: false,
         }
     }
 }
+
+impl SaveState for MBC1 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.rom_bank);
+        self.ram.save_state(state);
+        state.write_bool(self.ram_enabled);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.rom_bank = state.read_u8();
+        self.ram.load_state(state);
+        self.ram_enabled = state.read_bool();
+    }
+}
@@ -1,8 +1,10 @@
 use crate::cartridge::Cartridge;
+use crate::host::SharedHost;
 use crate::log;
 use crate::memory::battery_backed_ram::BatteryBackedRam;
 use crate::memory::mbcs::MBC;
 use crate::memory::rom::Rom;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 // 16KB (one bank size) in bytes
 pub const KB_16: usize = 16_384;
@@ -44,7 +46,7 @@ impl MBC for MBC2 {
 
     fn ram_read(&self, address: u16) -> u8 {
         if !self.ram_enabled && !self.has_shown_ram_warning {
-            log!("[WARN] MBC2 RAM read while disabled");
+            log!(self.ram.host, "[WARN] MBC2 RAM read while disabled");
         }
 
         // When an address outside of RAM space is read, the gameboy
@@ -60,7 +62,7 @@ impl MBC for MBC2 {
 
     fn ram_write(&mut self, address: u16, value: u8) {
         if !self.ram_enabled && !self.has_shown_ram_warning {
-            log!("[WARN] MBC2 RAM write while disabled");
+            log!(self.ram.host, "[WARN] MBC2 RAM write while disabled");
             // Otherwise the game is slowed down by constant debug printing
             self.has_shown_ram_warning = true;
         }
@@ -79,6 +81,13 @@ impl MBC for MBC2 {
     fn step(&mut self, ms_since_boot: usize) {
         self.ram.step(ms_since_boot)
     }
+
+    fn battery(&self) -> Option<&BatteryBackedRam> {
+        Some(&self.ram)
+    }
+    fn battery_mut(&mut self) -> Option<&mut BatteryBackedRam> {
+        Some(&mut self.ram)
+    }
 }
 
 impl MBC2 {
@@ -88,15 +97,35 @@ impl MBC2 {
         self.rom.bytes[KB_16 * ub + ua]
     }
 
-    pub fn new(cart_info: Cartridge, rom: Rom) -> Self {
+    pub fn new(cart_info: Cartridge, rom: Rom, host: SharedHost) -> Self {
         let has_battery = cart_info.cart_type == 0x06;
         MBC2 {
             rom,
             ram_enabled: false,
             rom_bank: 1,
             // The MBC2 always has 512 (half-)bytes of RAM built-in
-            ram: BatteryBackedRam::new(cart_info, 512, has_battery),
+            ram: BatteryBackedRam::new(
+                cart_info,
+                512,
+                has_battery,
+                false,
+                host,
+            ),
             has_shown_ram_warning: false,
         }
     }
 }
+
+impl SaveState for MBC2 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.rom_bank);
+        self.ram.save_state(state);
+        state.write_bool(self.ram_enabled);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.rom_bank = state.read_u8();
+        self.ram.load_state(state);
+        self.ram_enabled = state.read_bool();
+    }
+}
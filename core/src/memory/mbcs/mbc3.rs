@@ -1,8 +1,10 @@
 use crate::cartridge::Cartridge;
+use crate::host::SharedHost;
 use crate::log;
 use crate::memory::battery_backed_ram::BatteryBackedRam;
 use crate::memory::mbcs::MBC;
 use crate::memory::rom::Rom;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 // 8KB (one RAM bank size) in bytes
 pub const KB_8: usize = 8_192;
@@ -20,6 +22,8 @@ pub struct MBC3 {
     // Unique MBC3 feature, sometimes the RAM addresses can be set up to
     // read a Real Time Clock
     pub rtc_select: bool,
+    // 0-4, see Rtc
+    pub rtc_register: u8,
 
     has_shown_ram_warning: bool,
 }
@@ -56,16 +60,18 @@ impl MBC for MBC3 {
                         self.ram_bank = value;
                         self.rtc_select = false;
                     },
-                    // TODO: This maps Real Time Clock stuff
                     0x08..=0x0C => {
                         self.rtc_select = true;
+                        self.rtc_register = value - 0x08;
                     },
                     // This is a noop
                     _ => {},
                 }
             },
             0x6000..=0x7FFF => {
-                // TODO: RTC latching
+                if let Some(rtc) = self.ram.rtc.as_mut() {
+                    rtc.write_latch(value);
+                }
             },
             _ => {},
         }
@@ -73,14 +79,15 @@ impl MBC for MBC3 {
 
     fn ram_read(&self, address: u16) -> u8 {
         if !self.ram_enabled && !self.has_shown_ram_warning {
-            // log!("[WARN] MBC3 RAM read while disabled");
+            // log!(self.ram.host, "[WARN] MBC3 RAM read while disabled");
         }
 
         if self.rtc_select {
-            // The game has opted to replace RAM with the value of the RTC.
-            // TODO: Properly emulate the Real Time Clock
-            // log!("Reading the RTC");
-            return 0;
+            // The game has opted to replace RAM with the value of the RTC
+            return match self.ram.rtc.as_ref() {
+                Some(rtc) => rtc.read(self.rtc_register as usize),
+                None => 0xFF,
+            };
         }
 
         self.read_ram_bank(self.ram_bank, address)
@@ -88,17 +95,29 @@ impl MBC for MBC3 {
 
     fn ram_write(&mut self, address: u16, value: u8) {
         if !self.ram_enabled && !self.has_shown_ram_warning {
-            log!("[WARN] MBC3 RAM write while disabled");
+            log!(self.ram.host, "[WARN] MBC3 RAM write while disabled");
             // Otherwise the game is slowed down by constant debug printing
             self.has_shown_ram_warning = true;
         }
 
+        if self.rtc_select {
+            self.ram.write_rtc(self.rtc_register as usize, value);
+            return;
+        }
+
         self.write_ram_bank(self.ram_bank, address, value);
     }
 
     fn step(&mut self, ms_since_boot: usize) {
         self.ram.step(ms_since_boot)
     }
+
+    fn battery(&self) -> Option<&BatteryBackedRam> {
+        Some(&self.ram)
+    }
+    fn battery_mut(&mut self) -> Option<&mut BatteryBackedRam> {
+        Some(&mut self.ram)
+    }
 }
 
 impl MBC3 {
@@ -119,9 +138,10 @@ impl MBC3 {
         let ua = address as usize;
         let final_addr = KB_8 * ub + ua;
 
-        // if final_addr >= self.ram.size {
-        //     return 0xFF;
-        // }
+        // Clock-only cartridges don't have any RAM at all
+        if final_addr >= self.ram.size {
+            return 0xFF;
+        }
 
         self.ram.ram.bytes[final_addr]
     }
@@ -138,16 +158,44 @@ impl MBC3 {
         self.ram.write(final_addr as u16, value);
     }
 
-    pub fn new(cart_info: Cartridge, rom: Rom) -> Self {
-        let has_battery = cart_info.cart_type == 0x13;
+    pub fn new(cart_info: Cartridge, rom: Rom, host: SharedHost) -> Self {
+        let has_battery = matches!(cart_info.cart_type, 0x0F | 0x10 | 0x13);
+        let has_rtc = matches!(cart_info.cart_type, 0x0F | 0x10);
         MBC3 {
             rom,
             rom_bank: 1,
-            ram: BatteryBackedRam::new(cart_info, 0, has_battery),
+            ram: BatteryBackedRam::new(
+                cart_info,
+                0,
+                has_battery,
+                has_rtc,
+                host,
+            ),
             ram_bank: 0,
             ram_enabled: false,
             rtc_select: false,
+            rtc_register: 0,
             has_shown_ram_warning: false,
         }
     }
 }
+
+impl SaveState for MBC3 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.rom_bank);
+        self.ram.save_state(state);
+        state.write_u8(self.ram_bank);
+        state.write_bool(self.ram_enabled);
+        state.write_bool(self.rtc_select);
+        state.write_u8(self.rtc_register);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.rom_bank = state.read_u8();
+        self.ram.load_state(state);
+        self.ram_bank = state.read_u8();
+        self.ram_enabled = state.read_bool();
+        self.rtc_select = state.read_bool();
+        self.rtc_register = state.read_u8() % 5;
+    }
+}
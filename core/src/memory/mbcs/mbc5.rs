@@ -1,8 +1,10 @@
 use crate::cartridge::Cartridge;
+use crate::host::SharedHost;
 use crate::log;
 use crate::memory::battery_backed_ram::BatteryBackedRam;
 use crate::memory::mbcs::MBC;
 use crate::memory::rom::Rom;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 // 8KB (one RAM bank) in bytes
 pub const KB_8: usize = 8_192;
@@ -17,6 +19,10 @@ pub struct MBC5 {
     pub ram_enabled: bool,
     pub ram_bank: u8,
 
+    // Rumble cartridges use bit 3 of the RAM bank number to drive the motor
+    has_rumble: bool,
+    rumble_on: bool,
+
     has_shown_ram_warning: bool,
 }
 
@@ -47,12 +53,15 @@ impl MBC for MBC5 {
                     (self.rom_bank & 0b0000_0000_1111_1111) | (bit << 8);
             },
             0x4000..=0x5FFF => {
-                // TODO: Rumble. If the MBC has rumble circuitry, this may be
-                //   wrong because we pass on bit 3.
                 if value > 0x0F {
                     return;
                 }
-                self.ram_bank = value;
+                if self.has_rumble {
+                    self.set_rumble(value & 0b1000 != 0);
+                    self.ram_bank = value & 0b0111;
+                } else {
+                    self.ram_bank = value;
+                }
             },
             _ => {},
         }
@@ -60,7 +69,7 @@ impl MBC for MBC5 {
 
     fn ram_read(&self, address: u16) -> u8 {
         if !self.ram_enabled && !self.has_shown_ram_warning {
-            log!("[WARN] MBC5 RAM read while disabled");
+            log!(self.ram.host, "[WARN] MBC5 RAM read while disabled");
         }
 
         let banked_address = address as usize + self.ram_bank as usize * KB_8;
@@ -74,7 +83,7 @@ impl MBC for MBC5 {
 
     fn ram_write(&mut self, address: u16, value: u8) {
         if !self.ram_enabled && !self.has_shown_ram_warning {
-            log!("[WARN] MBC5 RAM write while disabled");
+            log!(self.ram.host, "[WARN] MBC5 RAM write while disabled");
             // Otherwise the game is slowed down by constant debug printing
             self.has_shown_ram_warning = true;
         }
@@ -91,6 +100,13 @@ impl MBC for MBC5 {
     fn step(&mut self, ms_since_boot: usize) {
         self.ram.step(ms_since_boot)
     }
+
+    fn battery(&self) -> Option<&BatteryBackedRam> {
+        Some(&self.ram)
+    }
+    fn battery_mut(&mut self) -> Option<&mut BatteryBackedRam> {
+        Some(&mut self.ram)
+    }
 }
 
 impl MBC5 {
@@ -100,20 +116,48 @@ impl MBC5 {
         self.rom.bytes[KB_16 * ub + ua]
     }
 
-    pub fn new(cart_info: Cartridge, rom: Rom) -> Self {
+    fn set_rumble(&mut self, on: bool) {
+        if on != self.rumble_on {
+            self.rumble_on = on;
+            self.ram.host.borrow_mut().set_rumble(on);
+        }
+    }
+
+    pub fn new(cart_info: Cartridge, rom: Rom, host: SharedHost) -> Self {
         // TODO: Banked RAM
         if cart_info.ram_size > 8_192 {
             panic!("gbrs doesn't support banked (>=32K) MBC5 RAM");
         }
 
-        let has_battery = cart_info.cart_type == 0x03;
+        let has_battery = matches!(cart_info.cart_type, 0x1B | 0x1E);
+        let has_rumble = (0x1C..=0x1E).contains(&cart_info.cart_type);
         MBC5 {
             rom,
             rom_bank: 1,
-            ram: BatteryBackedRam::new(cart_info, 0, has_battery),
+            ram: BatteryBackedRam::new(cart_info, 0, has_battery, false, host),
             ram_enabled: false,
             ram_bank: 0,
+            has_rumble,
+            rumble_on: false,
             has_shown_ram_warning: false,
         }
     }
 }
+
+impl SaveState for MBC5 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.rom_bank);
+        self.ram.save_state(state);
+        state.write_bool(self.ram_enabled);
+        state.write_u8(self.ram_bank);
+        state.write_bool(self.rumble_on);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.rom_bank = state.read_u16();
+        self.ram.load_state(state);
+        self.ram_enabled = state.read_bool();
+        self.ram_bank = state.read_u8();
+        self.set_rumble(state.read_bool());
+    }
+}
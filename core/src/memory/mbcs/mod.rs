@@ -1,11 +1,15 @@
 use crate::cartridge::Cartridge;
+use crate::host::SharedHost;
 use crate::log;
+use crate::memory::battery_backed_ram::BatteryBackedRam;
 use crate::memory::rom::Rom;
+use crate::state::SaveState;
 
 #[cfg(not(feature = "std"))]
 use alloc::boxed::Box;
 
-pub trait MBC {
+// Save states include the MBC's bank registers and cartridge RAM
+pub trait MBC: SaveState {
     fn read(&self, address: u16) -> u8;
     fn write(&mut self, address: u16, value: u8);
 
@@ -14,26 +18,39 @@ pub trait MBC {
 
     // Mostly used to debounce battery-backed RAM saves
     fn step(&mut self, ms_since_boot: usize);
+
+    // Cartridge RAM and the clock, for MBCs that have them
+    fn battery(&self) -> Option<&BatteryBackedRam> {
+        None
+    }
+    fn battery_mut(&mut self) -> Option<&mut BatteryBackedRam> {
+        None
+    }
 }
 
+pub mod gbs;
 mod mbc1;
 mod mbc2;
 mod mbc3;
 mod mbc5;
 mod none;
 
-pub fn mbc_from_info(cart_info: Cartridge, rom: Rom) -> Box<dyn MBC> {
-    log!("Loading game \"{}\"", cart_info.title);
-    log!("Extra chips: {}", get_cart_type_string(&cart_info));
-    log!("ROM size: {}KB", cart_info.rom_size / 1024);
-    log!("RAM size: {}KB", cart_info.ram_size / 1024);
+pub fn mbc_from_info(
+    cart_info: Cartridge,
+    rom: Rom,
+    host: SharedHost,
+) -> Box<dyn MBC> {
+    log!(host, "Loading game \"{}\"", cart_info.title);
+    log!(host, "Extra chips: {}", get_cart_type_string(&cart_info));
+    log!(host, "ROM size: {}KB", cart_info.rom_size / 1024);
+    log!(host, "RAM size: {}KB", cart_info.ram_size / 1024);
 
     match cart_info.cart_type {
         0x00 => Box::new(none::MBCNone::new(rom)),
-        0x01 ..= 0x03 => Box::new(mbc1::MBC1::new(cart_info, rom)),
-        0x05 ..= 0x06 => Box::new(mbc2::MBC2::new(cart_info, rom)),
-        0x0F ..= 0x13 => Box::new(mbc3::MBC3::new(cart_info, rom)),
-        0x19 ..= 0x1E => Box::new(mbc5::MBC5::new(cart_info, rom)),
+        0x01 ..= 0x03 => Box::new(mbc1::MBC1::new(cart_info, rom, host)),
+        0x05 ..= 0x06 => Box::new(mbc2::MBC2::new(cart_info, rom, host)),
+        0x0F ..= 0x13 => Box::new(mbc3::MBC3::new(cart_info, rom, host)),
+        0x19 ..= 0x1E => Box::new(mbc5::MBC5::new(cart_info, rom, host)),
         _ => panic!("gbrs doesn't support this cartridge's memory controller ({:#04x}).", cart_info.cart_type)
     }
 }
@@ -1,5 +1,6 @@
 use crate::memory::mbcs::MBC;
 use crate::memory::rom::Rom;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 pub struct MBCNone {
     pub rom: Rom,
@@ -33,3 +34,11 @@ impl MBCNone {
         MBCNone { rom }
     }
 }
+
+impl SaveState for MBCNone {
+    fn save_state(&self, _state: &mut StateWriter) {
+        // There's no state besides the ROM itself
+    }
+
+    fn load_state(&mut self, _state: &mut StateReader) {}
+}
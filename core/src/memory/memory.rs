@@ -1,29 +1,44 @@
 use crate::cartridge::Cartridge;
+use crate::cheats::{CheatCode, Cheats};
 use crate::colour::palette_ram::PaletteRam;
 use crate::constants::*;
 use crate::cpu::EmulationTarget;
 use crate::gpu::Gpu;
+use crate::host::SharedHost;
+use crate::infrared::Infrared;
 use crate::interrupts::*;
 use crate::joypad::Joypad;
 use crate::log;
+use crate::memory::battery_backed_ram::BatteryBackedRam;
+use crate::memory::boot_rom::BootRom;
 use crate::memory::cgb_speed_switch::CgbSpeedSwitch;
 use crate::memory::mbcs::*;
 use crate::memory::ram::Ram;
 use crate::memory::rom::Rom;
+use crate::memory::undocumented_registers::UndocumentedRegisters;
 use crate::memory::vram::VRam;
 use crate::serial_cable::SerialCable;
+use crate::sgb::Sgb;
 use crate::sound::apu::APU;
+use crate::state::{SaveState, StateReader, StateWriter};
 use crate::{combine_u8, split_u16};
 
 #[cfg(not(feature = "std"))]
 use alloc::boxed::Box;
 
+// KEY0's bits 2-3 are 0 for CGB mode, and anything else is a DMG mode
+const KEY0_MODE_BITS: u8 = 0b1100;
+
 // TODO: Rename this to something more appropriate
 //       (I've seen an emu call a similar struct 'Interconnect')
 pub struct Memory {
     cgb_features: bool,
+    cgb_hardware: bool,
 
     mbc: Box<dyn MBC>,
+    // Only when one was given in the Config
+    pub boot_rom: Option<BootRom>,
+    pub cheats: Cheats,
 
     // TODO: Move VRAM to GPU?
     pub vram: VRam,
@@ -35,7 +50,7 @@ pub struct Memory {
     // Used in CGB mode only
     pub palette_ram: PaletteRam,
 
-    serial_cable: SerialCable,
+    pub serial_cable: SerialCable,
 
     timer_divider_increase: u16,
     timer_divider: u8,
@@ -48,9 +63,22 @@ pub struct Memory {
     timer_control: u8,
 
     pub joypad: Joypad,
+    // Only when running as a Super Gameboy
+    pub sgb: Option<Sgb>,
 
     pub apu: APU,
     pub speed_switch: CgbSpeedSwitch,
+
+    // KEY0. The CGB boot ROM sets this to pick between CGB and DMG
+    // compatibility mode, then locks it by unmapping itself. Without a boot
+    // ROM, it's set from the emulation target and games can't change it.
+    pub key0: u8,
+    // Set when the boot ROM locks KEY0, until the Cpu switches modes
+    key0_locked: bool,
+    pub infrared: Infrared,
+    undocumented_registers: UndocumentedRegisters,
+
+    pub host: SharedHost,
 }
 
 impl Memory {
@@ -97,11 +125,55 @@ impl Memory {
         self.mbc.step(ms_since_boot);
     }
 
+    pub fn is_boot_rom_mapped(&self) -> bool {
+        self.boot_rom
+            .as_ref()
+            .is_some_and(|boot_rom| boot_rom.mapped)
+    }
+
+    // Once the boot ROM has locked KEY0, whether it picked CGB mode (rather
+    // than DMG compatibility mode). This is only given once.
+    pub fn take_key0_lock(&mut self) -> Option<bool> {
+        if !core::mem::take(&mut self.key0_locked) {
+            return None;
+        }
+        Some(self.key0 & KEY0_MODE_BITS == 0)
+    }
+
+    pub fn has_cgb_features(&self) -> bool {
+        self.cgb_features
+    }
+
+    // Switches between CGB mode and DMG compatibility mode. The Gpu has to be
+    // switched too.
+    pub fn set_cgb_features(&mut self, cgb_features: bool) {
+        self.cgb_features = cgb_features;
+        self.vram.cgb_features = cgb_features;
+        self.palette_ram.cgb_features = cgb_features;
+        self.serial_cable.cgb_features = cgb_features;
+        self.speed_switch.cgb_features = cgb_features;
+        self.infrared.cgb_features = cgb_features;
+        self.undocumented_registers.cgb_features = cgb_features;
+        // The banking registers stop working in DMG compatibility mode
+        if !cgb_features {
+            self.vram.bank = 0;
+            self.upper_wram_bank = 1;
+        }
+    }
+
     #[inline(always)]
     pub fn read(&self, ints: &Interrupts, gpu: &Gpu, address: u16) -> u8 {
         match address {
-            // Cartridge memory starts at the 0 address
-            0..=MBC_ROM_END => self.mbc.read(address),
+            // Cartridge memory starts at the 0 address, under the boot ROM
+            // Game Genie codes only change what comes out of the cartridge
+            0..=MBC_ROM_END => {
+                match self.boot_rom.as_ref().and_then(|b| b.read(address)) {
+                    Some(value) => value,
+                    None => {
+                        self.cheats.read_rom(address, self.mbc.read(address))
+                    },
+                }
+            },
 
             VRAM_START..=VRAM_END => self.vram.raw_read(address),
 
@@ -134,11 +206,15 @@ impl Memory {
             LCD_DATA_START..=LCD_DATA_END => gpu.raw_read(address),
             CGB_DMA_START..=CGB_DMA_END => gpu.raw_read(address),
             CGB_PALETTE_DATA_START..=CGB_PALETTE_DATA_END => {
-                self.palette_ram.raw_read(address)
+                self.palette_ram.raw_read(address, gpu.is_drawing())
             },
+            CGB_OBJECT_PRIORITY => gpu.raw_read(address),
             HRAM_START..=HRAM_END => self.hram.read(address - HRAM_START),
 
-            0xFF00 => self.joypad.read(),
+            0xFF00 => match &self.sgb {
+                Some(sgb) => sgb.read_p1(&self.joypad),
+                None => self.joypad.read(),
+            },
 
             // Timers
             0xFF04 => self.timer_divider,
@@ -146,17 +222,31 @@ impl Memory {
             0xFF06 => self.timer_modulo,
             0xFF07 => self.timer_control,
 
+            0xFF4C if self.cgb_hardware && self.is_boot_rom_mapped() => {
+                self.key0
+            },
+            0xFF4C => 0xFF,
             0xFF4D => self.speed_switch.read_switch_byte(),
 
             0xFF4F => self.vram.bank as u8,
+            // The boot ROM switch can't be read back
+            0xFF50 => 0xFF,
+
+            0xFF56 => self.infrared.read(),
 
             0xFF70 => self.upper_wram_bank as u8,
 
+            0xFF72..=0xFF75 => self.undocumented_registers.read(address),
+            0xFF76 if self.cgb_hardware => self.apu.read_pcm12(),
+            0xFF77 if self.cgb_hardware => self.apu.read_pcm34(),
+            // PCM12 & PCM34 are missing on DMG
+            0xFF76 | 0xFF77 => 0xFF,
+
             INTERRUPT_ENABLE_ADDRESS => ints.enable_read(),
             INTERRUPT_FLAG_ADDRESS => ints.flag_read(),
 
             _ => {
-                log!("Unsupported memory read at {:#06x}", address);
+                log!(self.host, "Unsupported memory read at {:#06x}", address);
                 0xFF
             },
         }
@@ -212,26 +302,51 @@ impl Memory {
             },
             CGB_DMA_START..=CGB_DMA_END => gpu.raw_write(address, value, ints),
             CGB_PALETTE_DATA_START..=CGB_PALETTE_DATA_END => {
-                self.palette_ram.raw_write(address, value)
+                self.palette_ram.raw_write(address, value, gpu.is_drawing())
             },
+            CGB_OBJECT_PRIORITY => gpu.raw_write(address, value, ints),
             HRAM_START..=HRAM_END => {
                 self.hram.write(address - HRAM_START, value)
             },
 
-            0xFF00 => self.joypad.write(value),
+            0xFF00 => {
+                self.joypad.write(value);
+                if let Some(sgb) = self.sgb.as_mut() {
+                    sgb.write_p1(value);
+                }
+            },
 
             // Timers
-            0xFF04 => self.timer_divider = 0,
+            0xFF04 => {
+                self.timer_divider = 0;
+                self.timer_divider_increase = 0;
+            },
             // NOTE: This goes to 0 when written to, not to value
             0xFF05 => self.timer_counter = 0,
             0xFF06 => self.timer_modulo = value,
             0xFF07 => self.timer_control = value,
 
+            // KEY0 is locked once the boot ROM has finished
+            0xFF4C if self.cgb_hardware && self.is_boot_rom_mapped() => {
+                self.key0 = value
+            },
+            0xFF4C => {},
             0xFF4D => self.speed_switch.write_switch_byte(value),
 
             // VRAM bank select
             0xFF4F => self.vram.bank_write(value),
 
+            // The boot ROM's last act is to unmap itself. Once it's gone,
+            // there's no bringing it back.
+            0xFF50 => {
+                if value != 0 && self.is_boot_rom_mapped() {
+                    if let Some(boot_rom) = self.boot_rom.as_mut() {
+                        boot_rom.mapped = false;
+                    }
+                    self.key0_locked = self.cgb_hardware;
+                }
+            },
+
             // Upper WRAM bank select
             0xFF70 => {
                 if !self.cgb_features {
@@ -244,6 +359,14 @@ impl Memory {
                 self.upper_wram_bank = desired_bank as usize;
             },
 
+            0xFF56 => self.infrared.write(value),
+
+            0xFF72..=0xFF75 => {
+                self.undocumented_registers.write(address, value)
+            },
+            // PCM12 & PCM34 are read only
+            0xFF76 | 0xFF77 => {},
+
             // TETRIS also writes here, Sameboy doesn't seem to care
             0xFF7F => {},
 
@@ -251,6 +374,7 @@ impl Memory {
             INTERRUPT_FLAG_ADDRESS => ints.flag_write(value),
 
             _ => log!(
+                self.host,
                 "Unsupported memory write at {:#06x} (value: {:#04x})",
                 address,
                 value
@@ -278,21 +402,82 @@ impl Memory {
         self.write(ints, gpu, address + 1, b2);
     }
 
+    // For debuggers and libretro's memory maps. All 8 banks are included,
+    // even on the DMG.
+    pub fn wram_mut(&mut self) -> &mut [u8] {
+        &mut self.wram.bytes
+    }
+
+    pub fn hram_mut(&mut self) -> &mut [u8] {
+        &mut self.hram.bytes
+    }
+
+    // GameShark codes, called by the GPU at the start of VBlank
+    pub fn apply_ram_cheats(&mut self, ints: &mut Interrupts, gpu: &mut Gpu) {
+        let cheats = core::mem::replace(&mut self.cheats, Cheats::new());
+        for code in cheats.ram_writes() {
+            if let CheatCode::GameShark { address, value, .. } = *code {
+                let bank = self.upper_wram_bank;
+                if let (Some(wram_bank), true) =
+                    (code.wram_bank(), self.cgb_features)
+                {
+                    self.upper_wram_bank = wram_bank.max(1);
+                }
+                self.write(ints, gpu, address, value);
+                self.upper_wram_bank = bank;
+            }
+        }
+        self.cheats = cheats;
+    }
+
+    // Cartridge RAM and the clock, if the cartridge has them
+    pub fn battery(&self) -> Option<&BatteryBackedRam> {
+        self.mbc.battery()
+    }
+
+    pub fn battery_mut(&mut self) -> Option<&mut BatteryBackedRam> {
+        self.mbc.battery_mut()
+    }
+
     pub fn from_info(
         cart_info: Cartridge,
         rom: Rom,
         target: &EmulationTarget,
+        sound_sample_rate: usize,
+        sound_buffer_size: usize,
+        host: SharedHost,
+    ) -> Memory {
+        Memory::with_mbc(
+            mbc_from_info(cart_info, rom, host.clone()),
+            target,
+            sound_sample_rate,
+            sound_buffer_size,
+            host,
+        )
+    }
+
+    // For when the memory controller doesn't come from a cartridge header,
+    // such as when playing GBS files
+    pub fn with_mbc(
+        mbc: Box<dyn MBC>,
+        target: &EmulationTarget,
+        sound_sample_rate: usize,
+        sound_buffer_size: usize,
+        host: SharedHost,
     ) -> Memory {
         let cgb_features = target.has_cgb_features();
         Memory {
             cgb_features,
-            mbc: mbc_from_info(cart_info, rom),
+            cgb_hardware: target.is_cgb_hardware(),
+            mbc,
+            boot_rom: None,
+            cheats: Cheats::new(),
             vram: VRam::new(cgb_features),
             wram: Ram::new(WRAM_BANK_SIZE * 8),
             upper_wram_bank: 1,
             hram: Ram::new(HRAM_SIZE),
             palette_ram: PaletteRam::new(&target),
-            serial_cable: SerialCable::new(),
+            serial_cable: SerialCable::new(cgb_features, host.clone()),
             timer_divider_increase: 0,
             timer_divider: 0,
             timer_counter_increase: 0,
@@ -300,8 +485,99 @@ impl Memory {
             timer_control: 0b00000010,
             timer_modulo: 0,
             joypad: Joypad::new(),
-            apu: APU::new(),
+            sgb: match target {
+                EmulationTarget::Sgb => Some(Sgb::new(host.clone())),
+                _ => None,
+            },
+            apu: APU::new(sound_sample_rate, sound_buffer_size, cgb_features),
             speed_switch: CgbSpeedSwitch::new(cgb_features),
+            key0: match target {
+                EmulationTarget::CgbCgbMode | EmulationTarget::GbaCgbMode => {
+                    0x80
+                },
+                // DMG compatibility mode, or not a CGB at all
+                _ => 0x04,
+            },
+            key0_locked: false,
+            infrared: Infrared::new(cgb_features),
+            undocumented_registers: UndocumentedRegisters::new(target),
+            host,
+        }
+    }
+}
+
+impl SaveState for Memory {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.mbc.save_state(state);
+        state.write_bool(self.is_boot_rom_mapped());
+        state.write_u8(self.key0);
+        state.write_bool(self.cgb_features);
+        self.vram.save_state(state);
+        self.wram.save_state(state);
+        state.write_usize(self.upper_wram_bank);
+        self.hram.save_state(state);
+        self.palette_ram.save_state(state);
+        self.serial_cable.save_state(state);
+
+        state.write_u16(self.timer_divider_increase);
+        state.write_u8(self.timer_divider);
+        state.write_u32(self.timer_counter_increase);
+        state.write_u8(self.timer_counter);
+        state.write_u8(self.timer_modulo);
+        state.write_u8(self.timer_control);
+
+        self.joypad.save_state(state);
+        self.apu.save_state(state);
+        self.speed_switch.save_state(state);
+        self.infrared.save_state(state);
+        self.undocumented_registers.save_state(state);
+        if let Some(sgb) = &self.sgb {
+            sgb.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.mbc.load_state(state);
+        let boot_rom_mapped = state.read_bool();
+        match self.boot_rom.as_mut() {
+            Some(boot_rom) => boot_rom.mapped = boot_rom_mapped,
+            // Saved partway through a boot ROM we don't have
+            None if boot_rom_mapped => state.corrupt = true,
+            None => {},
+        }
+        self.key0 = state.read_u8();
+        // The boot ROM may have picked DMG compatibility mode
+        let cgb_features = state.read_bool();
+        if cgb_features && !self.cgb_hardware {
+            state.corrupt = true;
+        } else {
+            self.set_cgb_features(cgb_features);
+        }
+        self.vram.load_state(state);
+        self.wram.load_state(state);
+        self.upper_wram_bank = state.read_usize();
+        if self.upper_wram_bank > 7 {
+            state.corrupt = true;
+            self.upper_wram_bank = 1;
+        }
+        self.hram.load_state(state);
+        self.palette_ram.load_state(state);
+        self.serial_cable.load_state(state);
+
+        self.timer_divider_increase = state.read_u16();
+        self.timer_divider = state.read_u8();
+        self.timer_counter_increase = state.read_u32();
+        self.timer_counter = state.read_u8();
+        self.timer_modulo = state.read_u8();
+        self.timer_control = state.read_u8();
+
+        self.joypad.load_state(state);
+        self.apu.load_state(state);
+        self.speed_switch.load_state(state);
+        self.infrared.load_state(state);
+        self.undocumented_registers.load_state(state);
+        if let Some(sgb) = self.sgb.as_mut() {
+            sgb.load_state(state);
         }
     }
 }
@@ -1,7 +1,11 @@
 pub mod battery_backed_ram;
+pub mod boot_rom;
 pub mod cgb_speed_switch;
 pub mod mbcs;
 pub mod memory;
+pub mod patch;
 pub mod ram;
 pub mod rom;
+pub mod rtc;
+pub mod undocumented_registers;
 pub mod vram;
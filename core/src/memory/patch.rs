@@ -0,0 +1,585 @@
+// Soft patching, for translations and ROM hacks. The patch is applied to the
+// ROM as it's loaded, so the original file is left alone.
+//
+// IPS is the oldest and simplest format: a list of bytes to overwrite. It
+// has no checksums, so nothing stops it being applied to the wrong ROM.
+// UPS and BPS both record the CRC-32 of the ROM they're meant for, of the
+// patched ROM, and of the patch itself.
+use crate::crc32::crc32;
+use core::fmt;
+
+#[cfg(feature = "std")]
+use std::{fs, io, path::PathBuf};
+
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::String, vec::Vec};
+
+// In the order they're looked for next to the ROM
+pub const PATCH_EXTENSIONS: [&str; 3] = ["bps", "ups", "ips"];
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+// The three CRC-32s on the end of UPS and BPS patches
+const FOOTER_SIZE: usize = 12;
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    // Not an IPS, UPS or BPS patch
+    UnknownFormat,
+    // The patch ends early, or points outside the ROM
+    Corrupt,
+    // The patch's own checksum is wrong, so it's been damaged
+    PatchChecksum { expected: u32, actual: u32 },
+    // The patch is for a different ROM, or another version of this one
+    WrongRom { expected: u32, actual: u32 },
+    // The ROM was patched, but didn't come out as the patch said it would
+    ResultChecksum { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => {
+                write!(f, "not an IPS, UPS or BPS patch")
+            },
+            PatchError::Corrupt => write!(f, "the patch is corrupt"),
+            PatchError::PatchChecksum { expected, actual } => write!(
+                f,
+                "the patch is damaged (its CRC-32 is {:08X}, not {:08X})",
+                actual, expected
+            ),
+            PatchError::WrongRom { expected, actual } => write!(
+                f,
+                "the patch is for a different ROM (CRC-32 {:08X}, but this \
+                 one is {:08X}). Check it's the right version of the game.",
+                expected, actual
+            ),
+            PatchError::ResultChecksum { expected, actual } => write!(
+                f,
+                "the patched ROM came out wrong (its CRC-32 is {:08X}, not \
+                 {:08X})",
+                actual, expected
+            ),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Patch {
+    pub bytes: Vec<u8>,
+    pub path: String,
+}
+
+// Reads through a patch, failing with Corrupt at the end
+struct PatchReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn read_u8(&mut self) -> Result<u8, PatchError> {
+        let byte = *self.bytes.get(self.offset).ok_or(PatchError::Corrupt)?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn read_slice(&mut self, length: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.offset.checked_add(length).ok_or(PatchError::Corrupt)?;
+        let slice = self
+            .bytes
+            .get(self.offset..end)
+            .ok_or(PatchError::Corrupt)?;
+        self.offset = end;
+        Ok(slice)
+    }
+
+    // Big endian, of any length up to 4 bytes
+    fn read_be(&mut self, length: usize) -> Result<usize, PatchError> {
+        let bytes = self.read_slice(length)?;
+        Ok(bytes
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as usize))
+    }
+
+    // UPS and BPS numbers have 7 bits in each byte, and the top bit set on
+    // the last one
+    fn read_number(&mut self) -> Result<usize, PatchError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.read_u8()?;
+            value = (byte as usize & 0x7F)
+                .checked_mul(shift)
+                .and_then(|part| value.checked_add(part))
+                .ok_or(PatchError::Corrupt)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_shl(7).ok_or(PatchError::Corrupt)?;
+            value = value.checked_add(shift).ok_or(PatchError::Corrupt)?;
+        }
+    }
+
+    fn new(bytes: &'a [u8], offset: usize) -> PatchReader<'a> {
+        PatchReader { bytes, offset }
+    }
+}
+
+// Where the file name starts, after the last slash
+fn file_name_start(path: &str) -> usize {
+    path.rfind(['/', '\\']).map_or(0, |slash| slash + 1)
+}
+
+// The file name without its extension
+fn file_stem(path: &str) -> &str {
+    let name = &path[file_name_start(path)..];
+    match name.rfind('.') {
+        Some(0) | None => name,
+        Some(dot) => &name[..dot],
+    }
+}
+
+// What a patched ROM is called, so its saves and screenshots are kept apart
+// from the original's: "game.gb" patched with "english.ips" becomes
+// "game (english).gb"
+pub fn patched_rom_path(rom_path: &str, patch_path: &str) -> String {
+    if rom_path.is_empty() {
+        return String::new();
+    }
+    let stem = file_stem(rom_path);
+    let stem_end = file_name_start(rom_path) + stem.len();
+    let patch_name = match file_stem(patch_path) {
+        "" => "patched",
+        name if name == stem => "patched",
+        name => name,
+    };
+    format!(
+        "{} ({}){}",
+        &rom_path[..stem_end],
+        patch_name,
+        &rom_path[stem_end..]
+    )
+}
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+// Checks the patch's own checksum and the ROM's, from the end of a UPS or
+// BPS patch. Returns the checksum the patched ROM should have.
+fn check_footer(patch: &[u8], rom: &[u8]) -> Result<u32, PatchError> {
+    let footer = &patch[patch.len() - FOOTER_SIZE..];
+    let patch_expected = read_u32_le(&footer[8..]);
+    let patch_actual = crc32(&patch[..patch.len() - 4]);
+    if patch_actual != patch_expected {
+        return Err(PatchError::PatchChecksum {
+            expected: patch_expected,
+            actual: patch_actual,
+        });
+    }
+
+    let rom_expected = read_u32_le(&footer[..4]);
+    let rom_actual = crc32(rom);
+    if rom_actual != rom_expected {
+        return Err(PatchError::WrongRom {
+            expected: rom_expected,
+            actual: rom_actual,
+        });
+    }
+    Ok(read_u32_le(&footer[4..8]))
+}
+
+fn check_result(patched: &[u8], expected: u32) -> Result<(), PatchError> {
+    let actual = crc32(patched);
+    if actual != expected {
+        return Err(PatchError::ResultChecksum { expected, actual });
+    }
+    Ok(())
+}
+
+fn apply_ips(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut patched = rom.to_vec();
+    let mut reader = PatchReader::new(patch, IPS_MAGIC.len());
+    loop {
+        if reader.read_slice(IPS_EOF.len())? == IPS_EOF {
+            break;
+        }
+        reader.offset -= IPS_EOF.len();
+
+        let offset = reader.read_be(3)?;
+        let length = reader.read_be(2)?;
+        // A length of 0 means a run of the same byte
+        let (length, run) = match length {
+            0 => (reader.read_be(2)?, Some(reader.read_u8()?)),
+            _ => (length, None),
+        };
+
+        // IPS patches can make the ROM bigger
+        if patched.len() < offset + length {
+            patched.resize(offset + length, 0);
+        }
+        let target = &mut patched[offset..offset + length];
+        match run {
+            Some(byte) => target.fill(byte),
+            None => target.copy_from_slice(reader.read_slice(length)?),
+        }
+    }
+
+    // Some patches shrink the ROM with a new size after the EOF
+    if reader.offset + 3 == patch.len() {
+        let size = reader.read_be(3)?;
+        patched.truncate(size);
+    }
+    Ok(patched)
+}
+
+fn apply_ups(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.len() < UPS_MAGIC.len() + FOOTER_SIZE {
+        return Err(PatchError::Corrupt);
+    }
+    let patched_crc = check_footer(patch, rom)?;
+    let end = patch.len() - FOOTER_SIZE;
+
+    let mut reader = PatchReader::new(&patch[..end], UPS_MAGIC.len());
+    let _rom_size = reader.read_number()?;
+    let patched_size = reader.read_number()?;
+
+    // Everything the patch changes is XORed with the original
+    let mut patched = rom.to_vec();
+    patched.resize(patched_size, 0);
+    let mut offset: usize = 0;
+    while reader.offset < end {
+        offset = offset
+            .checked_add(reader.read_number()?)
+            .ok_or(PatchError::Corrupt)?;
+        loop {
+            let byte = reader.read_u8()?;
+            if offset < patched.len() {
+                patched[offset] ^= byte;
+            }
+            offset += 1;
+            // The 0 ending each run still counts as a byte
+            if byte == 0 {
+                break;
+            }
+        }
+    }
+
+    check_result(&patched, patched_crc)?;
+    Ok(patched)
+}
+
+// BPS offsets are stored as a distance either way, with the sign in the
+// bottom bit
+fn bps_relative(
+    offset: usize,
+    reader: &mut PatchReader,
+) -> Result<usize, PatchError> {
+    let data = reader.read_number()?;
+    let distance = data >> 1;
+    match data & 1 {
+        0 => offset.checked_add(distance),
+        _ => offset.checked_sub(distance),
+    }
+    .ok_or(PatchError::Corrupt)
+}
+
+fn apply_bps(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.len() < BPS_MAGIC.len() + FOOTER_SIZE {
+        return Err(PatchError::Corrupt);
+    }
+    let patched_crc = check_footer(patch, rom)?;
+    let end = patch.len() - FOOTER_SIZE;
+
+    let mut reader = PatchReader::new(&patch[..end], BPS_MAGIC.len());
+    let _rom_size = reader.read_number()?;
+    let patched_size = reader.read_number()?;
+    let metadata_size = reader.read_number()?;
+    reader.read_slice(metadata_size)?;
+
+    // The patched ROM is built up from copies out of the original, the
+    // patch, and what's been built so far
+    let mut patched = Vec::with_capacity(patched_size);
+    let mut rom_offset: usize = 0;
+    let mut patched_offset: usize = 0;
+    while reader.offset < end {
+        let action = reader.read_number()?;
+        let length = (action >> 2) + 1;
+        match action & 0b11 {
+            // From the same place in the original
+            0 => {
+                let start = patched.len();
+                let bytes = rom
+                    .get(start..start + length)
+                    .ok_or(PatchError::Corrupt)?;
+                patched.extend_from_slice(bytes);
+            },
+            // Straight out of the patch
+            1 => patched.extend_from_slice(reader.read_slice(length)?),
+            // From anywhere in the original
+            2 => {
+                rom_offset = bps_relative(rom_offset, &mut reader)?;
+                let bytes = rom
+                    .get(rom_offset..rom_offset + length)
+                    .ok_or(PatchError::Corrupt)?;
+                patched.extend_from_slice(bytes);
+                rom_offset += length;
+            },
+            // From earlier in the patched ROM. This can overlap what it's
+            // writing, to repeat a pattern, so it goes a byte at a time.
+            _ => {
+                patched_offset = bps_relative(patched_offset, &mut reader)?;
+                for _ in 0..length {
+                    let byte = *patched
+                        .get(patched_offset)
+                        .ok_or(PatchError::Corrupt)?;
+                    patched.push(byte);
+                    patched_offset += 1;
+                }
+            },
+        }
+        if patched.len() > patched_size {
+            return Err(PatchError::Corrupt);
+        }
+    }
+
+    if patched.len() != patched_size {
+        return Err(PatchError::Corrupt);
+    }
+    check_result(&patched, patched_crc)?;
+    Ok(patched)
+}
+
+impl Patch {
+    // Returns the patched ROM. The format is worked out from the patch
+    // itself rather than its extension.
+    pub fn apply(&self, rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+        if self.bytes.starts_with(IPS_MAGIC) {
+            apply_ips(&self.bytes, rom)
+        } else if self.bytes.starts_with(UPS_MAGIC) {
+            apply_ups(&self.bytes, rom)
+        } else if self.bytes.starts_with(BPS_MAGIC) {
+            apply_bps(&self.bytes, rom)
+        } else {
+            Err(PatchError::UnknownFormat)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn from_file(path: &str) -> io::Result<Patch> {
+        Ok(Patch {
+            bytes: fs::read(path)?,
+            path: path.to_string(),
+        })
+    }
+
+    // The path of a patch with the same name as the ROM, eg. game.ips for
+    // game.gb, if there is one
+    #[cfg(feature = "std")]
+    pub fn path_next_to(rom_path: &str) -> Option<String> {
+        if rom_path.is_empty() {
+            return None;
+        }
+        PATCH_EXTENSIONS.iter().find_map(|extension| {
+            let mut path = PathBuf::from(rom_path);
+            path.set_extension(extension);
+            match path.exists() {
+                true => Some(path.to_string_lossy().into_owned()),
+                false => None,
+            }
+        })
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Patch {
+        Patch {
+            bytes,
+            path: String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: &[u8] = b"The quick brown fox jumps over the lazy dog";
+
+    // The inverse of PatchReader::read_number
+    fn push_number(patch: &mut Vec<u8>, mut value: usize) {
+        loop {
+            let bits = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                patch.push(bits | 0x80);
+                return;
+            }
+            patch.push(bits);
+            value -= 1;
+        }
+    }
+
+    fn push_footer(patch: &mut Vec<u8>, rom: &[u8], patched: &[u8]) {
+        patch.extend_from_slice(&crc32(rom).to_le_bytes());
+        patch.extend_from_slice(&crc32(patched).to_le_bytes());
+        let patch_crc = crc32(patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+    }
+
+    fn apply(bytes: Vec<u8>, rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+        Patch::from_bytes(bytes).apply(rom)
+    }
+
+    #[test]
+    fn numbers_round_trip() {
+        for value in [0, 1, 0x7F, 0x80, 0x407F, 0x4080, 0x123456] {
+            let mut bytes = vec![];
+            push_number(&mut bytes, value);
+            assert_eq!(PatchReader::new(&bytes, 0).read_number(), Ok(value));
+        }
+    }
+
+    #[test]
+    fn ips_records_and_runs() {
+        let mut patch = IPS_MAGIC.to_vec();
+        // "slow" over "quick"
+        patch.extend_from_slice(&[0, 0, 4, 0, 5]);
+        patch.extend_from_slice(b"slow ");
+        // A run of 3 'z's, then one past the end to grow the ROM
+        patch.extend_from_slice(&[0, 0, 40, 0, 0, 0, 3, b'z']);
+        patch.extend_from_slice(&[0, 0, 45, 0, 0, 0, 2, b'!']);
+        patch.extend_from_slice(IPS_EOF);
+
+        let patched = apply(patch, ROM).unwrap();
+        assert_eq!(
+            patched,
+            b"The slow  brown fox jumps over the lazy zzz\0\0!!".to_vec()
+        );
+    }
+
+    #[test]
+    fn ips_truncates() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(IPS_EOF);
+        patch.extend_from_slice(&[0, 0, 9]);
+        assert_eq!(apply(patch, ROM).unwrap(), b"The quick".to_vec());
+    }
+
+    #[test]
+    fn ips_corrupt() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0, 0, 4, 0, 5, b's']);
+        assert_eq!(apply(patch, ROM), Err(PatchError::Corrupt));
+    }
+
+    fn ups_patch(rom: &[u8], patched: &[u8]) -> Vec<u8> {
+        let mut patch = UPS_MAGIC.to_vec();
+        push_number(&mut patch, rom.len());
+        push_number(&mut patch, patched.len());
+        // A run for each stretch of bytes that differ. The 0 ending a run
+        // counts as a byte, so the next skip starts after it.
+        let differs = |i: usize| rom.get(i).copied().unwrap_or(0) != patched[i];
+        let mut offset = 0;
+        let mut i = 0;
+        while i < patched.len() {
+            if !differs(i) {
+                i += 1;
+                continue;
+            }
+            push_number(&mut patch, i - offset);
+            while i < patched.len() && differs(i) {
+                patch.push(rom.get(i).copied().unwrap_or(0) ^ patched[i]);
+                i += 1;
+            }
+            patch.push(0);
+            i += 1;
+            offset = i;
+        }
+        push_footer(&mut patch, rom, patched);
+        patch
+    }
+
+    #[test]
+    fn ups_xors() {
+        let patched = b"The quick brown cat jumps over the lazy dog!";
+        let patch = ups_patch(ROM, patched);
+        assert_eq!(apply(patch, ROM).unwrap(), patched.to_vec());
+    }
+
+    fn bps_patch(rom: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut patch = BPS_MAGIC.to_vec();
+        let patched = b"The quick red fox fox fox jumps".to_vec();
+        push_number(&mut patch, rom.len());
+        push_number(&mut patch, patched.len());
+        push_number(&mut patch, 0);
+        // "The quick " from the same place in the ROM
+        push_number(&mut patch, (10 - 1) << 2);
+        // "red" out of the patch
+        push_number(&mut patch, ((3 - 1) << 2) | 1);
+        patch.extend_from_slice(b"red");
+        // " fox " from 15 bytes into the ROM
+        push_number(&mut patch, ((5 - 1) << 2) | 2);
+        push_number(&mut patch, 15 << 1);
+        // "fox fox " repeated from what's just been written, overlapping
+        push_number(&mut patch, ((8 - 1) << 2) | 3);
+        push_number(&mut patch, 14 << 1);
+        // "jumps" from further along in the ROM
+        push_number(&mut patch, ((5 - 1) << 2) | 2);
+        push_number(&mut patch, 0);
+        (patch, patched)
+    }
+
+    #[test]
+    fn bps_copies() {
+        let (mut patch, patched) = bps_patch(ROM);
+        push_footer(&mut patch, ROM, &patched);
+        assert_eq!(apply(patch, ROM).unwrap(), patched);
+    }
+
+    #[test]
+    fn patch_checksum() {
+        let (mut patch, patched) = bps_patch(ROM);
+        push_footer(&mut patch, ROM, &patched);
+        // Damage the patch after its checksum was worked out
+        patch[6] ^= 1;
+        assert!(matches!(
+            apply(patch, ROM),
+            Err(PatchError::PatchChecksum { .. })
+        ));
+    }
+
+    #[test]
+    fn wrong_rom() {
+        let patched = b"The quick brown cat jumps over the lazy dog";
+        let patch = ups_patch(ROM, patched);
+        let other_rom = b"The quick brown fox jumps over the lazy cat";
+        assert_eq!(
+            apply(patch, other_rom),
+            Err(PatchError::WrongRom {
+                expected: crc32(ROM),
+                actual: crc32(other_rom),
+            })
+        );
+    }
+
+    #[test]
+    fn result_checksum() {
+        let (mut patch, patched) = bps_patch(ROM);
+        // Claim the patched ROM will come out as something else
+        push_footer(&mut patch, ROM, b"Something else");
+        assert_eq!(
+            apply(patch, ROM),
+            Err(PatchError::ResultChecksum {
+                expected: crc32(b"Something else"),
+                actual: crc32(&patched),
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_format() {
+        let patch = b"Not a patch".to_vec();
+        assert_eq!(apply(patch, ROM), Err(PatchError::UnknownFormat));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
 #[cfg(not(feature = "std"))]
 use alloc::{vec, vec::Vec};
 
@@ -39,3 +41,13 @@ impl Ram {
         }
     }
 }
+
+impl SaveState for Ram {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.bytes);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        state.read_bytes_into(&mut self.bytes);
+    }
+}
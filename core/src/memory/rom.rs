@@ -1,3 +1,5 @@
+use crate::memory::patch::{patched_rom_path, Patch, PatchError};
+
 #[cfg(feature = "std")]
 use std::{fs::File, io::Read};
 
@@ -16,6 +18,14 @@ impl Rom {
         self.bytes[address as usize]
     }
 
+    // The path is renamed after the patch, so the patched game gets its own
+    // save. If the patch fails, the ROM is left as it was.
+    pub fn apply_patch(&mut self, patch: &Patch) -> Result<(), PatchError> {
+        self.bytes = patch.apply(&self.bytes)?;
+        self.path = patched_rom_path(&self.path, &patch.path);
+        Ok(())
+    }
+
     #[cfg(feature = "std")]
     pub fn from_file(path: &str) -> Rom {
         let mut buffer = vec![];
@@ -0,0 +1,205 @@
+// MBC3 Real Time Clock
+// This counts emulated time, so it keeps in step with the game when fast
+// forwarding or playing movies. Time that passed while the game wasn't
+// running is caught up on when the save is loaded.
+use crate::state::{SaveState, StateReader, StateWriter};
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+// Seconds, minutes, hours, the bottom 8 bits of the day counter, then the
+// top bit of the day counter along with the halt and day carry flags
+pub const RTC_REGISTER_COUNT: usize = 5;
+const REGISTER_MASKS: [u8; RTC_REGISTER_COUNT] = [0x3F, 0x3F, 0x1F, 0xFF, 0xC1];
+const DAY_HIGH: usize = 4;
+const HALT: u8 = 0b0100_0000;
+const DAY_CARRY: u8 = 0b1000_0000;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// VBA-M, BGB, mGBA and friends put the clock on the end of .sav files as
+// the live registers, the latched registers (each as a little endian u32)
+// and then the Unix time the save was made. Older versions used a 32-bit
+// timestamp.
+pub const RTC_SAVE_SIZE: usize = 48;
+const OLD_RTC_SAVE_SIZE: usize = 44;
+
+pub struct Rtc {
+    registers: [u8; RTC_REGISTER_COUNT],
+    // What the game reads. Copied from `registers` by writing 0 then 1 to
+    // 0x6000-0x7FFF.
+    latched: [u8; RTC_REGISTER_COUNT],
+    latch_armed: bool,
+
+    // Towards the next second
+    ms_counter: usize,
+    last_stepped_at: usize,
+}
+
+impl Rtc {
+    pub fn read(&self, register: usize) -> u8 {
+        self.latched[register]
+    }
+
+    pub fn write(&mut self, register: usize, value: u8) {
+        self.registers[register] = value & REGISTER_MASKS[register];
+        // Writing the seconds restarts the current second
+        if register == 0 {
+            self.ms_counter = 0;
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 1 {
+            self.latched = self.registers;
+        }
+        self.latch_armed = value == 0;
+    }
+
+    pub fn step(&mut self, ms_since_boot: usize) {
+        let elapsed = ms_since_boot.saturating_sub(self.last_stepped_at);
+        self.last_stepped_at = ms_since_boot;
+        if self.is_halted() {
+            return;
+        }
+
+        self.ms_counter += elapsed;
+        while self.ms_counter >= 1000 {
+            self.ms_counter -= 1000;
+            self.tick();
+        }
+    }
+
+    fn is_halted(&self) -> bool {
+        self.registers[DAY_HIGH] & HALT != 0
+    }
+
+    fn days(&self) -> u64 {
+        self.registers[3] as u64 | ((self.registers[DAY_HIGH] & 1) as u64) << 8
+    }
+
+    fn set_days(&mut self, days: u64) {
+        let mut days = days;
+        if days >= 512 {
+            self.registers[DAY_HIGH] |= DAY_CARRY;
+            days %= 512;
+        }
+        self.registers[3] = days as u8;
+        self.registers[DAY_HIGH] =
+            (self.registers[DAY_HIGH] & !1) | (days >> 8) as u8;
+    }
+
+    // Out of range values (like 61 seconds) count up to the register's
+    // limit and wrap to 0 without carrying, as on hardware
+    fn tick(&mut self) {
+        let limits = [60, 60, 24];
+        for (i, limit) in limits.iter().enumerate() {
+            let value = (self.registers[i] + 1) & REGISTER_MASKS[i];
+            if value == *limit {
+                self.registers[i] = 0;
+            } else {
+                self.registers[i] = value;
+                return;
+            }
+        }
+        self.set_days(self.days() + 1);
+    }
+
+    // For catching up on time that passed while the game was off
+    pub fn advance(&mut self, seconds: u64) {
+        if self.is_halted() || seconds == 0 {
+            return;
+        }
+
+        let time_of_day = self.registers[0] as u64
+            + self.registers[1] as u64 * 60
+            + self.registers[2] as u64 * 60 * 60
+            + seconds;
+        let days = self.days() + time_of_day / SECONDS_PER_DAY;
+        let time_of_day = time_of_day % SECONDS_PER_DAY;
+
+        self.registers[0] = (time_of_day % 60) as u8;
+        self.registers[1] = (time_of_day / 60 % 60) as u8;
+        self.registers[2] = (time_of_day / (60 * 60)) as u8;
+        self.set_days(days);
+    }
+
+    // The .sav footer, stamped with `now` (Unix seconds)
+    pub fn save_data(&self, now: u64) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(RTC_SAVE_SIZE);
+        for register in self.registers.iter().chain(self.latched.iter()) {
+            bytes.extend_from_slice(&(*register as u32).to_le_bytes());
+        }
+        bytes.extend_from_slice(&now.to_le_bytes());
+        bytes
+    }
+
+    // Returns false if `bytes` isn't a clock footer. The clock is moved on by
+    // however long it's been since the save was made.
+    pub fn load_save_data(&mut self, bytes: &[u8], now: u64) -> bool {
+        if bytes.len() != RTC_SAVE_SIZE && bytes.len() != OLD_RTC_SAVE_SIZE {
+            return false;
+        }
+
+        let read_u32 = |i: usize| {
+            u32::from_le_bytes([
+                bytes[i * 4],
+                bytes[i * 4 + 1],
+                bytes[i * 4 + 2],
+                bytes[i * 4 + 3],
+            ])
+        };
+        for (i, mask) in REGISTER_MASKS.iter().enumerate() {
+            self.registers[i] = read_u32(i) as u8 & mask;
+            self.latched[i] = read_u32(i + RTC_REGISTER_COUNT) as u8 & mask;
+        }
+        let saved_at = match bytes.len() {
+            RTC_SAVE_SIZE => {
+                let mut timestamp = [0; 8];
+                timestamp.copy_from_slice(&bytes[40..48]);
+                u64::from_le_bytes(timestamp)
+            },
+            _ => read_u32(10) as u64,
+        };
+
+        // Hosts without a clock say it's 0
+        if saved_at != 0 && now > saved_at {
+            self.advance(now - saved_at);
+        }
+        true
+    }
+
+    pub fn new() -> Rtc {
+        Rtc {
+            registers: [0; RTC_REGISTER_COUNT],
+            latched: [0; RTC_REGISTER_COUNT],
+            latch_armed: false,
+            ms_counter: 0,
+            last_stepped_at: 0,
+        }
+    }
+}
+
+impl Default for Rtc {
+    fn default() -> Rtc {
+        Rtc::new()
+    }
+}
+
+impl SaveState for Rtc {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.registers);
+        state.write_bytes(&self.latched);
+        state.write_bool(self.latch_armed);
+        state.write_usize(self.ms_counter);
+        state.write_usize(self.last_stepped_at);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        state.read_bytes_into(&mut self.registers);
+        state.read_bytes_into(&mut self.latched);
+        self.latch_armed = state.read_bool();
+        self.ms_counter = state.read_usize();
+        self.last_stepped_at = state.read_usize();
+    }
+}
@@ -0,0 +1,66 @@
+// 0xFF72-0xFF75 do nothing, but are readable and writable on CGB hardware,
+// and some test ROMs check that they behave
+use crate::cpu::EmulationTarget;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+// Only bits 4-6 of 0xFF75 can be changed
+const FF75_MASK: u8 = 0b0111_0000;
+
+pub struct UndocumentedRegisters {
+    cgb_hardware: bool,
+    // 0xFF74 is locked in DMG compatibility mode
+    pub cgb_features: bool,
+    ff72: u8,
+    ff73: u8,
+    ff74: u8,
+    ff75: u8,
+}
+
+impl UndocumentedRegisters {
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0xFF72 if self.cgb_hardware => self.ff72,
+            0xFF73 if self.cgb_hardware => self.ff73,
+            0xFF74 if self.cgb_features => self.ff74,
+            0xFF75 if self.cgb_hardware => self.ff75 | !FF75_MASK,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF72 if self.cgb_hardware => self.ff72 = value,
+            0xFF73 if self.cgb_hardware => self.ff73 = value,
+            0xFF74 if self.cgb_features => self.ff74 = value,
+            0xFF75 if self.cgb_hardware => self.ff75 = value & FF75_MASK,
+            _ => {},
+        }
+    }
+
+    pub fn new(target: &EmulationTarget) -> UndocumentedRegisters {
+        UndocumentedRegisters {
+            cgb_hardware: target.is_cgb_hardware(),
+            cgb_features: target.has_cgb_features(),
+            ff72: 0,
+            ff73: 0,
+            ff74: 0,
+            ff75: 0,
+        }
+    }
+}
+
+impl SaveState for UndocumentedRegisters {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.ff72);
+        state.write_u8(self.ff73);
+        state.write_u8(self.ff74);
+        state.write_u8(self.ff75);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.ff72 = state.read_u8();
+        self.ff73 = state.read_u8();
+        self.ff74 = state.read_u8();
+        self.ff75 = state.read_u8() & FF75_MASK;
+    }
+}
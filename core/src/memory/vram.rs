@@ -1,9 +1,10 @@
 use super::ram::Ram;
 use crate::colour::bg_map_attributes::BgMapAttributeTable;
 use crate::constants::*;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 pub struct VRam {
-    cgb_features: bool,
+    pub cgb_features: bool,
     memory: Ram,
     pub bank: u16,
     pub bg_map_attributes: BgMapAttributeTable,
@@ -39,6 +40,12 @@ impl VRam {
             .write(self.bank * VRAM_BANK_SIZE as u16 + relative_address, value)
     }
 
+    // Both banks, one after the other. The CGB's background attributes are
+    // kept separately.
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.memory.bytes
+    }
+
     pub fn bank_write(&mut self, value: u8) {
         if !self.cgb_features {
             return;
@@ -55,3 +62,17 @@ impl VRam {
         }
     }
 }
+
+impl SaveState for VRam {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.memory.save_state(state);
+        state.write_u16(self.bank);
+        self.bg_map_attributes.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.memory.load_state(state);
+        self.bank = state.read_u16();
+        self.bg_map_attributes.load_state(state);
+    }
+}
@@ -0,0 +1,218 @@
+// Input movies: a recording of the joypad on every frame, which replays
+// exactly when started from the same state. Used for tool-assisted runs and
+// reproducible bug reports.
+//
+// A "frame" here is a fixed amount of emulated time (the length of an LCD
+// frame, which is what step_one_frame runs for on average), so frames line
+// up the same way no matter how a port chooses to step the CPU. The game
+// only sees the joypad change at the start of a frame.
+use crate::constants::*;
+use crate::cpu::EmulationTarget;
+use crate::joypad::Joypad;
+use crate::state::{StateError, StateReader, StateWriter};
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+const MOVIE_MAGIC: &[u8; 8] = b"GBRSMOVI";
+const MOVIE_VERSION: u8 = 2;
+
+#[derive(Debug, PartialEq)]
+pub enum MovieError {
+    InvalidHeader,
+    UnsupportedVersion(u8),
+    Corrupt,
+    // The movie was recorded with a different ROM
+    RomMismatch,
+    // The movie was recorded on a different Gameboy model
+    ModelMismatch,
+    // Power-on movies have to start before the CPU has run at all
+    NotAtPowerOn,
+    State(StateError),
+}
+
+impl From<StateError> for MovieError {
+    fn from(err: StateError) -> MovieError {
+        MovieError::State(err)
+    }
+}
+
+#[derive(Clone)]
+pub enum MovieStart {
+    PowerOn,
+    // A save state from Cpu::save_state
+    SaveState(Vec<u8>),
+}
+
+#[derive(Clone)]
+pub struct Movie {
+    pub rom_crc32: u32,
+    pub model: EmulationTarget,
+    pub start: MovieStart,
+    // One Joypad::buttons_bitmask per frame
+    pub inputs: Vec<u8>,
+}
+
+fn model_id(model: EmulationTarget) -> u8 {
+    match model {
+        EmulationTarget::Dmg => 0,
+        EmulationTarget::CgbDmgMode => 1,
+        EmulationTarget::CgbCgbMode => 2,
+        EmulationTarget::GbaCgbMode => 3,
+        EmulationTarget::Sgb => 4,
+    }
+}
+
+fn model_from_id(id: u8) -> Option<EmulationTarget> {
+    match id {
+        0 => Some(EmulationTarget::Dmg),
+        1 => Some(EmulationTarget::CgbDmgMode),
+        2 => Some(EmulationTarget::CgbCgbMode),
+        3 => Some(EmulationTarget::GbaCgbMode),
+        4 => Some(EmulationTarget::Sgb),
+        _ => None,
+    }
+}
+
+impl Movie {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.bytes.extend_from_slice(MOVIE_MAGIC);
+        writer.write_u8(MOVIE_VERSION);
+        writer.write_u32(self.rom_crc32);
+        writer.write_u8(model_id(self.model));
+        match &self.start {
+            MovieStart::PowerOn => writer.write_u8(0),
+            MovieStart::SaveState(state) => {
+                writer.write_u8(1);
+                writer.write_bytes(state);
+            },
+        }
+        writer.write_bytes(&self.inputs);
+        writer.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, MovieError> {
+        if bytes.len() < MOVIE_MAGIC.len()
+            || &bytes[..MOVIE_MAGIC.len()] != MOVIE_MAGIC
+        {
+            return Err(MovieError::InvalidHeader);
+        }
+
+        let mut reader = StateReader::new(&bytes[MOVIE_MAGIC.len()..]);
+        let version = reader.read_u8();
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let rom_crc32 = reader.read_u32();
+        let model =
+            model_from_id(reader.read_u8()).ok_or(MovieError::Corrupt)?;
+        let start = match reader.read_u8() {
+            0 => MovieStart::PowerOn,
+            1 => MovieStart::SaveState(reader.read_bytes()),
+            _ => return Err(MovieError::Corrupt),
+        };
+        let inputs = reader.read_bytes();
+
+        if reader.corrupt || !reader.is_at_end() {
+            return Err(MovieError::Corrupt);
+        }
+
+        Ok(Movie {
+            rom_crc32,
+            model,
+            start,
+            inputs,
+        })
+    }
+
+    pub fn new(
+        rom_crc32: u32,
+        model: EmulationTarget,
+        start: MovieStart,
+    ) -> Movie {
+        Movie {
+            rom_crc32,
+            model,
+            start,
+            inputs: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+    // Playback ran out of inputs, the port's joypad is in control again
+    Finished,
+}
+
+// A movie that's currently being recorded or played back. Lives in
+// Cpu::movie, and is stepped along with the CPU.
+pub struct MovieSession {
+    pub movie: Movie,
+    pub mode: MovieMode,
+    // The current frame, counting from 0 at the start of the movie
+    pub frame: usize,
+    // Frames in which the game never read the joypad
+    pub lag_frames: usize,
+    pub last_frame_lagged: bool,
+
+    // Emulated clocks since the current frame started
+    frame_clocks: usize,
+}
+
+impl MovieSession {
+    // Called with the clocks (at single speed) that the CPU just ran for
+    pub fn step(&mut self, clocks: usize, joypad: &mut Joypad) {
+        self.frame_clocks += clocks;
+
+        while self.frame_clocks >= CLOCKS_PER_FRAME {
+            self.frame_clocks -= CLOCKS_PER_FRAME;
+            self.end_frame(joypad);
+            self.begin_frame(joypad);
+        }
+    }
+
+    // Decides which buttons the game will see this frame
+    pub fn begin_frame(&mut self, joypad: &mut Joypad) {
+        joypad.polled.set(false);
+
+        match self.mode {
+            MovieMode::Recording => {
+                let bitmask = joypad.buttons_bitmask();
+                self.movie.inputs.push(bitmask);
+                joypad.movie_input = Some(bitmask);
+            },
+            MovieMode::Playing => match self.movie.inputs.get(self.frame) {
+                Some(bitmask) => joypad.movie_input = Some(*bitmask),
+                None => {
+                    self.mode = MovieMode::Finished;
+                    joypad.movie_input = None;
+                },
+            },
+            MovieMode::Finished => {},
+        }
+    }
+
+    fn end_frame(&mut self, joypad: &Joypad) {
+        self.last_frame_lagged = !joypad.polled.get();
+        if self.last_frame_lagged {
+            self.lag_frames += 1;
+        }
+        self.frame += 1;
+    }
+
+    pub fn new(movie: Movie, mode: MovieMode) -> MovieSession {
+        MovieSession {
+            movie,
+            mode,
+            frame: 0,
+            lag_frames: 0,
+            last_frame_lagged: false,
+            frame_clocks: 0,
+        }
+    }
+}
@@ -0,0 +1,85 @@
+// A tiny PNG encoder for screenshots and frame dumps. It doesn't compress
+// (the image data goes into "stored" deflate blocks), which keeps it small
+// and dependency-free at the cost of bigger files.
+
+use crate::crc32::Crc32;
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+// The most a single stored deflate block can hold
+const MAX_STORED_BLOCK: usize = 65_535;
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for byte in bytes {
+        a = (a + *byte as u32) % 65_521;
+        b = (b + a) % 65_521;
+    }
+    (b << 16) | a
+}
+
+fn write_chunk(out: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(chunk_type);
+    out.extend_from_slice(data);
+    let mut crc = Crc32::new();
+    crc.update(chunk_type);
+    crc.update(data);
+    out.extend_from_slice(&crc.finish().to_be_bytes());
+}
+
+// Wraps data in a zlib stream without compressing it
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let block_count = data.len().div_ceil(MAX_STORED_BLOCK);
+    let mut out = Vec::with_capacity(data.len() + block_count * 5 + 6);
+
+    // Deflate with a 32KB window, no preset dictionary
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        // An empty final block
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let is_final = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(is_final as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// Encodes 8-bit RGBA pixels (as from Gpu::get_rgba_frame) into a PNG file
+pub fn encode_rgba(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+    assert_eq!(rgba.len(), width * height * 4, "Wrong RGBA buffer size");
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGBA, default compression, filtering & no
+    // interlacing
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    // Each scanline starts with its filter type, which is always "none"
+    let row_size = width * 4;
+    let mut image_data = Vec::with_capacity((row_size + 1) * height);
+    for row in rgba.chunks(row_size) {
+        image_data.push(0);
+        image_data.extend_from_slice(row);
+    }
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&SIGNATURE);
+    write_chunk(&mut out, b"IHDR", &header);
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&image_data));
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
@@ -0,0 +1,164 @@
+// Records gameplay to disk for bug reports. Audio goes to a WAV file and
+// video to one of a few simple formats that other tools can convert.
+use crate::constants::*;
+use crate::cpu::Cpu;
+use crate::png;
+use crate::wav::WavWriter;
+use std::fs::{self, File};
+use std::io::{BufWriter, Result, Write};
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VideoFormat {
+    // Every frame's RGBA bytes one after another in a single file
+    RawRgba,
+    // A directory of numbered PNG files
+    PngSequence,
+    // Uncompressed YUV 4:4:4, which ffmpeg & most players understand
+    Y4m,
+}
+
+enum VideoWriter {
+    RawRgba(BufWriter<File>),
+    PngSequence(PathBuf),
+    Y4m(BufWriter<File>),
+}
+
+pub struct Recorder {
+    pub video_format: VideoFormat,
+    audio: WavWriter<BufWriter<File>>,
+    video: VideoWriter,
+    // Bigger than the Gameboy screen if there's a Super Gameboy border
+    width: usize,
+    height: usize,
+
+    // Clocks that have passed since the last frame was written
+    clocks_since_frame: usize,
+    pub frames_written: usize,
+}
+
+impl Recorder {
+    // Should be given every sample read from the APU
+    pub fn record_audio(&mut self, samples: &[i16]) -> Result<()> {
+        self.audio.write_samples(samples)
+    }
+
+    // Call after stepping the CPU, with the cycles that were stepped (such as
+    // the return value of step_one_frame). The video runs at the real
+    // Gameboy's frame rate, so frames are duplicated or skipped to match how
+    // much time has actually passed.
+    pub fn record_frame(&mut self, cpu: &Cpu, cycles: usize) -> Result<()> {
+        // Cycles count double in double speed mode, but the screen doesn't
+        // speed up
+        self.clocks_since_frame +=
+            match cpu.mem.speed_switch.current_speed_is_double {
+                true => cycles / 2,
+                false => cycles,
+            };
+
+        if self.clocks_since_frame < CLOCKS_PER_FRAME {
+            return Ok(());
+        }
+
+        let rgba = cpu.get_rgba_frame();
+        while self.clocks_since_frame >= CLOCKS_PER_FRAME {
+            self.clocks_since_frame -= CLOCKS_PER_FRAME;
+            self.write_frame(&rgba)?;
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, rgba: &[u8]) -> Result<()> {
+        match &mut self.video {
+            VideoWriter::RawRgba(file) => file.write_all(rgba)?,
+            VideoWriter::PngSequence(directory) => {
+                let path = directory
+                    .join(format!("frame_{:06}.png", self.frames_written));
+                let png = png::encode_rgba(self.width, self.height, rgba);
+                fs::write(path, png)?;
+            },
+            VideoWriter::Y4m(file) => {
+                file.write_all(b"FRAME\n")?;
+                file.write_all(&rgba_to_yuv444(rgba))?;
+            },
+        }
+        self.frames_written += 1;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        self.audio.finish()?;
+        match self.video {
+            VideoWriter::RawRgba(mut file) | VideoWriter::Y4m(mut file) => {
+                file.flush()
+            },
+            VideoWriter::PngSequence(_) => Ok(()),
+        }
+    }
+
+    // Creates `<base_path>.wav`, as well as `<base_path>.rgba`,
+    // `<base_path>.y4m` or a `<base_path>_frames` directory for the video.
+    // The audio is recorded at the CPU's APU sample rate.
+    pub fn new(
+        base_path: &str,
+        video_format: VideoFormat,
+        cpu: &Cpu,
+    ) -> Result<Recorder> {
+        let (width, height) = (cpu.screen_width(), cpu.screen_height());
+        let audio_file =
+            BufWriter::new(File::create(format!("{}.wav", base_path))?);
+        let audio = WavWriter::new(audio_file, cpu.mem.apu.sample_rate, 2)?;
+
+        let video = match video_format {
+            VideoFormat::RawRgba => VideoWriter::RawRgba(BufWriter::new(
+                File::create(format!("{}.rgba", base_path))?,
+            )),
+            VideoFormat::PngSequence => {
+                let directory = PathBuf::from(format!("{}_frames", base_path));
+                fs::create_dir_all(&directory)?;
+                VideoWriter::PngSequence(directory)
+            },
+            VideoFormat::Y4m => {
+                let mut file =
+                    BufWriter::new(File::create(format!("{}.y4m", base_path))?);
+                // The frame rate is given as a fraction, which lets it be
+                // exact
+                writeln!(
+                    file,
+                    "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+                    width, height, CLOCK_SPEED, CLOCKS_PER_FRAME
+                )?;
+                VideoWriter::Y4m(file)
+            },
+        };
+
+        Ok(Recorder {
+            video_format,
+            audio,
+            video,
+            width,
+            height,
+            clocks_since_frame: 0,
+            frames_written: 0,
+        })
+    }
+}
+
+// Converts to BT.601 (studio range) planar YUV, as expected by Y4M readers
+fn rgba_to_yuv444(rgba: &[u8]) -> Vec<u8> {
+    let pixel_count = rgba.len() / 4;
+    let mut out = vec![0; pixel_count * 3];
+    let (y_plane, uv_planes) = out.split_at_mut(pixel_count);
+    let (u_plane, v_plane) = uv_planes.split_at_mut(pixel_count);
+
+    for (i, pixel) in rgba.chunks_exact(4).enumerate() {
+        let r = pixel[0] as i32;
+        let g = pixel[1] as i32;
+        let b = pixel[2] as i32;
+
+        y_plane[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        u_plane[i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+        v_plane[i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+    }
+    out
+}
@@ -4,6 +4,7 @@ use crate::interrupts::*;
 use crate::memory::memory::Memory;
 use crate::{combine_u8, set_bit, split_u16};
 
+use crate::state::{SaveState, StateReader, StateWriter};
 #[cfg(not(feature = "std"))]
 use alloc::{format, string::String};
 
@@ -217,7 +218,9 @@ impl Registers {
         //       since we don't run that.
         // This is how games detect that they can use GameBoy Color features.
         let bootup_a_value = match emulation_target {
-            EmulationTarget::Dmg | EmulationTarget::CgbDmgMode => 0x01,
+            EmulationTarget::Dmg
+            | EmulationTarget::CgbDmgMode
+            | EmulationTarget::Sgb => 0x01,
             EmulationTarget::CgbCgbMode | EmulationTarget::GbaCgbMode => 0x11,
         };
         // This is exclusively used to detect running on the GameBoy Advance.
@@ -225,17 +228,48 @@ impl Registers {
             EmulationTarget::GbaCgbMode => 0x01,
             _ => 0x00,
         };
+        // The SGB's boot rom leaves different values in the other registers
+        let (c, e, f, h, l) = match emulation_target {
+            EmulationTarget::Sgb => (0x14, 0x00, 0x00, 0xC0, 0x60),
+            _ => (0x13, 0xD8, 0xB0, 0x01, 0x4D),
+        };
         Registers {
             a: bootup_a_value,
             b: bootup_b_value,
-            c: 0x13,
+            c,
             d: 0x00,
-            e: 0xD8,
-            f: 0xB0,
-            h: 0x01,
-            l: 0x4D,
+            e,
+            f,
+            h,
+            l,
             sp: 0xFFFE,
             pc: 0x100,
         }
     }
 }
+
+impl SaveState for Registers {
+    fn save_state(&self, state: &mut StateWriter) {
+        let singles = [
+            self.a, self.b, self.c, self.d, self.e, self.f, self.h, self.l,
+        ];
+        for value in singles {
+            state.write_u8(value);
+        }
+        state.write_u16(self.sp);
+        state.write_u16(self.pc);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.a = state.read_u8();
+        self.b = state.read_u8();
+        self.c = state.read_u8();
+        self.d = state.read_u8();
+        self.e = state.read_u8();
+        self.f = state.read_u8();
+        self.h = state.read_u8();
+        self.l = state.read_u8();
+        self.sp = state.read_u16();
+        self.pc = state.read_u16();
+    }
+}
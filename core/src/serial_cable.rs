@@ -1,27 +1,59 @@
 // Gameboy Link Cable
-// Mostly a stub, although some parts have to be emulated *somewhat* accurately
-// to emulate fussy games like Alleyway
+// Transfers go through a SerialLink when something is plugged in. Without
+// one, transfers using the internal clock still have to complete to keep
+// fussy games like Alleyway happy.
 use crate::constants::*;
+use crate::host::SharedHost;
 use crate::interrupts::{InterruptReason, Interrupts};
+use crate::link::SerialLink;
+use crate::state::{SaveState, StateReader, StateWriter};
 
-// Unusual serial code inspired by
-// https://github.com/rvaccarim/FrozenBoy/blob/master/FrozenBoyCore/Serial/SerialLink.cs
+#[cfg(feature = "std")]
+use std::collections::VecDeque;
+
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, collections::VecDeque};
+
+// Clocks per byte with the internal clock: 8 bits at 8192Hz, or 262144Hz
+// when the CGB's fast clock bit is set. Both double in double speed mode,
+// which happens for free as these are counted in CPU cycles.
+const TRANSFER_CLOCKS: usize = 8 * 512;
+const FAST_TRANSFER_CLOCKS: usize = 8 * 16;
+
+// Only the most recent output is kept, so that games which talk over the
+// link port all the time don't use up all our memory
+const OUTPUT_LIMIT: usize = 64 * 1024;
 
 pub struct SerialCable {
+    pub cgb_features: bool,
+
     transfer_data_byte: u8,
     transfer_control_byte: u8,
 
     counter: usize,
+    // Only for transfers using the internal clock. Externally clocked ones
+    // finish whenever the other end says so.
     transfer_in_progress: bool,
+
+    link: Option<Box<dyn SerialLink>>,
+
+    // Every byte that's been sent out, oldest first
+    pub output: VecDeque<u8>,
+
+    host: SharedHost,
 }
 
 impl SerialCable {
     pub fn read(&self, address: u16) -> u8 {
         match address {
-            // When there's no gameboy on the other end, this apparently
-            // just always reads 0xFF
             LINK_CABLE_SB => self.transfer_data_byte,
-            LINK_CABLE_SC => self.transfer_control_byte | 0b1111_1110,
+            LINK_CABLE_SC => {
+                let unused_bits = match self.cgb_features {
+                    true => 0b0111_1100,
+                    false => 0b0111_1110,
+                };
+                self.transfer_control_byte | unused_bits
+            },
             _ => unreachable!(),
         }
     }
@@ -32,36 +64,139 @@ impl SerialCable {
             LINK_CABLE_SC => {
                 self.transfer_control_byte = value;
 
-                if value == 0x81 {
+                if self.is_transfer_requested() && self.uses_internal_clock() {
                     self.transfer_in_progress = true;
                     self.counter = 0;
+                } else {
+                    self.transfer_in_progress = false;
                 }
             },
             _ => unreachable!(),
         }
+
+        self.update_ready();
     }
 
     pub fn step(&mut self, ints: &mut Interrupts, cycles: usize) {
+        // Always poll, so that the other end gets an answer even if this
+        // Gameboy isn't listening
+        let incoming = match self.link.as_mut() {
+            Some(link) => link.poll(cycles),
+            None => None,
+        };
+        if let Some(incoming) = incoming {
+            if self.is_transfer_requested() && !self.uses_internal_clock() {
+                self.finish_transfer(ints, incoming);
+            }
+        }
+
         if !self.transfer_in_progress {
             return;
         }
 
         self.counter += cycles;
 
-        if self.counter >= 512 {
-            self.transfer_in_progress = false;
-            self.transfer_data_byte = 0xFF;
-            ints.raise_interrupt(InterruptReason::Serial);
+        if self.counter >= self.transfer_clocks() {
+            // When there's no gameboy on the other end, this apparently
+            // just always reads 0xFF
+            let incoming = match self.link.as_mut() {
+                Some(link) => link.exchange(self.transfer_data_byte),
+                None => None,
+            };
+            self.finish_transfer(ints, incoming.unwrap_or(0xFF));
         }
     }
 
-    pub fn new() -> SerialCable {
+    fn finish_transfer(&mut self, ints: &mut Interrupts, incoming: u8) {
+        let outgoing = self.transfer_data_byte;
+        if self.output.len() == OUTPUT_LIMIT {
+            self.output.pop_front();
+        }
+        self.output.push_back(outgoing);
+        self.host.borrow_mut().serial(outgoing);
+
+        self.transfer_in_progress = false;
+        self.transfer_data_byte = incoming;
+        self.transfer_control_byte &= 0b0111_1111;
+        ints.raise_interrupt(InterruptReason::Serial);
+        self.update_ready();
+    }
+
+    fn is_transfer_requested(&self) -> bool {
+        self.transfer_control_byte & 0b1000_0000 != 0
+    }
+
+    fn uses_internal_clock(&self) -> bool {
+        self.transfer_control_byte & 0b1 != 0
+    }
+
+    fn transfer_clocks(&self) -> usize {
+        if self.cgb_features && self.transfer_control_byte & 0b10 != 0 {
+            FAST_TRANSFER_CLOCKS
+        } else {
+            TRANSFER_CLOCKS
+        }
+    }
+
+    // Lets the other end know what it'll get if it starts a transfer
+    fn update_ready(&mut self) {
+        let ready =
+            match self.is_transfer_requested() && !self.uses_internal_clock() {
+                true => Some(self.transfer_data_byte),
+                false => None,
+            };
+        if let Some(link) = self.link.as_mut() {
+            link.set_ready(ready);
+        }
+    }
+
+    // Plugs something into the link port, replacing whatever was there
+    pub fn connect(&mut self, link: Box<dyn SerialLink>) {
+        self.link = Some(link);
+        self.update_ready();
+    }
+
+    pub fn disconnect(&mut self) -> Option<Box<dyn SerialLink>> {
+        self.link.take()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.link.is_some()
+    }
+
+    pub fn new(cgb_features: bool, host: SharedHost) -> SerialCable {
         SerialCable {
+            cgb_features,
+
             transfer_data_byte: 0,
             transfer_control_byte: 0,
 
             counter: 0,
             transfer_in_progress: false,
+
+            link: None,
+
+            output: VecDeque::new(),
+
+            host,
         }
     }
 }
+
+impl SaveState for SerialCable {
+    // Whatever's plugged in stays plugged in, and the output isn't rewound
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.transfer_data_byte);
+        state.write_u8(self.transfer_control_byte);
+        state.write_usize(self.counter);
+        state.write_bool(self.transfer_in_progress);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.transfer_data_byte = state.read_u8();
+        self.transfer_control_byte = state.read_u8();
+        self.counter = state.read_usize();
+        self.transfer_in_progress = state.read_bool();
+        self.update_ready();
+    }
+}
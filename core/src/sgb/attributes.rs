@@ -0,0 +1,191 @@
+// Which of the 4 palettes each 8x8 cell of the Gameboy screen is drawn with.
+// The ATTR_* commands paint different shapes onto this map.
+use crate::constants::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::state::{SaveState, StateReader, StateWriter};
+
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+pub const CELLS_WIDE: usize = SCREEN_WIDTH / 8;
+pub const CELLS_HIGH: usize = SCREEN_HEIGHT / 8;
+const CELL_COUNT: usize = CELLS_WIDE * CELLS_HIGH;
+
+// Attribute files are whole maps at 2 bits per cell, sent with ATTR_TRN
+pub const ATTRIBUTE_FILE_SIZE: usize = CELL_COUNT / 4;
+pub const ATTRIBUTE_FILE_COUNT: usize = 45;
+
+pub struct AttributeMap {
+    cells: Vec<u8>,
+}
+
+impl AttributeMap {
+    pub fn palette_at(&self, x: usize, y: usize) -> u8 {
+        self.cells[(y / 8) * CELLS_WIDE + x / 8]
+    }
+
+    fn set(&mut self, x: usize, y: usize, palette: u8) {
+        if x < CELLS_WIDE && y < CELLS_HIGH {
+            self.cells[y * CELLS_WIDE + x] = palette & 0b11;
+        }
+    }
+
+    // ATTR_BLK: rectangles, each with a palette for the cells inside them,
+    // on their edge and outside them
+    pub fn apply_blocks(&mut self, data: &[u8]) {
+        let count = data.first().copied().unwrap_or(0) as usize;
+
+        for block in data[1..].chunks(6).take(count) {
+            if block.len() < 6 {
+                break;
+            }
+            let control = block[0] & 0b111;
+            let palettes = block[1];
+            let (x1, y1) = (block[2] as usize, block[3] as usize);
+            let (x2, y2) = (block[4] as usize, block[5] as usize);
+
+            let inside = palettes & 0b11;
+            let outside = (palettes >> 4) & 0b11;
+            // When only the inside or outside is changed, the edge goes
+            // along with it
+            let edge = match control {
+                0b001 => Some(inside),
+                0b100 => Some(outside),
+                _ if control & 0b010 != 0 => Some((palettes >> 2) & 0b11),
+                _ => None,
+            };
+
+            for y in 0..CELLS_HIGH {
+                for x in 0..CELLS_WIDE {
+                    let in_rect = x >= x1 && x <= x2 && y >= y1 && y <= y2;
+                    let in_middle = x > x1 && x < x2 && y > y1 && y < y2;
+
+                    let palette = if in_middle {
+                        (control & 0b001 != 0).then_some(inside)
+                    } else if in_rect {
+                        edge
+                    } else {
+                        (control & 0b100 != 0).then_some(outside)
+                    };
+                    if let Some(palette) = palette {
+                        self.set(x, y, palette);
+                    }
+                }
+            }
+        }
+    }
+
+    // ATTR_LIN: whole rows or columns
+    pub fn apply_lines(&mut self, data: &[u8]) {
+        let count = data.first().copied().unwrap_or(0) as usize;
+
+        for line in data[1..].iter().take(count) {
+            let position = (line & 0b1_1111) as usize;
+            let palette = (line >> 5) & 0b11;
+            let horizontal = line & 0b1000_0000 != 0;
+
+            if horizontal {
+                for x in 0..CELLS_WIDE {
+                    self.set(x, position, palette);
+                }
+            } else {
+                for y in 0..CELLS_HIGH {
+                    self.set(position, y, palette);
+                }
+            }
+        }
+    }
+
+    // ATTR_DIV: splits the screen in two at a row or column, which gets a
+    // palette of its own
+    pub fn apply_division(&mut self, data: &[u8]) {
+        let (settings, position) = match data {
+            [settings, position, ..] => (*settings, *position as usize),
+            _ => return,
+        };
+        let after = settings & 0b11;
+        let before = (settings >> 2) & 0b11;
+        let on_line = (settings >> 4) & 0b11;
+        let horizontal = settings & 0b0100_0000 != 0;
+
+        for y in 0..CELLS_HIGH {
+            for x in 0..CELLS_WIDE {
+                let along = if horizontal { y } else { x };
+                let palette = match along {
+                    _ if along < position => before,
+                    _ if along == position => on_line,
+                    _ => after,
+                };
+                self.set(x, y, palette);
+            }
+        }
+    }
+
+    // ATTR_CHR: individual cells, 4 to a byte, starting from a given cell
+    pub fn apply_cells(&mut self, data: &[u8]) {
+        if data.len() < 5 {
+            return;
+        }
+        let mut x = data[0] as usize;
+        let mut y = data[1] as usize;
+        let count =
+            (data[2] as usize | ((data[3] as usize) << 8)).min(CELL_COUNT);
+        let top_to_bottom = data[4] & 1 != 0;
+
+        for i in 0..count {
+            let byte = match data.get(5 + i / 4) {
+                Some(byte) => *byte,
+                None => break,
+            };
+            let palette = (byte >> (6 - (i % 4) * 2)) & 0b11;
+            self.set(x, y, palette);
+
+            if top_to_bottom {
+                y += 1;
+                if y == CELLS_HIGH {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == CELLS_WIDE {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    // Replaces the whole map with an attribute file
+    pub fn apply_file(&mut self, file: &[u8]) {
+        for (i, byte) in file.iter().take(ATTRIBUTE_FILE_SIZE).enumerate() {
+            for j in 0..4 {
+                self.cells[i * 4 + j] = (byte >> (6 - j * 2)) & 0b11;
+            }
+        }
+    }
+
+    pub fn new() -> AttributeMap {
+        AttributeMap {
+            cells: vec![0; CELL_COUNT],
+        }
+    }
+}
+
+impl Default for AttributeMap {
+    fn default() -> AttributeMap {
+        AttributeMap::new()
+    }
+}
+
+impl SaveState for AttributeMap {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.cells);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        state.read_bytes_into(&mut self.cells);
+        for cell in self.cells.iter_mut() {
+            *cell &= 0b11;
+        }
+    }
+}
@@ -0,0 +1,161 @@
+// The picture frame drawn around the Gameboy screen. It's made of SNES
+// tiles (4 bits per pixel), uploaded with CHR_TRN, and a 32x28 tile map
+// with its own palettes, uploaded with PCT_TRN.
+use super::{SCREEN_X, SCREEN_Y};
+use crate::colour::colour::Colour;
+use crate::constants::*;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+const TILE_SIZE: usize = 32;
+const TILE_COUNT: usize = 256;
+const MAP_WIDTH: usize = SGB_SCREEN_WIDTH / 8;
+const MAP_HEIGHT: usize = SGB_SCREEN_HEIGHT / 8;
+const MAP_SIZE: usize = MAP_WIDTH * MAP_HEIGHT * 2;
+// Border palettes 4-7, 16 colours each. PCT_TRN leaves a gap between them
+// and the map.
+const PALETTES_START: usize = 0x800;
+const PALETTES_SIZE: usize = 4 * 16 * 2;
+
+pub struct Border {
+    tiles: Vec<u8>,
+    map: Vec<u8>,
+    palettes: Vec<u8>,
+}
+
+impl Border {
+    // CHR_TRN sends half of the tiles at a time
+    pub fn set_tiles(&mut self, upper_half: bool, data: &[u8]) {
+        let half = TILE_COUNT / 2 * TILE_SIZE;
+        let start = if upper_half { half } else { 0 };
+        let length = data.len().min(half);
+        self.tiles[start..start + length].copy_from_slice(&data[..length]);
+    }
+
+    pub fn set_map(&mut self, data: &[u8]) {
+        let length = data.len().min(MAP_SIZE);
+        self.map[..length].copy_from_slice(&data[..length]);
+        if data.len() > PALETTES_START {
+            let length = (data.len() - PALETTES_START).min(PALETTES_SIZE);
+            self.palettes[..length].copy_from_slice(
+                &data[PALETTES_START..PALETTES_START + length],
+            );
+        }
+    }
+
+    fn palette_colour(&self, palette: usize, colour_id: usize) -> Colour {
+        let i = (palette * 16 + colour_id) * 2;
+        let raw =
+            self.palettes[i] as u16 | ((self.palettes[i + 1] as u16) << 8);
+        Colour::from_16_bit_colour(raw)
+    }
+
+    // Draws the border, leaving the Gameboy screen in the middle alone.
+    // Transparent pixels get the backdrop.
+    pub fn draw(&self, frame: &mut [Colour], backdrop: Colour) {
+        for map_y in 0..MAP_HEIGHT {
+            for map_x in 0..MAP_WIDTH {
+                let i = (map_y * MAP_WIDTH + map_x) * 2;
+                let entry =
+                    self.map[i] as u16 | ((self.map[i + 1] as u16) << 8);
+
+                let tile = (entry & 0xFF) as usize;
+                // Only palettes 4-7 can be used for the border
+                let palette = ((entry >> 10) & 0b11) as usize;
+                let x_flip = entry & 0x4000 != 0;
+                let y_flip = entry & 0x8000 != 0;
+
+                let tile_data =
+                    &self.tiles[tile * TILE_SIZE..(tile + 1) * TILE_SIZE];
+                for row in 0..8 {
+                    let tile_row = if y_flip { 7 - row } else { row };
+                    let planes = [
+                        tile_data[tile_row * 2],
+                        tile_data[tile_row * 2 + 1],
+                        tile_data[16 + tile_row * 2],
+                        tile_data[16 + tile_row * 2 + 1],
+                    ];
+
+                    for column in 0..8 {
+                        let bit = if x_flip { column } else { 7 - column };
+                        let colour_id = planes
+                            .iter()
+                            .enumerate()
+                            .map(|(plane, byte)| ((byte >> bit) & 1) << plane)
+                            .sum::<u8>()
+                            as usize;
+
+                        let x = map_x * 8 + column;
+                        let y = map_y * 8 + row;
+                        if is_on_screen(x, y) {
+                            continue;
+                        }
+
+                        let colour = match colour_id {
+                            0 => backdrop,
+                            _ => self.palette_colour(palette, colour_id),
+                        };
+                        frame[y * SGB_SCREEN_WIDTH + x] = colour;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn new() -> Border {
+        Border {
+            tiles: vec![0; TILE_COUNT * TILE_SIZE],
+            map: vec![0; MAP_SIZE],
+            palettes: vec![0; PALETTES_SIZE],
+        }
+    }
+}
+
+impl Default for Border {
+    fn default() -> Border {
+        Border::new()
+    }
+}
+
+fn is_on_screen(x: usize, y: usize) -> bool {
+    (SCREEN_X..SCREEN_X + SCREEN_WIDTH).contains(&x)
+        && (SCREEN_Y..SCREEN_Y + SCREEN_HEIGHT).contains(&y)
+}
+
+impl SaveState for Border {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.tiles);
+        state.write_bytes(&self.map);
+        state.write_bytes(&self.palettes);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        state.read_bytes_into(&mut self.tiles);
+        state.read_bytes_into(&mut self.map);
+        state.read_bytes_into(&mut self.palettes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palettes_come_after_the_gap() {
+        // A whole PCT_TRN, with junk in the unused part after the map
+        let mut data = vec![0; 4096];
+        data[MAP_SIZE..PALETTES_START].fill(0xFF);
+        // Palette 5, colour 1 is pure blue
+        let i = PALETTES_START + (16 + 1) * 2;
+        data[i..i + 2].copy_from_slice(&0x7C00u16.to_le_bytes());
+
+        let mut border = Border::new();
+        border.set_map(&data);
+        let colour = border.palette_colour(1, 1);
+        assert_eq!((colour.red, colour.green, colour.blue), (0, 0, 255));
+        let colour = border.palette_colour(0, 1);
+        assert_eq!((colour.red, colour.green, colour.blue), (0, 0, 0));
+    }
+}
@@ -0,0 +1,495 @@
+// Super Gameboy
+// Games talk to the SGB by sending packets through the joypad register, and
+// bulk data (palettes, border tiles, ...) by putting it on the screen for a
+// frame. The SGB colours the Gameboy's 4 shades in with up to 4 palettes,
+// chosen per 8x8 cell, and draws a border around the screen.
+// Details from https://gbdev.io/pandocs/SGB_Functions.html
+pub mod attributes;
+pub mod border;
+pub mod packet_receiver;
+
+use crate::colour::colour::Colour;
+use crate::colour::grey_shades::grey_shade_id_from_colour;
+use crate::constants::*;
+use crate::host::SharedHost;
+use crate::joypad::Joypad;
+use crate::log;
+use crate::state::{SaveState, StateReader, StateWriter};
+use attributes::{
+    AttributeMap, ATTRIBUTE_FILE_COUNT, ATTRIBUTE_FILE_SIZE, CELLS_WIDE,
+};
+use border::Border;
+use packet_receiver::{PacketReceiver, PACKET_SIZE};
+
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+const COMMAND_PAL01: u8 = 0x00;
+const COMMAND_PAL23: u8 = 0x01;
+const COMMAND_PAL03: u8 = 0x02;
+const COMMAND_PAL12: u8 = 0x03;
+const COMMAND_ATTR_BLK: u8 = 0x04;
+const COMMAND_ATTR_LIN: u8 = 0x05;
+const COMMAND_ATTR_DIV: u8 = 0x06;
+const COMMAND_ATTR_CHR: u8 = 0x07;
+const COMMAND_PAL_SET: u8 = 0x0A;
+const COMMAND_PAL_TRN: u8 = 0x0B;
+const COMMAND_MLT_REQ: u8 = 0x11;
+const COMMAND_CHR_TRN: u8 = 0x13;
+const COMMAND_PCT_TRN: u8 = 0x14;
+const COMMAND_ATTR_TRN: u8 = 0x15;
+const COMMAND_ATTR_SET: u8 = 0x16;
+const COMMAND_MASK_EN: u8 = 0x17;
+
+// Where the Gameboy screen goes within the border
+pub const SCREEN_X: usize = (SGB_SCREEN_WIDTH - SCREEN_WIDTH) / 2;
+pub const SCREEN_Y: usize = (SGB_SCREEN_HEIGHT - SCREEN_HEIGHT) / 2;
+
+// *_TRN commands send this much data, as 256 tiles on the screen
+const TRANSFER_SIZE: usize = 4096;
+// PAL_TRN fills this many 4-colour palettes, which PAL_SET picks from
+const SYSTEM_PALETTE_COUNT: usize = 512;
+
+// What the SGB shows before a game sets its own palettes
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MaskMode {
+    None,
+    // Keep showing the last frame
+    Freeze,
+    Black,
+    // Fill the screen with the shared colour 0
+    Colour0,
+}
+
+impl From<u8> for MaskMode {
+    fn from(value: u8) -> MaskMode {
+        match value & 0b11 {
+            1 => MaskMode::Freeze,
+            2 => MaskMode::Black,
+            3 => MaskMode::Colour0,
+            _ => MaskMode::None,
+        }
+    }
+}
+
+impl From<MaskMode> for u8 {
+    fn from(mask: MaskMode) -> u8 {
+        match mask {
+            MaskMode::None => 0,
+            MaskMode::Freeze => 1,
+            MaskMode::Black => 2,
+            MaskMode::Colour0 => 3,
+        }
+    }
+}
+
+// Data that'll be taken from the next frame
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Transfer {
+    Palettes,
+    Attributes,
+    BorderTiles { upper_half: bool },
+    BorderMap,
+}
+
+impl Transfer {
+    fn id(transfer: Option<Transfer>) -> u8 {
+        match transfer {
+            None => 0,
+            Some(Transfer::Palettes) => 1,
+            Some(Transfer::Attributes) => 2,
+            Some(Transfer::BorderTiles { upper_half: false }) => 3,
+            Some(Transfer::BorderTiles { upper_half: true }) => 4,
+            Some(Transfer::BorderMap) => 5,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Transfer> {
+        match id {
+            1 => Some(Transfer::Palettes),
+            2 => Some(Transfer::Attributes),
+            3 => Some(Transfer::BorderTiles { upper_half: false }),
+            4 => Some(Transfer::BorderTiles { upper_half: true }),
+            5 => Some(Transfer::BorderMap),
+            _ => None,
+        }
+    }
+}
+
+pub struct Sgb {
+    receiver: PacketReceiver,
+    // The packets of a command that's still arriving
+    command: Vec<u8>,
+    packets_left: usize,
+
+    // Colour 0 of palette 0 is used as colour 0 by all of them
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<u8>,
+    attribute_files: Vec<u8>,
+    attributes: AttributeMap,
+    mask: MaskMode,
+    pending_transfer: Option<Transfer>,
+
+    border: Border,
+    border_needs_drawing: bool,
+
+    // 1, 2 or 4, set by MLT_REQ
+    player_count: u8,
+    current_player: u8,
+    last_p1: u8,
+    // Buttons held by players 2-4 in multiplayer games, as
+    // Joypad::buttons_bitmask. Player 1 uses the Joypad as usual.
+    pub other_players: [u8; 3],
+
+    // The border with the colourised Gameboy screen in the middle. This is
+    // updated every VBlank.
+    pub frame: Vec<Colour>,
+
+    host: SharedHost,
+}
+
+impl Sgb {
+    pub fn write_p1(&mut self, value: u8) {
+        // In multiplayer mode, letting P15 go high moves on to the next
+        // player's joypad
+        let p15_rising =
+            self.last_p1 & 0b0010_0000 == 0 && value & 0b0010_0000 != 0;
+        if p15_rising && self.player_count > 1 {
+            self.current_player = (self.current_player + 1) % self.player_count;
+        }
+        self.last_p1 = value;
+
+        if let Some(packet) = self.receiver.write(value) {
+            self.receive_packet(&packet);
+        }
+    }
+
+    pub fn read_p1(&self, joypad: &Joypad) -> u8 {
+        match self.current_player {
+            0 => joypad.read(),
+            player => joypad.read_as_player(
+                player,
+                self.other_players[player as usize - 1],
+            ),
+        }
+    }
+
+    fn receive_packet(&mut self, packet: &[u8; PACKET_SIZE]) {
+        if self.packets_left == 0 {
+            // The first packet says how many make up the command
+            self.packets_left = (packet[0] & 0b111) as usize;
+            self.command.clear();
+            if self.packets_left == 0 {
+                return;
+            }
+        }
+
+        self.command.extend_from_slice(packet);
+        self.packets_left -= 1;
+        if self.packets_left == 0 {
+            let command = core::mem::take(&mut self.command);
+            self.run_command(command[0] >> 3, &command[1..]);
+            self.command = command;
+        }
+    }
+
+    fn run_command(&mut self, command: u8, data: &[u8]) {
+        match command {
+            COMMAND_PAL01 => self.set_palette_pair(0, 1, data),
+            COMMAND_PAL23 => self.set_palette_pair(2, 3, data),
+            COMMAND_PAL03 => self.set_palette_pair(0, 3, data),
+            COMMAND_PAL12 => self.set_palette_pair(1, 2, data),
+
+            COMMAND_ATTR_BLK => self.attributes.apply_blocks(data),
+            COMMAND_ATTR_LIN => self.attributes.apply_lines(data),
+            COMMAND_ATTR_DIV => self.attributes.apply_division(data),
+            COMMAND_ATTR_CHR => self.attributes.apply_cells(data),
+
+            COMMAND_PAL_SET => {
+                for i in 0..4 {
+                    let id = read_u16(data, i * 2) as usize;
+                    self.palettes[i] = self.system_palette(id);
+                }
+                self.share_colour_0(self.palettes[0][0]);
+
+                let attribute_settings = data[8];
+                if attribute_settings & 0b1000_0000 != 0 {
+                    self.apply_attribute_file(attribute_settings & 0b11_1111);
+                }
+                if attribute_settings & 0b0100_0000 != 0 {
+                    self.mask = MaskMode::None;
+                }
+            },
+            COMMAND_ATTR_SET => {
+                self.apply_attribute_file(data[0] & 0b11_1111);
+                if data[0] & 0b0100_0000 != 0 {
+                    self.mask = MaskMode::None;
+                }
+            },
+
+            COMMAND_PAL_TRN => self.pending_transfer = Some(Transfer::Palettes),
+            COMMAND_ATTR_TRN => {
+                self.pending_transfer = Some(Transfer::Attributes)
+            },
+            COMMAND_CHR_TRN => {
+                self.pending_transfer = Some(Transfer::BorderTiles {
+                    upper_half: data[0] & 1 != 0,
+                })
+            },
+            COMMAND_PCT_TRN => {
+                self.pending_transfer = Some(Transfer::BorderMap)
+            },
+
+            COMMAND_MASK_EN => self.mask = MaskMode::from(data[0]),
+            COMMAND_MLT_REQ => {
+                self.player_count = match data[0] & 0b11 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.current_player = 0;
+            },
+
+            // Sound, SNES code uploads and the like
+            _ => log!(
+                self.host,
+                "[WARN] Unsupported SGB command {:#04x}",
+                command
+            ),
+        }
+    }
+
+    // PAL01, PAL23 etc. set colour 0 and colours 1-3 of two palettes
+    fn set_palette_pair(&mut self, first: usize, second: usize, data: &[u8]) {
+        for i in 0..3 {
+            self.palettes[first][i + 1] = read_u16(data, 2 + i * 2);
+            self.palettes[second][i + 1] = read_u16(data, 8 + i * 2);
+        }
+        self.share_colour_0(read_u16(data, 0));
+    }
+
+    fn share_colour_0(&mut self, colour: u16) {
+        for palette in self.palettes.iter_mut() {
+            palette[0] = colour;
+        }
+        // Transparent parts of the border show colour 0
+        self.border_needs_drawing = true;
+    }
+
+    fn system_palette(&self, id: usize) -> [u16; 4] {
+        let start = (id % SYSTEM_PALETTE_COUNT) * 8;
+        let mut palette = [0; 4];
+        for (i, colour) in palette.iter_mut().enumerate() {
+            *colour = read_u16(&self.system_palettes, start + i * 2);
+        }
+        palette
+    }
+
+    fn apply_attribute_file(&mut self, id: u8) {
+        let id = id as usize;
+        if id >= ATTRIBUTE_FILE_COUNT {
+            return;
+        }
+        let start = id * ATTRIBUTE_FILE_SIZE;
+        self.attributes.apply_file(
+            &self.attribute_files[start..start + ATTRIBUTE_FILE_SIZE],
+        );
+    }
+
+    fn run_transfer(&mut self, transfer: Transfer, data: &[u8]) {
+        match transfer {
+            Transfer::Palettes => self.system_palettes.copy_from_slice(data),
+            Transfer::Attributes => {
+                let length = self.attribute_files.len();
+                self.attribute_files.copy_from_slice(&data[..length]);
+            },
+            Transfer::BorderTiles { upper_half } => {
+                self.border.set_tiles(upper_half, data);
+                self.border_needs_drawing = true;
+            },
+            Transfer::BorderMap => {
+                self.border.set_map(data);
+                self.border_needs_drawing = true;
+            },
+        }
+    }
+
+    // Called with each frame the Gameboy finishes
+    pub fn vblank(&mut self, screen: &[Colour]) {
+        if let Some(transfer) = self.pending_transfer.take() {
+            self.run_transfer(transfer, &transfer_data_from_screen(screen));
+        }
+
+        if self.border_needs_drawing {
+            let backdrop = Colour::from_16_bit_colour(self.palettes[0][0]);
+            self.border.draw(&mut self.frame, backdrop);
+            self.border_needs_drawing = false;
+        }
+
+        self.draw_screen(screen);
+    }
+
+    fn draw_screen(&mut self, screen: &[Colour]) {
+        if self.mask == MaskMode::Freeze {
+            return;
+        }
+
+        let mut colours = [[Colour::new(0, 0, 0); 4]; 4];
+        for (palette, raw_palette) in colours.iter_mut().zip(self.palettes) {
+            for (colour, raw_colour) in palette.iter_mut().zip(raw_palette) {
+                *colour = Colour::from_16_bit_colour(raw_colour);
+            }
+        }
+
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let colour = match self.mask {
+                    MaskMode::Black => Colour::new(0, 0, 0),
+                    MaskMode::Colour0 => colours[0][0],
+                    _ => {
+                        let shade = grey_shade_id_from_colour(
+                            screen[y * SCREEN_WIDTH + x],
+                        );
+                        let palette = self.attributes.palette_at(x, y);
+                        colours[palette as usize][shade as usize]
+                    },
+                };
+                let i = (y + SCREEN_Y) * SGB_SCREEN_WIDTH + x + SCREEN_X;
+                self.frame[i] = colour;
+            }
+        }
+    }
+
+    pub fn get_rgba_frame(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(SGB_SCREEN_BUFFER_SIZE * 4);
+        for colour in &self.frame {
+            out.extend_from_slice(&[
+                colour.red,
+                colour.green,
+                colour.blue,
+                0xFF,
+            ]);
+        }
+        out
+    }
+
+    pub fn new(host: SharedHost) -> Sgb {
+        Sgb {
+            receiver: PacketReceiver::new(),
+            command: Vec::with_capacity(PACKET_SIZE * 7),
+            packets_left: 0,
+
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![0; SYSTEM_PALETTE_COUNT * 8],
+            attribute_files: vec![
+                0;
+                ATTRIBUTE_FILE_COUNT * ATTRIBUTE_FILE_SIZE
+            ],
+            attributes: AttributeMap::new(),
+            mask: MaskMode::None,
+            pending_transfer: None,
+
+            border: Border::new(),
+            border_needs_drawing: true,
+
+            player_count: 1,
+            current_player: 0,
+            last_p1: 0xFF,
+            other_players: [0; 3],
+
+            frame: vec![Colour::new(0, 0, 0); SGB_SCREEN_BUFFER_SIZE],
+
+            host,
+        }
+    }
+}
+
+fn read_u16(data: &[u8], i: usize) -> u16 {
+    let low = data.get(i).copied().unwrap_or(0) as u16;
+    let high = data.get(i + 1).copied().unwrap_or(0) as u16;
+    low | (high << 8)
+}
+
+// Transfers read the first 256 tiles on the screen (20 to a row) as if they
+// were 2 bits per pixel tile data
+fn transfer_data_from_screen(screen: &[Colour]) -> Vec<u8> {
+    let mut data = vec![0; TRANSFER_SIZE];
+
+    for (tile, tile_data) in data.chunks_mut(16).enumerate() {
+        let tile_x = (tile % CELLS_WIDE) * 8;
+        let tile_y = (tile / CELLS_WIDE) * 8;
+
+        for row in 0..8 {
+            for column in 0..8 {
+                let pixel =
+                    screen[(tile_y + row) * SCREEN_WIDTH + tile_x + column];
+                let shade = grey_shade_id_from_colour(pixel);
+                let bit = 7 - column;
+                tile_data[row * 2] |= (shade & 1) << bit;
+                tile_data[row * 2 + 1] |= (shade >> 1) << bit;
+            }
+        }
+    }
+
+    data
+}
+
+impl SaveState for Sgb {
+    // The other players' buttons are input from the port, so aren't saved
+    fn save_state(&self, state: &mut StateWriter) {
+        self.receiver.save_state(state);
+        state.write_bytes(&self.command);
+        state.write_usize(self.packets_left);
+
+        for palette in &self.palettes {
+            for colour in palette {
+                state.write_u16(*colour);
+            }
+        }
+        state.write_bytes(&self.system_palettes);
+        state.write_bytes(&self.attribute_files);
+        self.attributes.save_state(state);
+        state.write_u8(u8::from(self.mask));
+        state.write_u8(Transfer::id(self.pending_transfer));
+
+        self.border.save_state(state);
+
+        state.write_u8(self.player_count);
+        state.write_u8(self.current_player);
+        state.write_u8(self.last_p1);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.receiver.load_state(state);
+        self.command = state.read_bytes();
+        self.packets_left = state.read_usize();
+        if self.command.len() + self.packets_left * PACKET_SIZE
+            > PACKET_SIZE * 7
+        {
+            state.corrupt = true;
+        }
+
+        for palette in self.palettes.iter_mut() {
+            for colour in palette.iter_mut() {
+                *colour = state.read_u16();
+            }
+        }
+        state.read_bytes_into(&mut self.system_palettes);
+        state.read_bytes_into(&mut self.attribute_files);
+        self.attributes.load_state(state);
+        self.mask = MaskMode::from(state.read_u8());
+        self.pending_transfer = Transfer::from_id(state.read_u8());
+
+        self.border.load_state(state);
+        self.border_needs_drawing = true;
+
+        self.player_count = match state.read_u8() {
+            count @ (1 | 2 | 4) => count,
+            _ => 1,
+        };
+        self.current_player = state.read_u8() % self.player_count;
+        self.last_p1 = state.read_u8();
+    }
+}
@@ -0,0 +1,88 @@
+// Super Gameboy packets are sent a bit at a time through the joypad register.
+// Pulling both P14 and P15 low starts a packet, then each bit is sent by
+// pulling one of them low (P14 for 0, P15 for 1) and letting it go again.
+// 128 bits later (least significant first) a 0 stop bit ends the packet.
+use crate::state::{SaveState, StateReader, StateWriter};
+
+pub const PACKET_SIZE: usize = 16;
+
+const LINES_MASK: u8 = 0b0011_0000;
+const BOTH_HIGH: u8 = 0b0011_0000;
+const P15_LOW: u8 = 0b0001_0000;
+
+pub struct PacketReceiver {
+    receiving: bool,
+    bits_received: usize,
+    packet: [u8; PACKET_SIZE],
+    last_lines: u8,
+}
+
+impl PacketReceiver {
+    // Returns the packet once its stop bit has arrived
+    pub fn write(&mut self, value: u8) -> Option<[u8; PACKET_SIZE]> {
+        let lines = value & LINES_MASK;
+        let previous_lines = self.last_lines;
+        self.last_lines = lines;
+
+        if lines == 0 {
+            self.receiving = true;
+            self.bits_received = 0;
+            self.packet = [0; PACKET_SIZE];
+            return None;
+        }
+
+        // A bit is a pulse on one line, starting from both being high
+        if !self.receiving || previous_lines != BOTH_HIGH || lines == BOTH_HIGH
+        {
+            return None;
+        }
+        let bit = lines == P15_LOW;
+
+        if self.bits_received == PACKET_SIZE * 8 {
+            self.receiving = false;
+            return match bit {
+                false => Some(self.packet),
+                // Not a valid stop bit, so the packet's thrown away
+                true => None,
+            };
+        }
+
+        if bit {
+            self.packet[self.bits_received / 8] |=
+                1 << (self.bits_received % 8);
+        }
+        self.bits_received += 1;
+        None
+    }
+
+    pub fn new() -> PacketReceiver {
+        PacketReceiver {
+            receiving: false,
+            bits_received: 0,
+            packet: [0; PACKET_SIZE],
+            last_lines: BOTH_HIGH,
+        }
+    }
+}
+
+impl Default for PacketReceiver {
+    fn default() -> PacketReceiver {
+        PacketReceiver::new()
+    }
+}
+
+impl SaveState for PacketReceiver {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.receiving);
+        state.write_usize(self.bits_received);
+        state.write_bytes(&self.packet);
+        state.write_u8(self.last_lines);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.receiving = state.read_bool();
+        self.bits_received = state.read_usize().min(PACKET_SIZE * 8);
+        state.read_bytes_into(&mut self.packet);
+        self.last_lines = state.read_u8();
+    }
+}
@@ -2,12 +2,25 @@ use super::channel1::APUChannel1;
 use super::channel2::APUChannel2;
 use super::channel3::APUChannel3;
 use super::channel4::APUChannel4;
+use super::mixer::{Channel, Mixer};
 use super::registers::*;
+use super::stereo_output::StereoOutput;
 use crate::constants::*;
 
+use crate::state::{SaveState, StateReader, StateWriter};
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+// How many clocks of amplitude changes we collect before they're turned into
+// output samples. Roughly 1ms.
+const BLIP_FRAME_CLOCKS: u32 = 4096;
+
 pub trait APUChannel {
     fn step(&mut self);
     fn sample(&self) -> f32;
+    // What's going into the channel's DAC, 0-15. Games can read these
+    // through PCM12 and PCM34.
+    fn amplitude(&self) -> u8;
     fn read(&self, address: u16) -> u8;
     fn write(&mut self, address: u16, value: u8);
 }
@@ -27,12 +40,25 @@ pub struct APU {
     pub channel3: APUChannel3,
     pub channel4: APUChannel4,
 
-    pub sample_counter: usize,
-    // This could be a Vec that we check len() against, but we can save the
-    // allocation because we know the size it's always going to be.
-    pub buffer: [i16; SOUND_BUFFER_SIZE],
-    pub buffer_idx: usize,
-    pub buffer_full: bool,
+    pub mixer: Mixer,
+
+    pub sample_rate: usize,
+    // The amount of samples (left and right counted separately) that a port
+    // wants to receive at once
+    pub buffer_size: usize,
+    cgb_features: bool,
+
+    // Clocks since the current blip frame started
+    frame_clocks: u32,
+    // What the channels were putting out when they were last mixed. Most
+    // clocks, nothing changes, and there's nothing to hand to blip_buf.
+    last_channel_samples: [f32; 4],
+    // Set when NR50, NR51 or the tracks change, which alters the mix even if
+    // the channels don't
+    mix_changed: bool,
+    output: StereoOutput,
+    // One output per channel when multi-track mode is on, otherwise empty
+    tracks: Vec<StereoOutput>,
 }
 
 impl APU {
@@ -42,70 +68,147 @@ impl APU {
         self.channel3.step();
         self.channel4.step();
 
-        self.sample_counter += 1;
+        self.sample();
 
-        if self.sample_counter == APU_SAMPLE_CLOCKS {
-            self.sample_counter = 0;
-            self.sample();
+        self.frame_clocks += 1;
+        if self.frame_clocks == BLIP_FRAME_CLOCKS {
+            self.output.end_frame(self.frame_clocks);
+            for track in self.tracks.iter_mut() {
+                track.end_frame(self.frame_clocks);
+            }
+            self.frame_clocks = 0;
         }
     }
 
+    // Hands the channels' current output to the band-limited synthesis
+    // buffers if it's changed since last time
     pub fn sample(&mut self) {
+        let channel_samples = [
+            self.channel1.sample(),
+            self.channel2.sample(),
+            self.channel3.sample(),
+            self.channel4.sample(),
+        ];
+
+        let mixer_changed = self.mixer.take_changed();
+        if mixer_changed
+            || self.mix_changed
+            || channel_samples != self.last_channel_samples
+        {
+            self.mix(channel_samples);
+        }
+    }
+
+    fn mix(&mut self, channel_samples: [f32; 4]) {
+        self.last_channel_samples = channel_samples;
+        self.mix_changed = false;
+
         let mut left_sample = 0.;
         let mut right_sample = 0.;
 
-        // TODO: Maybe we could generate these with a macro?
-        let chan1 = self.channel1.sample();
-        if self.stereo_panning.channel1_left {
-            left_sample += chan1;
-        }
-        if self.stereo_panning.channel1_right {
-            right_sample += chan1;
+        for (channel, sample) in Channel::ALL.into_iter().zip(channel_samples) {
+            let (to_left, to_right) = self.stereo_panning.channel(channel);
+
+            // Average the 4 channels, and adjust for soft-panning
+            let level = sample / 4. * self.mixer.channel(channel).gain;
+            let left = if to_left {
+                level * self.stereo_left_volume
+            } else {
+                0.
+            };
+            let right = if to_right {
+                level * self.stereo_right_volume
+            } else {
+                0.
+            };
+
+            // Tracks ignore mute and solo, so that listening to one channel
+            // doesn't stop the others from being recorded
+            if let Some(track) = self.tracks.get_mut(channel.index()) {
+                track.set_level(self.frame_clocks, left, right);
+            }
+
+            if self.mixer.channel_gain(channel) != 0. {
+                left_sample += left;
+                right_sample += right;
+            }
         }
 
-        let chan2 = self.channel2.sample();
-        if self.stereo_panning.channel2_left {
-            left_sample += chan2;
-        }
-        if self.stereo_panning.channel2_right {
-            right_sample += chan2;
-        }
+        self.output
+            .set_level(self.frame_clocks, left_sample, right_sample);
+    }
 
-        let chan3 = self.channel3.sample();
-        if self.stereo_panning.channel3_left {
-            left_sample += chan3;
-        }
-        if self.stereo_panning.channel3_right {
-            right_sample += chan3;
+    // Has the APU generated at least a port's buffer worth of samples?
+    pub fn is_buffer_full(&self) -> bool {
+        self.output.samples_available() >= self.buffer_size
+    }
+
+    pub fn samples_available(&self) -> usize {
+        self.output.samples_available()
+    }
+
+    // Moves as many generated samples as will fit into the port's buffer,
+    // returning how many were written. Samples are interleaved stereo
+    // (left, right, left, ...).
+    pub fn read_samples(&mut self, out: &mut [i16]) -> usize {
+        self.output.read_samples(out)
+    }
+
+    // Throws away everything that hasn't been read, for when a port isn't
+    // going to play it (like while fast-forwarding)
+    pub fn discard_samples(&mut self) {
+        self.output.discard_samples();
+        for track in self.tracks.iter_mut() {
+            track.discard_samples();
         }
+    }
 
-        let chan4 = self.channel4.sample();
-        if self.stereo_panning.channel4_left {
-            left_sample += chan4;
+    // Multi-track mode additionally outputs each channel as its own
+    // stereo stream, read with read_track_samples. Each track has the
+    // channel's panning, master volume and mixer gain applied, so the four
+    // tracks add up to the mixed output when nothing is muted or soloed.
+    pub fn set_multi_track(&mut self, enabled: bool) {
+        if !enabled {
+            self.tracks.clear();
+            return;
         }
-        if self.stereo_panning.channel4_right {
-            right_sample += chan4;
+        if self.tracks.is_empty() {
+            self.mix_changed = true;
+            for _ in 0..4 {
+                self.tracks.push(StereoOutput::new(
+                    self.sample_rate,
+                    self.buffer_size,
+                    self.cgb_features,
+                ));
+            }
         }
+    }
 
-        // Average the 4 channels
-        left_sample /= 4.;
-        right_sample /= 4.;
-
-        // Adjust for soft-panning
-        left_sample *= self.stereo_left_volume;
-        right_sample *= self.stereo_right_volume;
-
-        let left_sample_int = (left_sample * 30_000.) as i16;
-        let right_sample_int = (right_sample * 30_000.) as i16;
+    pub fn is_multi_track(&self) -> bool {
+        !self.tracks.is_empty()
+    }
 
-        self.buffer[self.buffer_idx] = left_sample_int;
-        self.buffer_idx += 1;
-        self.buffer[self.buffer_idx] = right_sample_int;
-        self.buffer_idx += 1;
+    // Like read_samples, but for a single channel's track. Tracks produce
+    // samples in step with the mixed output, so reading the same amount
+    // from each after read_samples keeps them aligned.
+    pub fn read_track_samples(
+        &mut self,
+        channel: Channel,
+        out: &mut [i16],
+    ) -> usize {
+        match self.tracks.get_mut(channel.index()) {
+            Some(track) => track.read_samples(out),
+            None => 0,
+        }
+    }
 
-        if self.buffer_idx == SOUND_BUFFER_SIZE {
-            self.buffer_idx = 0;
-            self.buffer_full = true;
+    // Changes the output sample rate. Any samples that are already waiting
+    // to be read are kept as they are.
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        self.sample_rate = sample_rate;
+        self.output.set_sample_rate(sample_rate);
+        for track in self.tracks.iter_mut() {
+            track.set_sample_rate(sample_rate);
         }
     }
 
@@ -137,8 +240,14 @@ impl APU {
         return;
 
         match address {
-            0xFF24 => self.deserialise_nr50(value),
-            0xFF25 => self.stereo_panning = StereoPanning::from(value),
+            0xFF24 => {
+                self.deserialise_nr50(value);
+                self.mix_changed = true;
+            },
+            0xFF25 => {
+                self.stereo_panning = StereoPanning::from(value);
+                self.mix_changed = true;
+            },
             0xFF26 => self.sound_on_register = value,
 
             0xFF10..=0xFF14 => self.channel1.write(address, value),
@@ -153,6 +262,16 @@ impl APU {
         }
     }
 
+    // PCM12 (0xFF76): channels 1 & 2's current amplitudes, one per nibble
+    pub fn read_pcm12(&self) -> u8 {
+        self.channel1.amplitude() | (self.channel2.amplitude() << 4)
+    }
+
+    // PCM34 (0xFF77): channels 3 & 4
+    pub fn read_pcm34(&self) -> u8 {
+        self.channel3.amplitude() | (self.channel4.amplitude() << 4)
+    }
+
     // NOTE: These functions don't take into account the
     //       Vin output flags. That feature is unused in all
     //       commercial Gameboy games, so we ignore it.
@@ -172,7 +291,11 @@ impl APU {
         (left_vol << 4) & right_vol
     }
 
-    pub fn new() -> APU {
+    pub fn new(
+        sample_rate: usize,
+        buffer_size: usize,
+        cgb_features: bool,
+    ) -> APU {
         APU {
             // These might be meant to start 0, not sure
             stereo_left_volume: 1.,
@@ -185,10 +308,44 @@ impl APU {
             channel3: APUChannel3::new(),
             channel4: APUChannel4::new(),
 
-            sample_counter: 0,
-            buffer: [0; SOUND_BUFFER_SIZE],
-            buffer_idx: 0,
-            buffer_full: false,
+            mixer: Mixer::new(),
+
+            sample_rate,
+            buffer_size,
+            cgb_features,
+
+            frame_clocks: 0,
+            last_channel_samples: [0.; 4],
+            mix_changed: true,
+            output: StereoOutput::new(sample_rate, buffer_size, cgb_features),
+            tracks: vec![],
         }
     }
 }
+
+impl SaveState for APU {
+    // The mixer settings and output buffers belong to the port rather than
+    // the emulated Gameboy, so they aren't saved
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_f32(self.stereo_left_volume);
+        state.write_f32(self.stereo_right_volume);
+        state.write_u8(u8::from(self.stereo_panning.clone()));
+        state.write_u8(self.sound_on_register);
+        self.channel1.save_state(state);
+        self.channel2.save_state(state);
+        self.channel3.save_state(state);
+        self.channel4.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.stereo_left_volume = state.read_f32();
+        self.stereo_right_volume = state.read_f32();
+        self.stereo_panning = StereoPanning::from(state.read_u8());
+        self.sound_on_register = state.read_u8();
+        self.channel1.load_state(state);
+        self.channel2.load_state(state);
+        self.channel3.load_state(state);
+        self.channel4.load_state(state);
+        self.mix_changed = true;
+    }
+}
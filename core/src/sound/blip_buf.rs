@@ -0,0 +1,210 @@
+// Band-limited sound synthesis, ported from Shay Green's blip_buf.
+// Instead of point-sampling the APU output (which aliases horribly for the
+// Gameboy's square waves), we record every change in amplitude at the
+// exact clock it happened, and blip_buf turns those steps into properly
+// band-limited samples at whatever output rate we like.
+// http://www.slack.net/~ant/libs/audio.html#blip_buf
+
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+// Fixed-point time is 64 bits, with the lower TIME_BITS being a fraction of
+// an output sample.
+const PRE_SHIFT: u32 = 32;
+const TIME_BITS: u32 = PRE_SHIFT + 20;
+const TIME_UNIT: u64 = 1 << TIME_BITS;
+
+// Higher values here simply remove less bass
+const BASS_SHIFT: u32 = 9;
+const END_FRAME_EXTRA: usize = 2;
+
+const HALF_WIDTH: usize = 8;
+const BUF_EXTRA: usize = HALF_WIDTH * 2 + END_FRAME_EXTRA;
+const PHASE_BITS: u32 = 5;
+const PHASE_COUNT: usize = 1 << PHASE_BITS;
+const DELTA_BITS: u32 = 15;
+const DELTA_UNIT: i64 = 1 << DELTA_BITS;
+const FRAC_BITS: u32 = TIME_BITS - PRE_SHIFT;
+
+// Half of a 16-tap band-limited step for each of the 32 sub-sample phases.
+// Row N and row (32 - N) reversed make up the full kernel for phase N, and
+// each kernel sums to exactly DELTA_UNIT so that nothing drifts.
+// Generated from a Blackman-windowed sinc with a cutoff of 0.45x the output
+// sample rate.
+const BL_STEP: [[i16; HALF_WIDTH]; PHASE_COUNT + 1] = [
+    [18, -110, 359, -843, 1561, -2371, 3025, 29490],
+    [17, -108, 347, -795, 1421, -2025, 2117, 29452],
+    [17, -105, 332, -742, 1276, -1679, 1252, 29332],
+    [16, -102, 315, -686, 1128, -1335, 434, 29131],
+    [16, -98, 297, -627, 977, -997, -336, 28853],
+    [15, -93, 277, -566, 824, -665, -1055, 28499],
+    [14, -87, 256, -503, 672, -343, -1721, 28067],
+    [13, -82, 234, -439, 522, -34, -2334, 27565],
+    [12, -76, 211, -375, 374, 262, -2891, 26992],
+    [10, -69, 188, -311, 229, 543, -3394, 26350],
+    [9, -63, 165, -248, 90, 807, -3840, 25646],
+    [8, -56, 142, -186, -44, 1052, -4231, 24877],
+    [7, -50, 119, -126, -171, 1277, -4566, 24057],
+    [6, -44, 96, -68, -291, 1482, -4846, 23182],
+    [5, -37, 74, -12, -403, 1666, -5072, 22257],
+    [4, -31, 53, 41, -506, 1828, -5246, 21289],
+    [3, -25, 33, 90, -600, 1968, -5368, 20283],
+    [3, -20, 14, 136, -685, 2086, -5441, 19243],
+    [2, -15, -4, 178, -760, 2182, -5467, 18174],
+    [2, -10, -21, 217, -825, 2255, -5448, 17081],
+    [1, -5, -36, 251, -881, 2307, -5386, 15970],
+    [1, -1, -50, 282, -926, 2338, -5283, 14845],
+    [0, 2, -62, 308, -962, 2348, -5144, 13712],
+    [0, 6, -73, 330, -987, 2339, -4970, 12577],
+    [0, 8, -83, 348, -1004, 2311, -4765, 11444],
+    [0, 11, -91, 362, -1011, 2266, -4531, 10317],
+    [0, 13, -97, 372, -1009, 2204, -4273, 9203],
+    [0, 15, -103, 378, -999, 2127, -3992, 8106],
+    [0, 16, -106, 381, -982, 2036, -3693, 7031],
+    [0, 17, -109, 380, -956, 1932, -3378, 5981],
+    [0, 17, -110, 376, -925, 1818, -3051, 4960],
+    [0, 18, -111, 369, -887, 1693, -2714, 3974],
+    [0, 18, -110, 359, -843, 1561, -2371, 3025],
+];
+
+pub struct BlipBuf {
+    // Output samples per clock, in fixed-point
+    factor: u64,
+    // Fractional sample position left over from the previous frame
+    offset: u64,
+    // Samples that have been finished by end_frame and can be read
+    avail: usize,
+    size: usize,
+    integrator: i32,
+    buffer: Vec<i32>,
+}
+
+impl BlipBuf {
+    // Sets the clock rate deltas are given in, and the rate samples are
+    // read out at. These don't have to be whole numbers, which lets the
+    // output be sped up or slowed down slightly.
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        let factor = TIME_UNIT as f64 * sample_rate / clock_rate;
+        self.factor = factor as u64;
+
+        // Round up, otherwise the buffer would fill ever so slightly slower
+        // than the caller expects
+        if (self.factor as f64) < factor {
+            self.factor += 1;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.offset = self.factor / 2;
+        self.avail = 0;
+        self.integrator = 0;
+        for sample in self.buffer.iter_mut() {
+            *sample = 0;
+        }
+    }
+
+    // Adds an amplitude change at a clock time relative to the start of the
+    // current frame
+    pub fn add_delta(&mut self, time: u32, delta: i32) {
+        if delta == 0 {
+            return;
+        }
+
+        let fixed =
+            ((time as u64 * self.factor + self.offset) >> PRE_SHIFT) as u32;
+        let start = self.avail + (fixed >> FRAC_BITS) as usize;
+        debug_assert!(
+            start + HALF_WIDTH * 2 <= self.size + BUF_EXTRA,
+            "Blip buffer overflow"
+        );
+
+        let phase_shift = FRAC_BITS - PHASE_BITS;
+        let phase = (fixed >> phase_shift) as usize & (PHASE_COUNT - 1);
+
+        // Linearly interpolate between this phase's kernel and the next
+        let interp =
+            (fixed >> (phase_shift - DELTA_BITS)) as i64 & (DELTA_UNIT - 1);
+        let delta2 = ((delta as i64 * interp) >> DELTA_BITS) as i32;
+        let delta = delta - delta2;
+
+        let fwd = &BL_STEP[phase];
+        let fwd_next = &BL_STEP[phase + 1];
+        let rev = &BL_STEP[PHASE_COUNT - phase];
+        let rev_next = &BL_STEP[PHASE_COUNT - phase - 1];
+
+        let out = &mut self.buffer[start..start + HALF_WIDTH * 2];
+        for i in 0..HALF_WIDTH {
+            out[i] = out[i].wrapping_add(
+                fwd[i] as i32 * delta + fwd_next[i] as i32 * delta2,
+            );
+            let j = HALF_WIDTH * 2 - 1 - i;
+            out[j] = out[j].wrapping_add(
+                rev[i] as i32 * delta + rev_next[i] as i32 * delta2,
+            );
+        }
+    }
+
+    // Ends the current frame, which was `clocks` long. The samples within
+    // it become available for reading and the next frame starts at time 0.
+    pub fn end_frame(&mut self, clocks: u32) {
+        let off = clocks as u64 * self.factor + self.offset;
+        self.avail += (off >> TIME_BITS) as usize;
+        self.offset = off & (TIME_UNIT - 1);
+
+        debug_assert!(self.avail <= self.size, "Blip buffer overflow");
+    }
+
+    pub fn samples_avail(&self) -> usize {
+        self.avail
+    }
+
+    // Reads up to out.len() samples (or every other slot of out if stereo,
+    // so that two BlipBufs can fill one interleaved buffer). Returns the
+    // number of samples that were read.
+    pub fn read_samples(&mut self, out: &mut [i16], stereo: bool) -> usize {
+        let step = if stereo { 2 } else { 1 };
+        let count = self.avail.min(out.len().div_ceil(step));
+        if count == 0 {
+            return 0;
+        }
+
+        let mut sum = self.integrator;
+        for i in 0..count {
+            // Eliminate fraction
+            let s = (sum >> DELTA_BITS).clamp(i16::MIN as i32, i16::MAX as i32);
+            sum = sum.wrapping_add(self.buffer[i]);
+            out[i * step] = s as i16;
+            // High-pass filter, otherwise rounding errors build up
+            sum -= s << (DELTA_BITS - BASS_SHIFT);
+        }
+        self.integrator = sum;
+
+        self.remove_samples(count);
+        count
+    }
+
+    fn remove_samples(&mut self, count: usize) {
+        let remaining = self.avail + BUF_EXTRA - count;
+        self.avail -= count;
+
+        self.buffer.copy_within(count..count + remaining, 0);
+        for sample in &mut self.buffer[remaining..remaining + count] {
+            *sample = 0;
+        }
+    }
+
+    // `size` is the maximum number of samples that can be buffered between
+    // reads
+    pub fn new(size: usize) -> BlipBuf {
+        let mut blip = BlipBuf {
+            factor: 0,
+            offset: 0,
+            avail: 0,
+            size,
+            integrator: 0,
+            buffer: vec![0; size + BUF_EXTRA],
+        };
+        blip.clear();
+        blip
+    }
+}
@@ -1,6 +1,7 @@
 use super::apu::APUChannel;
 use super::length_function::LengthFunction;
 use super::volume_envelope::VolumeEnvelope;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 const WAVEFORM_TABLE: [u8; 4] =
     [0b00000001, 0b00000011, 0b00001111, 0b11111100];
@@ -107,12 +108,39 @@ impl APUChannel for APUChannel2 {
             return 0.;
         }
 
+        // The DAC in the Gameboy outputs between -1.0 and 1.0
+        (self.amplitude() as f32 / 7.5) - 1.0
+    }
+
+    fn amplitude(&self) -> u8 {
+        if !self.length_function.channel_enabled {
+            return 0;
+        }
+
         let wave_pattern = WAVEFORM_TABLE[self.wave_duty];
         let amplitude_bit = (wave_pattern & (1 << self.wave_duty_position))
             >> self.wave_duty_position;
 
-        let dac_input = amplitude_bit as usize * self.volume_envelope.volume;
-        // The DAC in the Gameboy outputs between -1.0 and 1.0
-        (dac_input as f32 / 7.5) - 1.0
+        (amplitude_bit as usize * self.volume_envelope.volume) as u8
+    }
+}
+
+impl SaveState for APUChannel2 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.frequency);
+        state.write_usize(self.frequency_timer);
+        state.write_usize(self.wave_duty);
+        state.write_usize(self.wave_duty_position);
+        self.volume_envelope.save_state(state);
+        self.length_function.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.frequency = state.read_usize();
+        self.frequency_timer = state.read_usize();
+        self.wave_duty = state.read_usize();
+        self.wave_duty_position = state.read_usize();
+        self.volume_envelope.load_state(state);
+        self.length_function.load_state(state);
     }
 }
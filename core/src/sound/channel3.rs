@@ -2,6 +2,7 @@ use super::apu::APUChannel;
 use super::length_function::LengthFunction;
 use crate::constants::*;
 use crate::memory::ram::Ram;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 pub struct APUChannel3 {
     frequency: usize,
@@ -103,6 +104,14 @@ impl APUChannel for APUChannel3 {
             return 0.;
         }
 
+        (self.amplitude() as f32 / 7.5) - 1.0
+    }
+
+    fn amplitude(&self) -> u8 {
+        if !self.length_function.channel_enabled {
+            return 0;
+        }
+
         // This implementation is a bit guessed for now :)
         // Documentation on Channel 3 seems a little bit thin
         let wave_byte = self.wave_ram.bytes[self.wave_ram_ptr / 2];
@@ -121,6 +130,28 @@ impl APUChannel for APUChannel3 {
                 _ => unreachable!(),
             };
 
-        (wave_nibble as f32 / 7.5) - 1.0
+        wave_nibble
+    }
+}
+
+impl SaveState for APUChannel3 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.frequency);
+        state.write_usize(self.frequency_timer);
+        state.write_bool(self.master_enable);
+        self.length_function.save_state(state);
+        self.wave_ram.save_state(state);
+        state.write_usize(self.wave_ram_ptr);
+        state.write_u8(self.volume_shift);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.frequency = state.read_usize();
+        self.frequency_timer = state.read_usize();
+        self.master_enable = state.read_bool();
+        self.length_function.load_state(state);
+        self.wave_ram.load_state(state);
+        self.wave_ram_ptr = state.read_usize();
+        self.volume_shift = state.read_u8();
     }
 }
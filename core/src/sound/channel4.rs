@@ -1,6 +1,7 @@
 use super::apu::APUChannel;
 use super::length_function::LengthFunction;
 use super::volume_envelope::VolumeEnvelope;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 pub struct APUChannel4 {
     // TODO: Size these better. Maybe u32 rather than usize?
@@ -111,9 +112,37 @@ impl APUChannel for APUChannel4 {
             return 0.;
         }
 
+        (self.amplitude() as f32 / 7.5) - 1.0
+    }
+
+    fn amplitude(&self) -> u8 {
+        if !self.length_function.channel_enabled {
+            return 0;
+        }
+
         let lfsr_bit = !(self.lfsr) & 1;
+        (lfsr_bit as usize * self.volume_envelope.volume) as u8
+    }
+}
+
+impl SaveState for APUChannel4 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.frequency_timer);
+        self.length_function.save_state(state);
+        self.volume_envelope.save_state(state);
+        state.write_u16(self.lfsr);
+        state.write_usize(self.divisor_shift);
+        state.write_bool(self.half_width_mode);
+        state.write_usize(self.divisor_code);
+    }
 
-        let dac_input = lfsr_bit as usize * self.volume_envelope.volume;
-        (dac_input as f32 / 7.5) - 1.0
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.frequency_timer = state.read_usize();
+        self.length_function.load_state(state);
+        self.volume_envelope.load_state(state);
+        self.lfsr = state.read_u16();
+        self.divisor_shift = state.read_usize();
+        self.half_width_mode = state.read_bool();
+        self.divisor_code = state.read_usize();
     }
 }
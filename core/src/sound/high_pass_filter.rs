@@ -0,0 +1,68 @@
+use crate::constants::*;
+
+// The Gameboy's sound output passes through a capacitor which slowly removes
+// any DC offset. Without this, a channel whose DAC is on but silent would
+// hold the speaker at -1.0 forever, and every channel starting or stopping
+// would cause a loud pop.
+// https://gbdev.gg8.se/wiki/articles/Gameboy_sound_hardware#Obscure_Behavior
+
+// How much charge the capacitor keeps per 4MHz clock
+const DMG_CHARGE_PER_CLOCK: f64 = 0.999958;
+const CGB_CHARGE_PER_CLOCK: f64 = 0.998943;
+
+pub struct HighPassFilter {
+    cgb_features: bool,
+    charge_factor: f32,
+    capacitor_left: f32,
+    capacitor_right: f32,
+}
+
+// Equivalent to base.powf(exponent) for a base very close to 1, without
+// relying on float functions that aren't in core.
+fn charge_factor(base: f64, clocks_per_sample: f64) -> f64 {
+    let whole_clocks = clocks_per_sample as usize;
+    let fraction = clocks_per_sample - whole_clocks as f64;
+
+    let mut factor = 1.;
+    for _ in 0..whole_clocks {
+        factor *= base;
+    }
+    // For a base this close to 1, base^x is very nearly linear in x
+    // between 0 and 1.
+    factor * (1. - fraction * (1. - base))
+}
+
+impl HighPassFilter {
+    pub fn filter(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let out_left = left - self.capacitor_left;
+        self.capacitor_left = left - out_left * self.charge_factor;
+
+        let out_right = right - self.capacitor_right;
+        self.capacitor_right = right - out_right * self.charge_factor;
+
+        (out_left, out_right)
+    }
+
+    // The filter runs at the output sample rate rather than per-clock, so
+    // the capacitor's charge factor has to be scaled up to match.
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        let base = if self.cgb_features {
+            CGB_CHARGE_PER_CLOCK
+        } else {
+            DMG_CHARGE_PER_CLOCK
+        };
+        let clocks_per_sample = CLOCK_SPEED as f64 / sample_rate;
+        self.charge_factor = charge_factor(base, clocks_per_sample) as f32;
+    }
+
+    pub fn new(sample_rate: usize, cgb_features: bool) -> HighPassFilter {
+        let mut filter = HighPassFilter {
+            cgb_features,
+            charge_factor: 1.,
+            capacitor_left: 0.,
+            capacitor_right: 0.,
+        };
+        filter.set_sample_rate(sample_rate as f64);
+        filter
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
 // 256Hz
 const LENGTH_CLOCKS: usize = 16_392;
 
@@ -52,3 +54,21 @@ impl LengthFunction {
         }
     }
 }
+
+impl SaveState for LengthFunction {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.channel_enabled);
+        state.write_usize(self.data);
+        state.write_bool(self.timer_enabled);
+        state.write_usize(self.timer);
+        state.write_usize(self.clock_timer);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.channel_enabled = state.read_bool();
+        self.data = state.read_usize();
+        self.timer_enabled = state.read_bool();
+        self.timer = state.read_usize();
+        self.clock_timer = state.read_usize();
+    }
+}
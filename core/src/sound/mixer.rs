@@ -0,0 +1,117 @@
+// Lets ports mute, solo and adjust the volume of each of the four channels.
+// This sits after the Gameboy's own mixing (NR50/NR51) and isn't visible to
+// the game.
+
+// One of the APU's four sound channels
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Channel {
+    Channel1,
+    Channel2,
+    Channel3,
+    Channel4,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [
+        Channel::Channel1,
+        Channel::Channel2,
+        Channel::Channel3,
+        Channel::Channel4,
+    ];
+
+    // 0-3, for indexing per-channel arrays
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    // 1-4, as the channels are numbered in documentation
+    pub fn from_number(number: usize) -> Option<Channel> {
+        Channel::ALL.get(number.checked_sub(1)?).copied()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ChannelMix {
+    pub muted: bool,
+    pub solo: bool,
+    // 1.0 is the channel's normal volume
+    pub gain: f32,
+}
+
+impl ChannelMix {
+    pub fn new() -> ChannelMix {
+        ChannelMix {
+            muted: false,
+            solo: false,
+            gain: 1.,
+        }
+    }
+}
+
+impl Default for ChannelMix {
+    fn default() -> ChannelMix {
+        ChannelMix::new()
+    }
+}
+
+#[derive(Clone)]
+pub struct Mixer {
+    channels: [ChannelMix; 4],
+    // Whether any channel is soloed, kept up to date by set_solo as it's
+    // needed whenever the channels are mixed
+    any_solo: bool,
+    // Set whenever the mix changes, until the APU takes it with take_changed
+    changed: bool,
+}
+
+impl Mixer {
+    pub fn channel(&self, channel: Channel) -> ChannelMix {
+        self.channels[channel.index()]
+    }
+
+    // How loud a channel is in the mixed output. When any channel is soloed,
+    // only soloed channels can be heard.
+    pub fn channel_gain(&self, channel: Channel) -> f32 {
+        let mix = &self.channels[channel.index()];
+        if mix.muted || (self.any_solo && !mix.solo) {
+            0.
+        } else {
+            mix.gain
+        }
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.channels[channel.index()].muted = muted;
+        self.changed = true;
+    }
+
+    pub fn set_solo(&mut self, channel: Channel, solo: bool) {
+        self.channels[channel.index()].solo = solo;
+        self.any_solo = self.channels.iter().any(|mix| mix.solo);
+        self.changed = true;
+    }
+
+    pub fn set_gain(&mut self, channel: Channel, gain: f32) {
+        self.channels[channel.index()].gain = gain;
+        self.changed = true;
+    }
+
+    // Whether anything has changed since this was last called
+    pub fn take_changed(&mut self) -> bool {
+        core::mem::replace(&mut self.changed, false)
+    }
+
+    pub fn new() -> Mixer {
+        Mixer {
+            channels: [ChannelMix::new(); 4],
+            any_solo: false,
+            changed: false,
+        }
+    }
+}
+
+impl Default for Mixer {
+    fn default() -> Mixer {
+        Mixer::new()
+    }
+}
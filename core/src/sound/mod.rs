@@ -1,8 +1,12 @@
 pub mod apu;
+pub mod blip_buf;
 pub mod channel1;
 pub mod channel2;
 pub mod channel3;
 pub mod channel4;
+pub mod high_pass_filter;
 pub mod length_function;
+pub mod mixer;
 pub mod registers;
+pub mod stereo_output;
 pub mod volume_envelope;
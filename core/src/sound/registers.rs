@@ -1,3 +1,5 @@
+use super::mixer::Channel;
+
 #[derive(Clone)]
 pub struct StereoPanning {
     pub channel1_left: bool,
@@ -10,6 +12,18 @@ pub struct StereoPanning {
     pub channel4_right: bool,
 }
 
+impl StereoPanning {
+    // Whether a channel is sent to the (left, right) outputs
+    pub fn channel(&self, channel: Channel) -> (bool, bool) {
+        match channel {
+            Channel::Channel1 => (self.channel1_left, self.channel1_right),
+            Channel::Channel2 => (self.channel2_left, self.channel2_right),
+            Channel::Channel3 => (self.channel3_left, self.channel3_right),
+            Channel::Channel4 => (self.channel4_left, self.channel4_right),
+        }
+    }
+}
+
 impl From<u8> for StereoPanning {
     fn from(n: u8) -> StereoPanning {
         StereoPanning {
@@ -0,0 +1,114 @@
+use super::blip_buf::BlipBuf;
+use super::high_pass_filter::HighPassFilter;
+use crate::constants::*;
+
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+// Scales a -1.0 to 1.0 mixer output into 16-bit sample range
+const AMPLITUDE_SCALE: f32 = 30_000.;
+// If nobody is reading samples (eg. a port without sound output), we stop
+// queuing them after this many buffers' worth.
+const MAX_QUEUED_BUFFERS: usize = 4;
+
+// One stereo stream of audio on its way out of the APU: band-limited
+// resampling, the high-pass filter, and a queue of samples for the port.
+pub struct StereoOutput {
+    last_left_amplitude: i32,
+    last_right_amplitude: i32,
+    blip_left: BlipBuf,
+    blip_right: BlipBuf,
+    high_pass: HighPassFilter,
+    max_queued: usize,
+
+    // Interleaved stereo samples that are ready for the port to read
+    samples: Vec<i16>,
+    scratch: Vec<i16>,
+}
+
+impl StereoOutput {
+    // Records the output level at a clock time relative to the start of the
+    // current blip frame. Levels are between -1.0 and 1.0.
+    pub fn set_level(&mut self, time: u32, left: f32, right: f32) {
+        let left_amplitude = (left * AMPLITUDE_SCALE) as i32;
+        let right_amplitude = (right * AMPLITUDE_SCALE) as i32;
+
+        self.blip_left
+            .add_delta(time, left_amplitude - self.last_left_amplitude);
+        self.blip_right
+            .add_delta(time, right_amplitude - self.last_right_amplitude);
+        self.last_left_amplitude = left_amplitude;
+        self.last_right_amplitude = right_amplitude;
+    }
+
+    // Turns everything recorded in the last `clocks` into samples
+    pub fn end_frame(&mut self, clocks: u32) {
+        self.blip_left.end_frame(clocks);
+        self.blip_right.end_frame(clocks);
+
+        let available = self.blip_left.samples_avail();
+        self.scratch.resize(available * 2, 0);
+        self.blip_left.read_samples(&mut self.scratch[..], true);
+        self.blip_right.read_samples(&mut self.scratch[1..], true);
+
+        for pair in self.scratch.chunks_exact(2) {
+            let (left, right) =
+                self.high_pass.filter(pair[0] as f32, pair[1] as f32);
+            self.samples.push(left as i16);
+            self.samples.push(right as i16);
+        }
+
+        if self.samples.len() > self.max_queued {
+            let excess = self.samples.len() - self.max_queued;
+            self.samples.drain(..excess);
+        }
+    }
+
+    pub fn samples_available(&self) -> usize {
+        self.samples.len()
+    }
+
+    // Moves as many queued samples as will fit into `out`, returning how many
+    // were written
+    pub fn read_samples(&mut self, out: &mut [i16]) -> usize {
+        // Don't split a left/right pair between two reads
+        let count = self.samples.len().min(out.len()) & !1;
+        out[..count].copy_from_slice(&self.samples[..count]);
+        self.samples.drain(..count);
+        count
+    }
+
+    pub fn discard_samples(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        let clock_rate = CLOCK_SPEED as f64;
+        self.blip_left.set_rates(clock_rate, sample_rate as f64);
+        self.blip_right.set_rates(clock_rate, sample_rate as f64);
+        self.high_pass.set_sample_rate(sample_rate as f64);
+    }
+
+    pub fn new(
+        sample_rate: usize,
+        buffer_size: usize,
+        cgb_features: bool,
+    ) -> StereoOutput {
+        // A generous upper bound on the samples produced by one blip frame
+        let blip_size = sample_rate / 10;
+
+        let mut output = StereoOutput {
+            last_left_amplitude: 0,
+            last_right_amplitude: 0,
+            blip_left: BlipBuf::new(blip_size),
+            blip_right: BlipBuf::new(blip_size),
+            high_pass: HighPassFilter::new(sample_rate, cgb_features),
+            max_queued: buffer_size * MAX_QUEUED_BUFFERS,
+
+            samples: vec![],
+            scratch: vec![],
+        };
+        output.set_sample_rate(sample_rate);
+        output
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
 #[derive(PartialEq)]
 enum EnvelopeDirection {
     Up,
@@ -73,3 +75,26 @@ impl VolumeEnvelope {
         }
     }
 }
+
+impl SaveState for VolumeEnvelope {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.initial_volume);
+        state.write_bool(self.direction == EnvelopeDirection::Up);
+        state.write_usize(self.sweep_period);
+        state.write_usize(self.period_timer);
+        state.write_usize(self.volume_timer);
+        state.write_usize(self.volume);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.initial_volume = state.read_usize();
+        self.direction = match state.read_bool() {
+            true => EnvelopeDirection::Up,
+            false => EnvelopeDirection::Down,
+        };
+        self.sweep_period = state.read_usize();
+        self.period_timer = state.read_usize();
+        self.volume_timer = state.read_usize();
+        self.volume = state.read_usize();
+    }
+}
@@ -0,0 +1,158 @@
+// Save states: a snapshot of everything needed to resume emulation exactly
+// where it left off. Each part of the emulator writes its own fields in a
+// fixed order, and reads them back in the same order.
+use core::fmt;
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    // Not a gbrs save state at all
+    InvalidHeader,
+    // Made by a version of gbrs with a different save state layout
+    UnsupportedVersion(u8),
+    // Made with a different ROM
+    RomMismatch,
+    // The state ended early or had the wrong sizes in it
+    Corrupt,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::InvalidHeader => write!(f, "not a gbrs save state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "made by a version of gbrs with a different layout \
+                 (version {})",
+                version
+            ),
+            StateError::RomMismatch => write!(f, "made with a different ROM"),
+            StateError::Corrupt => write!(f, "the save state is corrupt"),
+        }
+    }
+}
+
+pub trait SaveState {
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader);
+}
+
+pub struct StateWriter {
+    pub bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+    pub fn write_bool(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+    pub fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    // usizes are always stored as 64-bit so states work across platforms
+    pub fn write_usize(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+    pub fn write_f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_bits().to_le_bytes());
+    }
+    // Length-prefixed, so that reading can check it has the right size
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn new() -> StateWriter {
+        StateWriter { bytes: Vec::new() }
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+// Reading past the end of the state doesn't panic. It gives zeros and marks
+// the state as corrupt, which is checked once everything has been read.
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    pub corrupt: bool,
+}
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, count: usize) -> &'a [u8] {
+        if self.position + count > self.bytes.len() {
+            self.corrupt = true;
+            self.position = self.bytes.len();
+            return &[];
+        }
+        let taken = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        taken
+    }
+
+    fn take_array<const N: usize>(&mut self) -> [u8; N] {
+        let mut array = [0; N];
+        let taken = self.take(N);
+        if taken.len() == N {
+            array.copy_from_slice(taken);
+        }
+        array
+    }
+
+    pub fn read_u8(&mut self) -> u8 {
+        self.take_array::<1>()[0]
+    }
+    pub fn read_bool(&mut self) -> bool {
+        self.read_u8() != 0
+    }
+    pub fn read_u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.take_array())
+    }
+    pub fn read_u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take_array())
+    }
+    pub fn read_usize(&mut self) -> usize {
+        u64::from_le_bytes(self.take_array()) as usize
+    }
+    pub fn read_f32(&mut self) -> f32 {
+        f32::from_bits(u32::from_le_bytes(self.take_array()))
+    }
+    // Fills `out`, which must be the same size as what was written
+    pub fn read_bytes_into(&mut self, out: &mut [u8]) {
+        let len = self.read_u32() as usize;
+        if len != out.len() {
+            self.corrupt = true;
+            return;
+        }
+        let taken = self.take(len);
+        if taken.len() == len {
+            out.copy_from_slice(taken);
+        }
+    }
+    pub fn read_bytes(&mut self) -> Vec<u8> {
+        let len = self.read_u32() as usize;
+        self.take(len).to_vec()
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    pub fn new(bytes: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            bytes,
+            position: 0,
+            corrupt: false,
+        }
+    }
+}
@@ -0,0 +1,76 @@
+// Writes 16-bit PCM WAV files, such as rendered GBS tracks
+use std::io::{Result, Seek, SeekFrom, Write};
+
+const HEADER_SIZE: u32 = 44;
+
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    channels: u16,
+    // In bytes
+    data_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    // Samples are interleaved if there is more than one channel
+    pub fn write_samples(&mut self, samples: &[i16]) -> Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.writer.write_all(&bytes)?;
+        self.data_size += bytes.len() as u32;
+        Ok(())
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    // Fills in the sizes in the header, which aren't known until all samples
+    // have been written
+    pub fn finish(mut self) -> Result<W> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    pub fn new(
+        mut writer: W,
+        sample_rate: usize,
+        channels: u16,
+    ) -> Result<WavWriter<W>> {
+        let sample_rate = sample_rate as u32;
+        let block_align = channels * 2;
+        let byte_rate = sample_rate * block_align as u32;
+
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(b"RIFF");
+        // The file and data sizes are filled in by finish()
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&byte_rate.to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        // Bits per sample
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        writer.write_all(&header)?;
+
+        Ok(WavWriter {
+            writer,
+            channels,
+            data_size: 0,
+        })
+    }
+}
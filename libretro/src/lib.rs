@@ -1,25 +1,172 @@
+mod memory_maps;
+mod options;
+
+use gbrs_core::cheats::Cheats;
 use gbrs_core::config::Config;
 use gbrs_core::constants::*;
 use gbrs_core::cpu::Cpu;
+use gbrs_core::host::{shared, Host};
 use gbrs_core::memory::rom::Rom;
+use gbrs_core::memory::rtc::RTC_SAVE_SIZE;
 use libretro_rs::c_utf8::{c_utf8, CUtf8};
 use libretro_rs::ffi::retro_log_level::*;
 use libretro_rs::retro::env::{Init, UnloadGame};
+use libretro_rs::retro::memory::MemoryType;
 use libretro_rs::retro::pixel::{Format, XRGB8888};
 use libretro_rs::retro::*;
 use libretro_rs::{ext, libretro_core};
+use memory_maps::MemoryMap;
+use options::Options;
 use spin::{mutex::SpinMutex, Once};
 
 struct LibretroCore {
     gameboy: Cpu,
     last_cpu_config: Config,
     rendering_mode: SoftwareRenderEnabled,
-    frame_buffer: [XRGB8888; SCREEN_WIDTH * SCREEN_HEIGHT],
+    frame_buffer: Vec<XRGB8888>,
+    audio_buffer: Vec<i16>,
     pixel_format: Format<XRGB8888>,
+    // RETRO_MEMORY_RTC, in the .sav clock layout. The frontend writes to it
+    // after loading the game, so it's compared against what we last put in
+    // it to spot that.
+    rtc_buffer: Vec<u8>,
+    last_rtc_buffer: Vec<u8>,
+    // Pointers into the Cpu. Kept alive for as long as the frontend might
+    // use them, and resent when the Cpu is rebuilt.
+    memory_map: MemoryMap,
+    memory_map_changed: bool,
+    // The Cpu and map from before a reset. The frontend may read through the
+    // old map until it's given the new one, so they're only dropped then.
+    retired_gameboy: Option<(Cpu, MemoryMap)>,
+    options: Options,
+    // retro_serialize_size must not change while a game is running
+    state_size: usize,
 }
 
+// Save states are prefixed with their length, as the frontend's buffer is
+// padded out to state_size. The slack allows for states growing a little,
+// like when an SGB game first sends a border.
+const STATE_LENGTH_SIZE: usize = 4;
+const STATE_SLACK: usize = 1024;
+
 static LOGGER: Once<SpinMutex<PlatformLogger>> = Once::new();
 
+// Sends logs to the frontend's logger. The frontend reads and writes saves
+// itself through RETRO_MEMORY_SAVE_RAM and RETRO_MEMORY_RTC, so they're left
+// to the Host defaults.
+struct LibretroHost;
+
+impl Host for LibretroHost {
+    fn log(&mut self, message: &str) {
+        let null_terminated = &format!("{}\0", message)[..];
+        let retro_str = CUtf8::from_str(null_terminated).unwrap();
+        if let Some(logger) = LOGGER.get() {
+            logger.lock().log(RETRO_LOG_INFO, retro_str);
+        }
+    }
+
+    fn now(&mut self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+impl LibretroCore {
+    fn build_gameboy(config: &Config, options: &Options) -> Cpu {
+        let mut gameboy = Cpu::from_config(config.clone());
+        gameboy.dmg_palette = options.dmg_palette;
+        gameboy
+    }
+
+    fn update_options(&mut self, env: &mut impl env::Run) {
+        if !env.get_variable_update() {
+            return;
+        }
+        self.options = Options::read(env);
+        // The model waits until the game is next loaded, as the screen size
+        // can change
+        self.gameboy.dmg_palette = self.options.dmg_palette;
+    }
+
+    fn update_joypad(&mut self, runtime: &mut impl Callbacks) {
+        let joypad = &mut self.gameboy.mem.joypad;
+        let port = DevicePort::new(0);
+        joypad.a_pressed =
+            runtime.is_joypad_button_pressed(port, JoypadButton::A);
+        joypad.b_pressed =
+            runtime.is_joypad_button_pressed(port, JoypadButton::B);
+        joypad.start_pressed =
+            runtime.is_joypad_button_pressed(port, JoypadButton::Start);
+        joypad.select_pressed =
+            runtime.is_joypad_button_pressed(port, JoypadButton::Select);
+        joypad.left_pressed =
+            runtime.is_joypad_button_pressed(port, JoypadButton::Left);
+        joypad.right_pressed =
+            runtime.is_joypad_button_pressed(port, JoypadButton::Right);
+        joypad.up_pressed =
+            runtime.is_joypad_button_pressed(port, JoypadButton::Up);
+        joypad.down_pressed =
+            runtime.is_joypad_button_pressed(port, JoypadButton::Down);
+    }
+
+    fn upload_video_frame(&mut self, runtime: &mut impl Callbacks) {
+        let gb = &self.gameboy;
+        let rgba = gb.get_rgba_frame();
+        for (i, colour) in rgba.chunks(4).enumerate() {
+            let mut pixel: u32 = 0;
+            let r = colour[0];
+            let g = colour[1];
+            let b = colour[2];
+            pixel |= b as u32;
+            pixel |= (g as u32) << 8;
+            pixel |= (r as u32) << 16;
+            self.frame_buffer[i] = XRGB8888::new_with_raw_value(pixel);
+        }
+
+        let frame = Frame::new(
+            &self.frame_buffer[..],
+            gb.screen_width() as u32,
+            gb.screen_height() as u32,
+        );
+        runtime.upload_video_frame(
+            &self.rendering_mode,
+            &self.pixel_format,
+            &frame,
+        );
+    }
+
+    // Everything the APU made during the frame, which isn't the same amount
+    // every time
+    fn upload_audio_frame(&mut self, runtime: &mut impl Callbacks) {
+        let apu = &mut self.gameboy.mem.apu;
+        self.audio_buffer.resize(apu.samples_available(), 0);
+        let sample_count = apu.read_samples(&mut self.audio_buffer);
+        runtime.upload_audio_frame(&self.audio_buffer[..sample_count]);
+    }
+
+    fn load_rtc_from_frontend(&mut self) {
+        if self.rtc_buffer == self.last_rtc_buffer {
+            return;
+        }
+        let now = LibretroHost.now();
+        let battery = self.gameboy.mem.battery_mut();
+        if let Some(rtc) = battery.and_then(|battery| battery.rtc.as_mut()) {
+            rtc.load_save_data(&self.rtc_buffer, now);
+        }
+    }
+
+    fn store_rtc_for_frontend(&mut self) {
+        let now = LibretroHost.now();
+        let battery = self.gameboy.mem.battery();
+        if let Some(rtc) = battery.and_then(|battery| battery.rtc.as_ref()) {
+            self.rtc_buffer.copy_from_slice(&rtc.save_data(now));
+            self.last_rtc_buffer.copy_from_slice(&self.rtc_buffer);
+        }
+    }
+}
+
 impl<'a> Core<'a> for LibretroCore {
     type Init = ();
 
@@ -33,18 +180,7 @@ impl<'a> Core<'a> for LibretroCore {
 
     fn init(env: &mut impl Init) -> Self::Init {
         LOGGER.call_once(|| SpinMutex::new(env.get_log_interface().unwrap()));
-
-        gbrs_core::callbacks::set_callbacks(gbrs_core::callbacks::Callbacks {
-            log: |log_str| {
-                let null_terminated = &format!("{}\0", log_str)[..];
-                let retro_str = CUtf8::from_str(null_terminated).unwrap();
-                if let Some(logger) = LOGGER.get() {
-                    logger.lock().log(RETRO_LOG_INFO, retro_str);
-                }
-            },
-            save: |_game_name, _rom_path, _save_data| {},
-            load: |_game_name, _rom_path, expected_size| vec![0; expected_size],
-        })
+        options::define(env);
     }
 
     fn get_system_av_info(
@@ -52,69 +188,135 @@ impl<'a> Core<'a> for LibretroCore {
         _env: &mut impl env::GetAvInfo,
     ) -> SystemAVInfo {
         SystemAVInfo::new(
-            GameGeometry::fixed(SCREEN_WIDTH as u16, SCREEN_HEIGHT as u16),
-            SystemTiming::new(
-                DEFAULT_FRAME_RATE as f64,
-                SOUND_SAMPLE_RATE as f64,
+            GameGeometry::fixed(
+                self.gameboy.screen_width() as u16,
+                self.gameboy.screen_height() as u16,
             ),
+            SystemTiming::new(FRAME_RATE, SOUND_SAMPLE_RATE as f64),
         )
     }
 
+    // Runs exactly one frame, so that the frontend can pace us by its display
     fn run(
         &mut self,
-        _env: &mut impl env::Run,
+        env: &mut impl env::Run,
         runtime: &mut impl Callbacks,
     ) -> InputsPolled {
-        let gb = &mut self.gameboy;
+        self.update_options(env);
+        if self.memory_map_changed {
+            env.set_memory_maps(&self.memory_map.as_ffi());
+            self.memory_map_changed = false;
+            self.retired_gameboy = None;
+        }
+        self.load_rtc_from_frontend();
 
-        for i in 0..SCREEN_BUFFER_SIZE {
-            let mut pixel: u32 = 0;
-            let r = gb.gpu.finished_frame[i].red;
-            let g = gb.gpu.finished_frame[i].green;
-            let b = gb.gpu.finished_frame[i].blue;
-            pixel |= b as u32;
-            pixel |= (g as u32) << 8;
-            pixel |= (r as u32) << 16;
-            self.frame_buffer[i] = XRGB8888::new_with_raw_value(pixel);
+        let inputs_polled = runtime.poll_inputs();
+        self.update_joypad(runtime);
+
+        // GameShark codes are written by the core, in VBlank
+        self.gameboy.step_one_frame();
+
+        self.upload_video_frame(runtime);
+        self.upload_audio_frame(runtime);
+        self.store_rtc_for_frontend();
+
+        inputs_polled
+    }
+
+    fn get_memory_data(
+        &mut self,
+        _env: &mut impl env::GetMemoryData,
+        id: MemoryType,
+    ) -> Option<&mut [u8]> {
+        match id {
+            MemoryType::SaveRam => self
+                .gameboy
+                .mem
+                .battery_mut()
+                .filter(|battery| battery.has_battery())
+                .map(|battery| &mut battery.ram.bytes[..]),
+            MemoryType::Rtc if !self.rtc_buffer.is_empty() => {
+                Some(&mut self.rtc_buffer[..])
+            },
+            MemoryType::SystemRam => Some(self.gameboy.mem.wram_mut()),
+            MemoryType::VideoRam => Some(self.gameboy.mem.vram.bytes_mut()),
+            _ => None,
         }
+    }
 
-        let frame = Frame::new(
-            &self.frame_buffer,
-            SCREEN_WIDTH as u32,
-            SCREEN_HEIGHT as u32,
-        );
-        runtime.upload_video_frame(
-            &self.rendering_mode,
-            &self.pixel_format,
-            &frame,
-        );
-        runtime.upload_audio_frame(&gb.mem.apu.buffer);
+    fn get_memory_size(
+        &self,
+        _env: &mut impl env::GetMemorySize,
+        id: MemoryType,
+    ) -> usize {
+        match id {
+            MemoryType::SaveRam => self
+                .gameboy
+                .mem
+                .battery()
+                .filter(|battery| battery.has_battery())
+                .map_or(0, |battery| battery.size),
+            MemoryType::Rtc => self.rtc_buffer.len(),
+            MemoryType::SystemRam => WRAM_BANK_SIZE * 8,
+            MemoryType::VideoRam => VRAM_BANK_SIZE * 2,
+            _ => 0,
+        }
+    }
 
-        let inputs_polled = runtime.poll_inputs();
-        let port = DevicePort::new(0);
-        gb.mem.joypad.a_pressed =
-            runtime.is_joypad_button_pressed(port, JoypadButton::A);
-        gb.mem.joypad.b_pressed =
-            runtime.is_joypad_button_pressed(port, JoypadButton::B);
-        gb.mem.joypad.start_pressed =
-            runtime.is_joypad_button_pressed(port, JoypadButton::Start);
-        gb.mem.joypad.select_pressed =
-            runtime.is_joypad_button_pressed(port, JoypadButton::Select);
-        gb.mem.joypad.left_pressed =
-            runtime.is_joypad_button_pressed(port, JoypadButton::Left);
-        gb.mem.joypad.right_pressed =
-            runtime.is_joypad_button_pressed(port, JoypadButton::Right);
-        gb.mem.joypad.up_pressed =
-            runtime.is_joypad_button_pressed(port, JoypadButton::Up);
-        gb.mem.joypad.down_pressed =
-            runtime.is_joypad_button_pressed(port, JoypadButton::Down);
+    fn serialize_size(&self, _env: &mut impl env::SerializeSize) -> usize {
+        self.state_size
+    }
 
-        while !gb.mem.apu.buffer_full {
-            gb.step();
+    fn serialize(
+        &self,
+        _env: &mut impl env::Serialize,
+        data: &mut [u8],
+    ) -> bool {
+        let state = self.gameboy.save_state();
+        let length = STATE_LENGTH_SIZE + state.len();
+        if length > data.len() {
+            return false;
         }
-        gb.mem.apu.buffer_full = false;
 
-        inputs_polled
+        data[..STATE_LENGTH_SIZE]
+            .copy_from_slice(&(state.len() as u32).to_le_bytes());
+        data[STATE_LENGTH_SIZE..length].copy_from_slice(&state);
+        data[length..].fill(0);
+        true
+    }
+
+    fn unserialize(
+        &mut self,
+        _env: &mut impl env::Unserialize,
+        data: &[u8],
+    ) -> bool {
+        if data.len() < STATE_LENGTH_SIZE {
+            return false;
+        }
+        let mut length = [0; STATE_LENGTH_SIZE];
+        length.copy_from_slice(&data[..STATE_LENGTH_SIZE]);
+        let state = &data[STATE_LENGTH_SIZE..];
+        let length = u32::from_le_bytes(length) as usize;
+        if length > state.len() {
+            return false;
+        }
+
+        self.gameboy.load_state(&state[..length]).is_ok()
+    }
+
+    fn cheat_reset(&mut self, _env: &mut impl env::CheatReset) {
+        self.gameboy.mem.cheats.reset();
+    }
+
+    fn cheat_set(
+        &mut self,
+        _env: &mut impl env::CheatSet,
+        index: u32,
+        enabled: bool,
+        code: &str,
+    ) {
+        // The frontend doesn't hear about invalid codes, which are skipped
+        let _ = self.gameboy.mem.cheats.set(index as usize, enabled, code);
     }
 
     fn load_game<E: env::LoadGame>(
@@ -129,22 +331,72 @@ impl<'a> Core<'a> for LibretroCore {
         } = args;
         let pixel_format = env.set_pixel_format_xrgb8888(pixel_format)?;
         let data: &[u8] = game.as_data().ok_or(CoreError::new())?.data();
+        let options = Options::read(env);
         let config = Config {
             sound_buffer_size: SOUND_BUFFER_SIZE,
             sound_sample_rate: SOUND_SAMPLE_RATE,
             rom: Rom::from_bytes(data.to_vec()),
+            host: shared(LibretroHost),
+            model: options.model,
+            boot_rom: None,
+            patch: None,
         };
-        Ok(Self {
+        let mut gameboy = LibretroCore::build_gameboy(&config, &options);
+        let memory_map = MemoryMap::new(&mut gameboy);
+        env.set_memory_maps(&memory_map.as_ffi());
+        let state_size =
+            STATE_LENGTH_SIZE + gameboy.save_state().len() + STATE_SLACK;
+        let frame_buffer = vec![
+            XRGB8888::DEFAULT;
+            gameboy.screen_width() * gameboy.screen_height()
+        ];
+        let rtc_size = match gameboy.mem.battery() {
+            Some(battery) if battery.rtc.is_some() => RTC_SAVE_SIZE,
+            _ => 0,
+        };
+        let mut core = Self {
             rendering_mode,
             pixel_format,
-            gameboy: Cpu::from_config(config.clone()),
+            gameboy,
             last_cpu_config: config,
-            frame_buffer: [XRGB8888::DEFAULT; SCREEN_WIDTH * SCREEN_HEIGHT],
-        })
+            frame_buffer,
+            audio_buffer: Vec::with_capacity(SOUND_BUFFER_SIZE),
+            rtc_buffer: vec![0; rtc_size],
+            last_rtc_buffer: vec![0; rtc_size],
+            memory_map,
+            memory_map_changed: false,
+            retired_gameboy: None,
+            options,
+            state_size,
+        };
+        core.store_rtc_for_frontend();
+        Ok(core)
     }
 
+    // Saves live in the frontend, so they're carried over to the new Cpu
     fn reset(&mut self, _env: &mut impl env::Reset) {
-        self.gameboy = Cpu::from_config(self.last_cpu_config.clone());
+        let save_data = self.gameboy.save_data();
+        // The frontend doesn't set the cheats again after a reset
+        let cheats =
+            core::mem::replace(&mut self.gameboy.mem.cheats, Cheats::new());
+        let old_gameboy = core::mem::replace(
+            &mut self.gameboy,
+            LibretroCore::build_gameboy(&self.last_cpu_config, &self.options),
+        );
+        if let Some(save_data) = save_data {
+            self.gameboy.load_save_data(&save_data);
+        }
+        self.gameboy.mem.cheats = cheats;
+        let old_memory_map = core::mem::replace(
+            &mut self.memory_map,
+            MemoryMap::new(&mut self.gameboy),
+        );
+        // A second reset before the next frame keeps the first retired
+        // Cpu, as that's the one the frontend's map still points into
+        if self.retired_gameboy.is_none() {
+            self.retired_gameboy = Some((old_gameboy, old_memory_map));
+        }
+        self.memory_map_changed = true;
     }
 
     fn unload_game(self, _env: &mut impl UnloadGame) -> Self::Init {
@@ -0,0 +1,78 @@
+// RETRO_ENVIRONMENT_SET_MEMORY_MAPS, which RetroAchievements and the
+// frontend's memory viewers use to find their way around the Gameboy.
+// Addresses past 0xFFFF follow the rcheevos layout for the extra banks.
+use gbrs_core::constants::*;
+use gbrs_core::cpu::Cpu;
+use libretro_rs::ffi::*;
+use std::ffi::c_void;
+use std::ptr;
+
+pub struct MemoryMap {
+    descriptors: Vec<retro_memory_descriptor>,
+}
+
+impl MemoryMap {
+    pub fn as_ffi(&self) -> retro_memory_map {
+        retro_memory_map {
+            descriptors: self.descriptors.as_ptr(),
+            num_descriptors: self.descriptors.len() as u32,
+        }
+    }
+
+    // The pointers are into the Cpu's memory, so this has to be rebuilt
+    // whenever the Cpu is
+    pub fn new(gameboy: &mut Cpu) -> MemoryMap {
+        let mut descriptors = Vec::new();
+        let mut add = |flags: u32, memory: &mut [u8], start: usize, len| {
+            descriptors.push(retro_memory_descriptor {
+                flags: flags as u64,
+                ptr: memory.as_mut_ptr() as *mut c_void,
+                offset: 0,
+                start,
+                select: 0,
+                disconnect: 0,
+                len,
+                addrspace: ptr::null(),
+            });
+        };
+
+        let vram = gameboy.mem.vram.bytes_mut();
+        add(RETRO_MEMDESC_VIDEO_RAM, vram, 0x8000, VRAM_BANK_SIZE);
+
+        if let Some(battery) = gameboy.mem.battery_mut() {
+            let sram = &mut battery.ram.bytes[..];
+            let size = sram.len();
+            if size > 0 {
+                let (first, rest) = sram.split_at_mut(size.min(0x2000));
+                add(RETRO_MEMDESC_SAVE_RAM, first, 0xA000, first.len());
+                if !rest.is_empty() {
+                    add(RETRO_MEMDESC_SAVE_RAM, rest, 0x16000, rest.len());
+                }
+            }
+        }
+
+        // 0xC000 is bank 0, 0xD000 bank 1 and the CGB's banks 2-7 go after
+        // the address space
+        let wram = gameboy.mem.wram_mut();
+        let (lower_banks, upper_banks) = wram.split_at_mut(WRAM_BANK_SIZE * 2);
+        add(
+            RETRO_MEMDESC_SYSTEM_RAM,
+            lower_banks,
+            0xC000,
+            WRAM_BANK_SIZE * 2,
+        );
+        if gameboy.emulation_target.has_cgb_features() {
+            add(
+                RETRO_MEMDESC_SYSTEM_RAM,
+                upper_banks,
+                0x10000,
+                WRAM_BANK_SIZE * 6,
+            );
+        }
+
+        let hram = gameboy.mem.hram_mut();
+        add(RETRO_MEMDESC_SYSTEM_RAM, hram, 0xFF80, HRAM_SIZE);
+
+        MemoryMap { descriptors }
+    }
+}
@@ -0,0 +1,49 @@
+// Core options, shown in the frontend's quick menu
+use gbrs_core::colour::colour::Colour;
+use gbrs_core::colour::dmg_palettes::dmg_palette_by_name;
+use gbrs_core::cpu::EmulationTarget;
+use libretro_rs::c_utf8::{c_utf8, CUtf8};
+use libretro_rs::retro::env;
+use libretro_rs::retro::Variable;
+
+const MODEL_KEY: &CUtf8 = c_utf8!("gbrs_model");
+const PALETTE_KEY: &CUtf8 = c_utf8!("gbrs_dmg_palette");
+
+// The first value is the default
+pub fn define(env: &mut impl env::Init) {
+    env.set_variables(&[
+        Variable::new(
+            MODEL_KEY,
+            c_utf8!("Model (restart content); Auto|DMG|CGB|GBA|SGB"),
+        ),
+        Variable::new(
+            PALETTE_KEY,
+            c_utf8!("DMG palette; Grey|Green|Pocket|Light|Black and white"),
+        ),
+    ]);
+}
+
+#[derive(Clone, Copy)]
+pub struct Options {
+    pub model: Option<EmulationTarget>,
+    // None leaves the GPU's greys alone
+    pub dmg_palette: Option<[Colour; 4]>,
+}
+
+impl Options {
+    pub fn read(env: &mut impl env::GetVariable) -> Options {
+        let model = match env.get_variable(MODEL_KEY) {
+            Some("DMG") => Some(EmulationTarget::Dmg),
+            Some("CGB") => Some(EmulationTarget::CgbCgbMode),
+            Some("GBA") => Some(EmulationTarget::GbaCgbMode),
+            Some("SGB") => Some(EmulationTarget::Sgb),
+            _ => None,
+        };
+        let dmg_palette = match env.get_variable(PALETTE_KEY) {
+            Some("Grey") | None => None,
+            Some(name) => dmg_palette_by_name(name).map(|p| p.colours),
+        };
+
+        Options { model, dmg_palette }
+    }
+}
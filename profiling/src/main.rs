@@ -5,6 +5,7 @@ use gbrs_core::{
     config::Config,
     constants::{SOUND_BUFFER_SIZE, SOUND_SAMPLE_RATE},
     cpu::Cpu,
+    host::default_host,
     memory::rom::Rom,
 };
 
@@ -16,6 +17,10 @@ fn main() {
         rom: Rom::from_file(&rom_path),
         sound_buffer_size: SOUND_BUFFER_SIZE,
         sound_sample_rate: SOUND_SAMPLE_RATE,
+        host: default_host(),
+        model: None,
+        boot_rom: None,
+        patch: None,
     });
 
     // Just run the CPU forever so we can profile hot areas of emulation.
@@ -0,0 +1,69 @@
+use gbrs_core::cpu::Cpu;
+
+use sdl2::audio::AudioQueue;
+
+// Dynamic rate control. The video is timed by the display (or the system
+// clock), and the audio by the sound card, and the two never quite agree.
+// Rather than waiting on the audio, the APU's sample rate is nudged up when
+// the queue is running low and down when it's filling up, which is too
+// small a change in pitch to hear.
+
+// At most half a percent either way
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+// How much audio to aim to have queued, in APU buffers. More is safer, less
+// is lower latency.
+const TARGET_QUEUED_BUFFERS: usize = 4;
+// Past this many times the target (like after the window was dragged), the
+// queue is thrown away rather than playing catch-up for seconds
+const MAX_QUEUED_TARGETS: usize = 4;
+
+pub struct AudioSync {
+    // The rate that would play at exactly the right speed if the clocks
+    // agreed
+    base_rate: f64,
+    // In samples (counting left and right separately)
+    target_queued: usize,
+}
+
+impl AudioSync {
+    fn queued_samples(&self, audio_queue: &AudioQueue<i16>) -> usize {
+        audio_queue.size() as usize / core::mem::size_of::<i16>()
+    }
+
+    // Call before each frame
+    pub fn update_rate(
+        &mut self,
+        gameboy: &mut Cpu,
+        audio_queue: &AudioQueue<i16>,
+    ) {
+        let mut queued = self.queued_samples(audio_queue);
+        if queued > self.target_queued * MAX_QUEUED_TARGETS {
+            audio_queue.clear();
+            queued = 0;
+        }
+
+        let fill = (self.target_queued as f64 - queued as f64)
+            / self.target_queued as f64;
+        let adjustment = 1.
+            + (fill * MAX_RATE_ADJUSTMENT)
+                .clamp(-MAX_RATE_ADJUSTMENT, MAX_RATE_ADJUSTMENT);
+        gameboy
+            .mem
+            .apu
+            .set_sample_rate((self.base_rate * adjustment).round() as usize);
+    }
+
+    // `device_rate` is what the sound card plays at. When frames are timed
+    // by the display, which is a little faster or slower than a Gameboy,
+    // `speed` is how much faster, so the audio can keep up.
+    pub fn new(
+        device_rate: usize,
+        speed: f64,
+        buffer_size: usize,
+    ) -> AudioSync {
+        AudioSync {
+            base_rate: device_rate as f64 / speed,
+            target_queued: buffer_size * TARGET_QUEUED_BUFFERS,
+        }
+    }
+}
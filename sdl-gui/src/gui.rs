@@ -1,132 +1,322 @@
-use gbrs_core::constants::*;
+use gbrs_common::hotkeys::{soft_reset, toggle_cheats, Hotkey, Hotkeys};
+use gbrs_common::input::{Device, Input};
+use gbrs_common::recording::{skip_audio, toggle_recording};
+use gbrs_common::rom_file::{swap_rom, RomPrompt};
+use gbrs_common::screenshot::take_screenshot;
+use gbrs_common::settings::Settings;
+use gbrs_common::video::screen_rect;
+use gbrs_core::config::Config;
+use gbrs_core::constants::FRAME_RATE;
 use gbrs_core::cpu::Cpu;
+use gbrs_core::recorder::Recorder;
+
+use crate::audio::AudioSync;
+
+use std::time::{Duration, Instant};
 
 use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::controller::GameController;
 use sdl2::event::Event;
-use sdl2::keyboard::Scancode;
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
 
-// NOTE: The SDL port does not currently perform non-integer scaling.
-//   Please choose a multiple of 160x144
-const WINDOW_WIDTH: u32 = 800;
-const WINDOW_HEIGHT: u32 = 720;
+// If the display refreshes this close to the Gameboy's frame rate, a frame
+// is run for each refresh and the audio is sped up or slowed down to match.
+// Otherwise frames are timed by the system clock.
+const VSYNC_TOLERANCE: f64 = 0.02;
+// When we've fallen behind (eg. the window was being dragged), give up on
+// catching up after this many frames
+const MAX_FRAMES_PER_LOOP: usize = 4;
+
+fn window_title(gameboy: &Cpu) -> String {
+    format!("{} - gbrs (SDL)", gameboy.cart_info.title)
+}
 
-pub fn run_gui(mut gameboy: Cpu) {
+// Returns the CPU once the window is closed. `config` is what the CPU was
+// made from, for resetting it. Dropping a ROM onto the window swaps it in.
+pub fn run_gui(
+    mut gameboy: Cpu,
+    mut config: Config,
+    settings: &Settings,
+) -> Cpu {
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
-    let window_title = format!("{} - gbrs (SDL)", gameboy.cart_info.title);
-    let window = video_subsystem
-        .window(&window_title[..], WINDOW_WIDTH, WINDOW_HEIGHT)
-        .position_centered()
-        .build()
-        .unwrap();
+    // The Super Gameboy's border makes the picture bigger
+    let mut screen_size = (
+        gameboy.screen_width() as u32,
+        gameboy.screen_height() as u32,
+    );
+
+    let mut window = video_subsystem.window(
+        &window_title(&gameboy),
+        screen_size.0 * settings.scale,
+        screen_size.1 * settings.scale,
+    );
+    window.position_centered().resizable();
+    if settings.fullscreen {
+        window.fullscreen_desktop();
+    }
+    let window = window.build().unwrap();
+    let refresh_rate = window
+        .display_mode()
+        .map(|mode| mode.refresh_rate as f64)
+        .unwrap_or(0.);
 
-    let square_width = WINDOW_WIDTH as usize / SCREEN_WIDTH;
-    let square_height = WINDOW_HEIGHT as usize / SCREEN_HEIGHT;
+    let mut canvas = window.into_canvas();
+    if settings.vsync {
+        canvas = canvas.present_vsync();
+    }
+    let mut canvas = canvas.build().unwrap();
 
-    let mut canvas = window
-        .into_canvas()
-        // TODO: This option fixes visual tearing, but it messes up our sound
-        //   timing code, and the speed of the emulator is thrown way off :(
-        // .present_vsync()
-        .build()
+    // The frame is copied straight into this, and the GPU scales it
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGBA32,
+            screen_size.0,
+            screen_size.1,
+        )
         .unwrap();
 
-    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
     canvas.clear();
     canvas.present();
     let mut event_pump = sdl_context.event_pump().unwrap();
 
     let audio_subsystem = sdl_context.audio().unwrap();
     let desired_spec = AudioSpecDesired {
-        freq: Some(SOUND_SAMPLE_RATE as i32),
+        freq: Some(gameboy.mem.apu.sample_rate as i32),
         channels: Some(2),
-        samples: Some(SOUND_BUFFER_SIZE as u16),
+        samples: Some(gameboy.mem.apu.buffer_size as u16),
     };
 
     let audio_queue: AudioQueue<i16> =
         audio_subsystem.open_queue(None, &desired_spec).unwrap();
+    audio_queue.resume();
+    let mut sound_buffer = vec![];
 
-    assert_eq!(
-        audio_queue.spec().samples,
-        SOUND_BUFFER_SIZE as u16,
-        "Audio device does not support gbrs' sound buffer size"
+    let vsync_speed = refresh_rate / FRAME_RATE;
+    let frames_follow_vsync =
+        settings.vsync && (vsync_speed - 1.).abs() < VSYNC_TOLERANCE;
+    // SDL may not give us exactly the sample rate we asked for, so the APU
+    // resamples to whatever the device actually runs at
+    let mut audio_sync = AudioSync::new(
+        audio_queue.spec().freq as usize,
+        match frames_follow_vsync {
+            true => vsync_speed,
+            false => 1.,
+        },
+        gameboy.mem.apu.buffer_size,
     );
 
-    gameboy.step_until_full_audio_buffer();
-    // gameboy.mem.apu.buffer_full = true;
+    // Controllers SDL knows about are opened as they're plugged in (and at
+    // startup), and have to be kept open to get their events
+    let controller_subsystem = sdl_context.game_controller().unwrap();
+    if let Some(path) = settings.controller_db_path() {
+        match controller_subsystem.load_mappings(&path) {
+            Ok(count) => println!("Loaded {} controller mappings", count),
+            Err(err) => println!(
+                "Failed to load controller mappings from {}: {}",
+                path.display(),
+                err
+            ),
+        }
+    }
+    let mut controllers: Vec<GameController> = vec![];
+
+    let mut hotkeys = Hotkeys::new(settings);
+    let mut input = Input::new(settings);
+    // Toggled with the record hotkey
+    let mut recorder: Option<Recorder> = None;
+    let mut rom_prompt = RomPrompt::new();
+
+    // Frames are owed at speed_multiplier per display refresh, or per
+    // 1/FRAME_RATE seconds
+    let mut frames_owed = 0.;
+    let mut last_loop = Instant::now();
+    // Only one frame's sound in every speed_multiplier is played
+    let mut audio_owed = 0.;
 
     'running: loop {
+        // Dropped onto the window
+        let mut dropped_rom = None;
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit { .. } => break 'running,
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    repeat,
+                    ..
+                } => {
+                    input.key_down(scancode.name());
+                    if repeat {
+                        continue;
+                    }
+                    match hotkeys.key_down(scancode.name()) {
+                        Some(Hotkey::Reset) => {
+                            soft_reset(&mut gameboy, &config)
+                        },
+                        Some(Hotkey::Screenshot) => take_screenshot(&gameboy),
+                        Some(Hotkey::Record) => {
+                            toggle_recording(&mut recorder, &gameboy)
+                        },
+                        Some(Hotkey::Cheats) => toggle_cheats(&mut gameboy),
+                        Some(Hotkey::LoadRom) => rom_prompt.ask(),
+                        _ => {},
+                    }
+                },
+                Event::KeyUp {
+                    scancode: Some(scancode),
+                    ..
+                } => {
+                    input.key_up(scancode.name());
+                    hotkeys.key_up(scancode.name());
+                },
+                Event::DropFile { filename, .. } => {
+                    dropped_rom = Some(filename)
+                },
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match controller_subsystem.open(which) {
+                        Ok(controller) => {
+                            println!("Connected {}", controller.name());
+                            controllers.push(controller);
+                        },
+                        Err(err) => {
+                            println!("Failed to open controller: {}", err)
+                        },
+                    }
+                },
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    controllers
+                        .retain(|controller| controller.instance_id() != which);
+                    input.device_removed(Device::Gamepad(which));
+                },
+                Event::ControllerButtonDown { which, button, .. } => {
+                    input.button(Device::Gamepad(which), &button.string(), true)
+                },
+                Event::ControllerButtonUp { which, button, .. } => input
+                    .button(Device::Gamepad(which), &button.string(), false),
+                Event::ControllerAxisMotion {
+                    which, axis, value, ..
+                } => input.axis(
+                    Device::Gamepad(which),
+                    &axis.string(),
+                    value as f32 / i16::MAX as f32,
+                ),
                 _ => {},
             }
         }
+        if let Some(path) = dropped_rom.or_else(|| rom_prompt.take_path()) {
+            // The video would change size partway through
+            if recorder.is_some() {
+                toggle_recording(&mut recorder, &gameboy);
+            }
+            swap_rom(&mut gameboy, &mut config, &path);
+            canvas
+                .window_mut()
+                .set_title(&window_title(&gameboy))
+                .unwrap();
+        }
+        gameboy.speed_multiplier = hotkeys.speed_multiplier();
+        input.update_joypad(&mut gameboy);
 
-        // Draw the screen
-        for x in 0..SCREEN_WIDTH {
-            for y in 0..SCREEN_HEIGHT {
-                let i = (y * 160 + x) as usize;
-                let colour = &gameboy.gpu.finished_frame[i];
-                canvas.set_draw_color(Color::RGB(
-                    colour.red,
-                    colour.green,
-                    colour.blue,
-                ));
-                canvas
-                    .fill_rect(Rect::new(
-                        (x * square_width) as i32,
-                        (y * square_height) as i32,
-                        square_width as u32,
-                        square_height as u32,
-                    ))
-                    .unwrap();
+        let now = Instant::now();
+        frames_owed += match frames_follow_vsync {
+            true => gameboy.speed_multiplier,
+            false => (now - last_loop).as_secs_f64() * gameboy.frame_rate(),
+        };
+        last_loop = now;
+        if hotkeys.paused {
+            frames_owed = 0.;
+        }
+        frames_owed = frames_owed
+            .min(MAX_FRAMES_PER_LOOP as f64 * gameboy.speed_multiplier.max(1.));
+
+        // Paused, this is true once for each frame advance
+        while hotkeys.should_run() {
+            let advancing = hotkeys.paused;
+            if !advancing {
+                if frames_owed < 1. {
+                    break;
+                }
+                frames_owed -= 1.;
+            }
+
+            audio_sync.update_rate(&mut gameboy, &audio_queue);
+            let cycles = gameboy.step_one_frame();
+
+            // A single frame advance of sound would just be a click
+            if !advancing {
+                audio_owed += 1. / gameboy.speed_multiplier.max(1.);
+            }
+            if audio_owed >= 1. {
+                audio_owed -= 1.;
+                sound_buffer.resize(gameboy.mem.apu.samples_available(), 0);
+                let sample_count =
+                    gameboy.mem.apu.read_samples(&mut sound_buffer);
+                let samples = &mut sound_buffer[..sample_count];
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder
+                        .record_audio(samples)
+                        .expect("Failed to record audio");
+                }
+                hotkeys.apply_volume(samples);
+                audio_queue.queue_audio(samples).unwrap();
+            } else {
+                skip_audio(&mut recorder, &mut gameboy);
+            }
+
+            if let Some(recorder) = recorder.as_mut() {
+                recorder
+                    .record_frame(&gameboy, cycles)
+                    .expect("Failed to record video");
             }
+            if advancing {
+                break;
+            }
+        }
+
+        // A reset or a new game can change the size of the screen
+        let new_screen_size = (
+            gameboy.screen_width() as u32,
+            gameboy.screen_height() as u32,
+        );
+        if new_screen_size != screen_size {
+            screen_size = new_screen_size;
+            texture = texture_creator
+                .create_texture_streaming(
+                    PixelFormatEnum::RGBA32,
+                    screen_size.0,
+                    screen_size.1,
+                )
+                .unwrap();
         }
+
+        texture
+            .update(None, &gameboy.get_rgba_frame(), screen_size.0 as usize * 4)
+            .unwrap();
+        let window_size = canvas.output_size().unwrap();
+        let (x, y, width, height) =
+            screen_rect(settings.scale_mode, window_size, screen_size);
+        canvas.clear();
+        canvas
+            .copy(&texture, None, Some(Rect::new(x, y, width, height)))
+            .unwrap();
+        // Waits for the display when vsync is on
         canvas.present();
 
-        gameboy.mem.joypad.start_pressed = event_pump
-            .keyboard_state()
-            .is_scancode_pressed(Scancode::Return);
-        gameboy.mem.joypad.select_pressed = event_pump
-            .keyboard_state()
-            .is_scancode_pressed(Scancode::Backspace);
-        gameboy.mem.joypad.a_pressed =
-            event_pump.keyboard_state().is_scancode_pressed(Scancode::X);
-        gameboy.mem.joypad.b_pressed =
-            event_pump.keyboard_state().is_scancode_pressed(Scancode::Z);
-        gameboy.mem.joypad.left_pressed = event_pump
-            .keyboard_state()
-            .is_scancode_pressed(Scancode::Left);
-        gameboy.mem.joypad.right_pressed = event_pump
-            .keyboard_state()
-            .is_scancode_pressed(Scancode::Right);
-        gameboy.mem.joypad.up_pressed = event_pump
-            .keyboard_state()
-            .is_scancode_pressed(Scancode::Up);
-        gameboy.mem.joypad.down_pressed = event_pump
-            .keyboard_state()
-            .is_scancode_pressed(Scancode::Down);
-
-        gameboy.step_until_full_audio_buffer();
-
-        let pre = audio_queue.size();
-        audio_queue.queue_audio(&gameboy.mem.apu.buffer).unwrap();
-        audio_queue.resume();
-        let diff = audio_queue.size() - pre;
-
-        while audio_queue.size() > diff {
-            // NOTE: You can comment this if statement out if you're having
-            //   speed or sound issues. It is an attempt to help out slower
-            //   machines, but you may not need it if your machine is fast
-            //   enough.
-            if !gameboy.mem.apu.buffer_full {
-                gameboy.step();
-            }
-            std::hint::spin_loop();
+        // Otherwise sleep until the next frame is due
+        if !frames_follow_vsync && frames_owed < 1. {
+            let until_next_frame =
+                (1. - frames_owed) / gameboy.frame_rate().max(1.);
+            std::thread::sleep(Duration::from_secs_f64(until_next_frame));
         }
     }
+
+    if let Some(recorder) = recorder {
+        recorder.finish().expect("Failed to finish recording");
+    }
+
+    gameboy
 }
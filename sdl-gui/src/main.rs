@@ -1,10 +1,9 @@
+pub mod audio;
 pub mod gui;
 
-use std::env;
-
-use gbrs_core::config::Config;
-use gbrs_core::cpu::Cpu;
-use gbrs_core::memory::rom::Rom;
+use gbrs_common::cli::Args;
+use gbrs_common::headless::run_headless;
+use gbrs_common::settings::Settings;
 use gui::run_gui;
 
 // TODO: Get these from an SDL audio device
@@ -12,11 +11,18 @@ const SOUND_BUFFER_SIZE: usize = 1024;
 const SOUND_SAMPLE_RATE: usize = 48000;
 
 fn main() {
-    let rom_path = env::args().nth(1).expect("Pass a ROM path as an argument");
-    let processor = Cpu::from_config(Config {
-        sound_buffer_size: SOUND_BUFFER_SIZE,
-        sound_sample_rate: SOUND_SAMPLE_RATE,
-        rom: Rom::from_file(&rom_path),
-    });
-    run_gui(processor);
+    let args = Args::from_env();
+    let mut settings = Settings::load();
+    args.apply_to_settings(&mut settings);
+
+    let config = args.config(SOUND_BUFFER_SIZE, SOUND_SAMPLE_RATE);
+    let mut processor = args.gameboy(&config);
+    args.start(&mut processor);
+
+    let mut processor = match args.headless {
+        true => run_headless(processor, &args),
+        false => run_gui(processor, config, &settings),
+    };
+
+    args.finish(&mut processor);
 }
@@ -1,7 +1,15 @@
 use crate::control::*;
 
-use gbrs_core::constants::*;
+use gbrs_common::hotkeys::{soft_reset, toggle_cheats, Hotkey, Hotkeys};
+use gbrs_common::input::Input;
+use gbrs_common::recording::{skip_audio, toggle_recording};
+use gbrs_common::rom_file::{swap_rom, RomPrompt};
+use gbrs_common::screenshot::take_screenshot;
+use gbrs_common::settings::Settings;
+use gbrs_core::config::Config;
+use gbrs_core::constants::FRAME_RATE;
 use gbrs_core::cpu::Cpu;
+use gbrs_core::recorder::Recorder;
 
 use sfml::audio::{Sound, SoundBuffer, SoundStatus};
 use sfml::graphics::*;
@@ -9,36 +17,60 @@ use sfml::system::*;
 use sfml::window::*;
 use spin::mutex::SpinMutex;
 
-pub const STEP_BY_STEP: bool = false;
 // NOTE: This debug option is only supported on macOS. See note below
 pub const DRAW_FPS: bool = false;
 
-static SOUND_BACKING_STORE: SpinMutex<[i16; SOUND_BUFFER_SIZE]> =
-    SpinMutex::new([0; SOUND_BUFFER_SIZE]);
+static SOUND_BACKING_STORE: SpinMutex<Vec<i16>> = SpinMutex::new(Vec::new());
 
-pub fn run_gui(mut gameboy: Cpu) {
-    let sw = SCREEN_WIDTH as u32;
-    let sh = SCREEN_HEIGHT as u32;
-    let window_width: u32 = 640;
-    let window_height: u32 = 512;
+// SFML doesn't have names for its keys, so hotkeys use the enum's
+fn key_name(key: Key) -> String {
+    format!("{:?}", key)
+}
+
+fn window_title(gameboy: &Cpu) -> String {
+    format!("{} - gbrs (SFML)", gameboy.cart_info.title)
+}
 
-    let style = Style::RESIZE | Style::TITLEBAR | Style::CLOSE;
+// Returns the CPU once the window is closed. `config` is what the CPU was
+// made from, for resetting it. SFML can't tell us about files dropped onto
+// the window, so the load ROM hotkey asks for one in the terminal instead.
+pub fn run_gui(
+    mut gameboy: Cpu,
+    mut config: Config,
+    settings: &Settings,
+) -> Cpu {
+    // The Super Gameboy's border makes the picture bigger
+    let mut sw = gameboy.screen_width() as u32;
+    let mut sh = gameboy.screen_height() as u32;
+    let (window_width, window_height, style) = match settings.fullscreen {
+        true => {
+            let mode = VideoMode::desktop_mode();
+            (mode.width, mode.height, Style::FULLSCREEN)
+        },
+        false => (
+            sw * settings.scale,
+            sh * settings.scale,
+            Style::RESIZE | Style::TITLEBAR | Style::CLOSE,
+        ),
+    };
     let mut window = RenderWindow::new(
         (window_width, window_height),
-        &format!("{} - gbrs (SFML)", gameboy.cart_info.title)[..],
+        &window_title(&gameboy)[..],
         style,
         &Default::default(),
     )
     .unwrap();
-    // window.set_framerate_limit(gameboy.frame_rate as u32);
+    // window.set_framerate_limit(gameboy.frame_rate() as u32);
+    // Hotkeys should only fire once per press
+    window.set_key_repeat_enabled(false);
 
     let mut screen_texture = Texture::new().unwrap();
     screen_texture
         .create(sw, sh)
         .expect("Failed to create screen texture");
 
-    // Scale the 160x144 image to the appropriate resolution
-    let sprite_scale = Vector2f::new(
+    // Scale the 160x144 (or 256x224) image to the appropriate resolution
+    let mut sprite_scale = Vector2f::new(
         window_width as f32 / sw as f32,
         window_height as f32 / sh as f32,
     );
@@ -58,7 +90,13 @@ pub fn run_gui(mut gameboy: Cpu) {
     }
 
     // Get the initial frame & buffer of audio
-    gameboy.step_until_full_audio_buffer();
+    let mut cycles = gameboy.step_until_full_audio_buffer();
+
+    let mut hotkeys = Hotkeys::new(settings);
+    let mut input = Input::new(settings);
+    // Toggled with the record hotkey
+    let mut recorder: Option<Recorder> = None;
+    let mut rom_prompt = RomPrompt::new();
 
     loop {
         let secs = clock.restart().as_seconds();
@@ -67,18 +105,68 @@ pub fn run_gui(mut gameboy: Cpu) {
             match ev {
                 Event::Closed => {
                     window.close();
-                    return;
+                    if let Some(recorder) = recorder {
+                        recorder.finish().expect("Failed to finish recording");
+                    }
+                    return gameboy;
+                },
+                Event::KeyPressed { code, .. } => {
+                    input.key_down(&key_name(code));
+                    match hotkeys.key_down(&key_name(code)) {
+                        Some(Hotkey::Reset) => {
+                            soft_reset(&mut gameboy, &config);
+                        },
+                        Some(Hotkey::Screenshot) => take_screenshot(&gameboy),
+                        Some(Hotkey::Record) => {
+                            toggle_recording(&mut recorder, &gameboy)
+                        },
+                        Some(Hotkey::Cheats) => toggle_cheats(&mut gameboy),
+                        Some(Hotkey::LoadRom) => rom_prompt.ask(),
+                        _ => {},
+                    }
+                },
+                Event::KeyReleased { code, .. } => {
+                    input.key_up(&key_name(code));
+                    hotkeys.key_up(&key_name(code));
                 },
                 _ => {},
             }
         }
+        if let Some(path) = rom_prompt.take_path() {
+            // The video would change size partway through
+            if recorder.is_some() {
+                toggle_recording(&mut recorder, &gameboy);
+            }
+            swap_rom(&mut gameboy, &mut config, &path);
+            window.set_title(&window_title(&gameboy)[..]);
+            cycles = 0;
+        }
+        gameboy.speed_multiplier = hotkeys.speed_multiplier();
 
-        update_joypad_state(&mut gameboy);
+        poll_joysticks(&mut input);
+        input.update_joypad(&mut gameboy);
         // gameboy.step_until_full_audio_buffer();
 
+        // Swapping between an SGB game and any other changes the size
+        let (new_sw, new_sh) = (
+            gameboy.screen_width() as u32,
+            gameboy.screen_height() as u32,
+        );
+        if (new_sw, new_sh) != (sw, sh) {
+            (sw, sh) = (new_sw, new_sh);
+            screen_texture
+                .create(sw, sh)
+                .expect("Failed to create screen texture");
+            let window_size = window.size();
+            sprite_scale = Vector2f::new(
+                window_size.x as f32 / sw as f32,
+                window_size.y as f32 / sh as f32,
+            );
+        }
+
         // Draw the previous frame
         screen_texture.update_from_pixels(
-            &gameboy.gpu.get_rgba_frame(),
+            &gameboy.get_rgba_frame(),
             sw,
             sh,
             0,
@@ -97,6 +185,33 @@ pub fn run_gui(mut gameboy: Cpu) {
         }
         window.display();
 
+        if !hotkeys.should_run() {
+            // Paused. Keep the window responsive without spinning.
+            sleep(Time::seconds((1. / FRAME_RATE) as f32));
+            continue;
+        }
+        if hotkeys.paused {
+            // Frame advance. A single frame of sound would just be a click.
+            cycles = gameboy.step_one_frame();
+            skip_audio(&mut recorder, &mut gameboy);
+            if let Some(recorder) = recorder.as_mut() {
+                recorder
+                    .record_frame(&gameboy, cycles)
+                    .expect("Failed to record video");
+            }
+            cycles = 0;
+            continue;
+        }
+
+        // Resets and frame advances leave the sound buffer empty
+        cycles += gameboy.step_until_full_audio_buffer();
+        // Fast-forward by running several buffers' worth in the time it
+        // takes to play one
+        for _ in 1..gameboy.speed_multiplier.round() as usize {
+            skip_audio(&mut recorder, &mut gameboy);
+            cycles += gameboy.step_until_full_audio_buffer();
+        }
+
         // Play the audio while creating the next frame and sound buffer
         // This way we're not idling, we're actively computing the next event.
         // let sound_buffer = SoundBuffer::from_samples(&gameboy.mem.apu.buffer, 2, SOUND_SAMPLE_RATE as u32).unwrap();
@@ -104,20 +219,27 @@ pub fn run_gui(mut gameboy: Cpu) {
         // sound.play();
 
         let mut sound_backing_store = SOUND_BACKING_STORE.lock();
-        *sound_backing_store = gameboy.mem.apu.buffer;
+        sound_backing_store.resize(gameboy.mem.apu.buffer_size, 0);
+        let sample_count =
+            gameboy.mem.apu.read_samples(&mut sound_backing_store);
+        if let Some(recorder) = recorder.as_mut() {
+            recorder
+                .record_audio(&sound_backing_store[..sample_count])
+                .expect("Failed to record audio");
+        }
         let sound_buffer = SoundBuffer::from_samples(
-            &*sound_backing_store,
+            &sound_backing_store[..sample_count],
             2,
-            SOUND_SAMPLE_RATE as u32,
+            gameboy.mem.apu.sample_rate as u32,
         )
         .unwrap();
         let mut sound = Sound::with_buffer(&sound_buffer);
 
-        // sound.set_volume(0.);
+        sound.set_volume(hotkeys.volume() * 100.);
         sound.play();
         while sound.status() == SoundStatus::PLAYING {
-            if !gameboy.mem.apu.buffer_full {
-                gameboy.step();
+            if !gameboy.mem.apu.is_buffer_full() {
+                cycles += gameboy.step();
             } else {
                 // We're finished with this frame. Let's just wait for audio
                 // to sync up.
@@ -128,9 +250,13 @@ pub fn run_gui(mut gameboy: Cpu) {
         // Just in-case we're running too slow, let's catch up.
         // This may be when you get a small audio pop. It happens more often
         // on slower machines.
-        while !gameboy.mem.apu.buffer_full {
-            gameboy.step();
+        cycles += gameboy.step_until_full_audio_buffer();
+
+        if let Some(recorder) = recorder.as_mut() {
+            recorder
+                .record_frame(&gameboy, cycles)
+                .expect("Failed to record video");
         }
-        gameboy.mem.apu.buffer_full = false;
+        cycles = 0;
     }
 }